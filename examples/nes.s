@@ -0,0 +1,24 @@
+; NROM-128 ROM with one PRG bank and one CHR bank
+.ines mapper 0
+.ines mirroring vertical
+.ines prg 1
+.ines chr 1
+
+.bank 0, $C000
+reset:
+	sei
+	cld
+loop:
+	jmp loop
+
+nmi:
+irq:
+	rti
+
+.origin $FFFA
+.word nmi
+.word reset
+.word irq
+
+.chr_bank 0
+.incbin "examples/tiles.chr"
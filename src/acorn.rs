@@ -0,0 +1,104 @@
+//
+// src
+// acorn.rs: Builds BBC Micro single sided DFS disc images (SSD).
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+// The number of sectors on an 80 track single sided disc
+const DISC_SECTORS: usize = 800;
+
+// The first sector after the catalogue
+const FIRST_DATA_SECTOR: usize = 2;
+
+// The maximum number of files in a catalogue
+const MAX_FILES: usize = 31;
+
+// Represents a file on a DFS disc
+#[derive(Debug)]
+pub struct DfsFile<'a> {
+	pub name: String,
+	pub load: u16,
+	pub exec: u16,
+	pub bytes: &'a [u8],
+}
+
+// Builds files named after the output, one per segment; the first segment is executed at entry
+pub fn segment_files<'a>(name: &str, segments: &[(u16, &'a [u8])], entry: Option<u16>) -> Vec<DfsFile<'a>> {
+	let name: String = name.to_uppercase().chars().filter(|c| c.is_ascii_graphic() && *c != '.').collect();
+	segments.iter().enumerate().map(|(i, (addr, bytes))| DfsFile {
+		name: if segments.len() == 1 {
+			name.chars().take(7).collect()
+		} else {
+			format!("{}{}", name.chars().take(7 - (i + 1).to_string().len()).collect::<String>(), i + 1)
+		},
+		load: *addr,
+		exec: if i == 0 { entry.unwrap_or(*addr) } else { *addr },
+		bytes,
+	}).collect()
+}
+
+// Builds an SSD image holding the files in directory $ (the image is truncated after the last used sector)
+pub fn write_ssd(title: &str, files: &[DfsFile]) -> Result<Vec<u8>, String> {
+	if files.len() > MAX_FILES {
+		return Err(format!("A DFS disc can only hold {} files", MAX_FILES));
+	}
+
+	// Lay out files one after another
+	let mut starts = Vec::new();
+	let mut sector = FIRST_DATA_SECTOR;
+	for file in files {
+		starts.push(sector);
+		sector += file.bytes.len().div_ceil(256);
+	}
+	if sector > DISC_SECTORS {
+		return Err(String::from("Files are too large to fit on a disc"));
+	}
+
+	let mut disc = vec![0u8; sector * 256];
+
+	// Disc title is split over both catalogue sectors
+	let mut padded = [b' '; 12];
+	for (i, c) in title.bytes().take(12).enumerate() {
+		padded[i] = c;
+	}
+	disc[0..8].copy_from_slice(&padded[0..8]);
+	disc[256..260].copy_from_slice(&padded[8..12]);
+
+	// Catalogue header: cycle number, number of files * 8, boot option and sector count
+	disc[256 + 4] = 0;
+	disc[256 + 5] = (files.len() * 8) as u8;
+	disc[256 + 6] = ((DISC_SECTORS >> 8) & 0x03) as u8;
+	disc[256 + 7] = DISC_SECTORS as u8;
+
+	// Catalogue entries are sorted by descending start sector
+	for (slot, i) in (0..files.len()).rev().enumerate() {
+		let file = &files[i];
+		let name_entry = 8 + slot * 8;
+		let info_entry = 256 + 8 + slot * 8;
+		let len = file.bytes.len();
+		let start = starts[i];
+
+		let mut name = [b' '; 7];
+		for (j, c) in file.name.bytes().take(7).enumerate() {
+			name[j] = c;
+		}
+		disc[name_entry..name_entry + 7].copy_from_slice(&name);
+		disc[name_entry + 7] = b'$';
+
+		// Addresses are in the I/O processor (&FFxxxx), so their high bits are set
+		disc[info_entry] = file.load as u8;
+		disc[info_entry + 1] = (file.load >> 8) as u8;
+		disc[info_entry + 2] = file.exec as u8;
+		disc[info_entry + 3] = (file.exec >> 8) as u8;
+		disc[info_entry + 4] = len as u8;
+		disc[info_entry + 5] = (len >> 8) as u8;
+		disc[info_entry + 6] = 0b11_00_11_00 | (((len >> 16) as u8 & 0x03) << 4) | ((start >> 8) as u8 & 0x03);
+		disc[info_entry + 7] = start as u8;
+
+		disc[start * 256..start * 256 + len].copy_from_slice(file.bytes);
+	}
+
+	Ok(disc)
+}
@@ -0,0 +1,76 @@
+//
+// src
+// apple.rs: Builds Apple DOS 3.3 and ProDOS binary files.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+// The ProDOS file type for binary files
+const PRODOS_BIN: u16 = 0x06;
+
+// Joins segments into a single block of bytes starting at the first segment, filling gaps with zeros
+pub fn flatten_segments(segments: &[(u16, &[u8])]) -> (u16, Vec<u8>) {
+	let start = match segments.first() {
+		Some(v) => v.0,
+		None => return (0, Vec::new())
+	};
+
+	let mut bytes = Vec::new();
+	for (addr, data) in segments {
+		bytes.resize((addr - start) as usize, 0);
+		bytes.extend_from_slice(data);
+	}
+	(start, bytes)
+}
+
+// Builds an Apple DOS 3.3 binary file (B), which starts with the load address and the length
+pub fn write_dos33(segments: &[(u16, &[u8])]) -> Result<Vec<u8>, String> {
+	let (start, bytes) = flatten_segments(segments);
+	if bytes.len() > u16::MAX as usize {
+		return Err(format!("{} bytes is too large for the length of a DOS 3.3 binary file", bytes.len()));
+	}
+	let len = bytes.len() as u16;
+
+	let mut out = vec![start as u8, (start >> 8) as u8, len as u8, (len >> 8) as u8];
+	out.extend(bytes);
+	Ok(out)
+}
+
+// Builds an AppleSingle file holding a ProDOS binary file (BIN) whose aux type is the load address
+pub fn write_prodos(name: &str, segments: &[(u16, &[u8])]) -> Vec<u8> {
+	let (start, bytes) = flatten_segments(segments);
+
+	// Entries are (id, contents)
+	let mut file_info = Vec::new();
+	file_info.extend(&0x00C3u16.to_be_bytes());
+	file_info.extend(&PRODOS_BIN.to_be_bytes());
+	file_info.extend(&(start as u32).to_be_bytes());
+	let entries: Vec<(u32, Vec<u8>)> = vec![
+		(3, name.to_uppercase().into_bytes()),
+		(11, file_info),
+		(1, bytes),
+	];
+
+	// Header
+	let mut out = Vec::new();
+	out.extend(&0x0005_1600u32.to_be_bytes());
+	out.extend(&0x0002_0000u32.to_be_bytes());
+	out.extend(&[0u8; 16]);
+	out.extend(&(entries.len() as u16).to_be_bytes());
+
+	// Entry descriptors
+	let mut offset = out.len() + entries.len() * 12;
+	for (id, contents) in entries.iter() {
+		out.extend(&id.to_be_bytes());
+		out.extend(&(offset as u32).to_be_bytes());
+		out.extend(&(contents.len() as u32).to_be_bytes());
+		offset += contents.len();
+	}
+
+	// Entry contents
+	for (_, contents) in entries {
+		out.extend(contents);
+	}
+	out
+}
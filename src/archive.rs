@@ -0,0 +1,79 @@
+//
+// src
+// archive.rs: Reads and writes static libraries of object files.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+// An archive is a list of object files with their names, using the same encoding as object files:
+//
+// "A65A"                            magic
+// u8 version
+// u32 count, (str name, u32 length, bytes)*
+//
+
+use crate::object;
+use crate::object::Reader;
+use crate::pass_1::FirstPassResult;
+
+// The magic number at the start of every archive
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"A65A";
+
+// The version of the archive format
+pub const ARCHIVE_VERSION: u8 = 1;
+
+// An object file stored in an archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveMember {
+	pub name: String,
+	pub bytes: Vec<u8>
+}
+
+// Checks whether some bytes are an archive
+pub fn is_archive(bytes: &[u8]) -> bool {
+	bytes.starts_with(ARCHIVE_MAGIC)
+}
+
+// Serialises object files into an archive
+pub fn write_archive(members: &[ArchiveMember]) -> Vec<u8> {
+	let mut out = Vec::new();
+	out.extend(ARCHIVE_MAGIC);
+	out.push(ARCHIVE_VERSION);
+	object::put_u32(&mut out, members.len() as u32);
+	for member in members {
+		object::put_str(&mut out, &member.name);
+		object::put_u32(&mut out, member.bytes.len() as u32);
+		out.extend(&member.bytes);
+	}
+	out
+}
+
+// Reads the members of an archive
+pub fn read_archive(filename: &str, bytes: &[u8]) -> Result<Vec<ArchiveMember>, String> {
+	if !is_archive(bytes) {
+		return Err(format!("{} is not an archive", filename));
+	}
+	let mut r = Reader::new(filename, bytes, ARCHIVE_MAGIC.len());
+	let version = r.u8()?;
+	if version != ARCHIVE_VERSION {
+		return Err(format!("{} has unsupported archive version {}", filename, version));
+	}
+
+	let mut members = Vec::new();
+	for _ in 0..r.u32()? {
+		let name = r.str()?;
+		let len = r.u32()? as usize;
+		members.push(ArchiveMember {
+			name,
+			bytes: r.take(len)?.to_vec()
+		});
+	}
+	Ok(members)
+}
+
+// Reads every member of an archive as an object file
+pub fn read_objects(filename: &str, bytes: &[u8]) -> Result<Vec<FirstPassResult>, String> {
+	read_archive(filename, bytes)?.iter()
+		.map(|v| object::read_object(&format!("{}({})", filename, v.name), &v.bytes))
+		.collect()
+}
@@ -0,0 +1,374 @@
+//
+// src
+// assembler.rs: Assembles sources in one call, for programs that embed the assembler.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use std::fmt;
+use std::io;
+
+use crate::archive;
+use crate::files::{
+	FileProvider,
+	FileSystem
+};
+use crate::ines;
+use crate::ines::InesHeader;
+use crate::lexer::Lexer;
+use crate::linker;
+use crate::linker::{
+	AreaUsage,
+	LinkerConfig
+};
+use crate::o65;
+use crate::output;
+use crate::output::{
+	OutputFormat,
+	OutputOptions
+};
+use crate::parser::ParseError;
+use crate::pass_1;
+use crate::pass_1::{
+	Bank,
+	Diagnostic,
+	FirstPassOptions,
+	FirstPassResult
+};
+use crate::pass_2;
+use crate::pass_2::{
+	AssemblerResult,
+	Segment
+};
+use crate::symbols;
+use crate::symbols::ExportedSymbol;
+
+// The processor code is assembled for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Processor {
+	// The original 6502, without undocumented opcodes
+	Nmos6502
+}
+
+// An error from assembling
+#[derive(Debug)]
+pub enum AssemblerError {
+	// A source file couldn't be read
+	Io(String, io::Error),
+
+	// An error in a source file
+	Parse(ParseError),
+
+	// An error placing segments, merging files or writing output
+	Output(String)
+}
+
+impl fmt::Display for AssemblerError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			AssemblerError::Io(path, e) => write!(f, "Could not read {}: {}", path, e),
+			AssemblerError::Parse(e) => write!(f, "{}", e),
+			AssemblerError::Output(message) => write!(f, "{}", message)
+		}
+	}
+}
+
+impl std::error::Error for AssemblerError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			AssemblerError::Io(_, e) => Some(e),
+			AssemblerError::Parse(e) => Some(e),
+			AssemblerError::Output(_) => None
+		}
+	}
+}
+
+impl From<ParseError> for AssemblerError {
+	fn from(e: ParseError) -> Self {
+		AssemblerError::Parse(e)
+	}
+}
+
+impl From<String> for AssemblerError {
+	fn from(message: String) -> Self {
+		AssemblerError::Output(message)
+	}
+}
+
+// A source to assemble
+#[derive(Debug, Clone)]
+enum Source {
+	Path(String),
+
+	// The name it's reported under and its text
+	Text(String, String)
+}
+
+// Assembled code
+#[derive(Debug)]
+pub struct Assembly {
+	// The file in the output format
+	pub bytes: Vec<u8>,
+
+	// Every block of written bytes; for iNES ROMs, the blocks of every bank, which may overlap
+	pub segments: Vec<Segment>,
+
+	// The labels and constants of every source and linked library member, sorted by value then name within each file
+	pub symbols: Vec<ExportedSymbol>,
+
+	// The .warning and .print messages of every source
+	pub diagnostics: Vec<Diagnostic>,
+
+	// How much of each memory area of the linker config is used
+	pub usage: Vec<AreaUsage>
+}
+
+// Files that have been linked, along with the code generated for them
+#[derive(Debug)]
+pub struct LinkedProgram {
+	// Every file, including the library members that were needed
+	pub files: Vec<FirstPassResult>,
+
+	// The code of each file, by bank; files that aren't part of an iNES ROM have one result in Bank::None
+	pub code: Vec<Vec<(Bank, AssemblerResult)>>,
+
+	// How much of each memory area of the linker config is used
+	pub usage: Vec<AreaUsage>,
+
+	// The code of every file merged, with memory areas filled
+	pub result: AssemblerResult,
+
+	// The banks of an iNES ROM, merged across files, and the first header set
+	pub ines_banks: Vec<(Bank, AssemblerResult)>,
+	pub ines_header: Option<InesHeader>,
+
+	format: OutputFormat
+}
+
+impl LinkedProgram {
+	// Every block of written bytes; for iNES ROMs, the blocks of every bank, which may overlap
+	pub fn segments(&self) -> Vec<Segment> {
+		if self.format == OutputFormat::Ines {
+			self.ines_banks.iter().flat_map(|v| v.1.segments.iter().cloned()).collect()
+		} else {
+			self.result.segments.clone()
+		}
+	}
+
+	// Writes the program in its output format
+	pub fn write(&mut self, options: &OutputOptions) -> Result<Vec<u8>, String> {
+		match self.format {
+			OutputFormat::O65 => o65::write_o65(&mut self.files),
+			OutputFormat::Ines => ines::write_ines(&self.ines_header.clone().unwrap_or_default(), &self.ines_banks),
+			format => output::write_output(format, &self.result, options)
+		}
+	}
+}
+
+// Adds the library members that files need, places named segments and generates the code of every file. o65 binaries are placed
+// when they're loaded, so their files are only written.
+pub fn link_program(mut files: Vec<FirstPassResult>, libraries: Vec<Vec<FirstPassResult>>, config: &LinkerConfig, format: OutputFormat) -> Result<LinkedProgram, AssemblerError> {
+	linker::add_library_members(&mut files, libraries);
+	let mut program = LinkedProgram {
+		files: Vec::new(),
+		code: Vec::new(),
+		usage: Vec::new(),
+		result: AssemblerResult::new("total"),
+		ines_banks: Vec::new(),
+		ines_header: None,
+		format
+	};
+	if format == OutputFormat::O65 {
+		program.files = files;
+		return Ok(program);
+	}
+
+	// Place named segments
+	program.usage = linker::link(config, &mut files)?;
+
+	// Generate code
+	for file in files.iter() {
+		let code = if format == OutputFormat::Ines {
+			let banks = pass_2::second_pass_banked(file)?;
+			for (bank, result) in banks.iter() {
+				program.result.debug.extend(result.debug.iter().cloned());
+				match program.ines_banks.iter_mut().find(|v| v.0 == *bank) {
+					Some(v) => v.1.merge(result)?,
+					None => {
+						let mut merged = AssemblerResult::new(&result.filename);
+						merged.merge(result)?;
+						program.ines_banks.push((*bank, merged));
+					}
+				}
+			}
+			if program.ines_header.is_none() {
+				program.ines_header = file.ines.clone();
+			}
+			banks
+		} else {
+			let result = pass_2::second_pass(file)?;
+			program.result.merge(&result)?;
+			vec![(Bank::None, result)]
+		};
+		program.code.push(code);
+	}
+	program.files = files;
+
+	// Fill memory areas
+	linker::fill_areas(config, &mut program.result);
+	Ok(program)
+}
+
+// Assembles sources with a set of options, the same way as the command line
+//
+// let assembly = Assembler::new()
+// 	.source("main.s", ".origin $0801\nlda #COLOUR\nsta $D020\nrts\n")
+// 	.define("COLOUR", 2)
+// 	.format(OutputFormat::Prg)
+// 	.assemble()?;
+pub struct Assembler {
+	sources: Vec<Source>,
+	files: Box<dyn FileProvider>,
+	first_pass: FirstPassOptions,
+	linker_config: LinkerConfig,
+	libraries: Vec<String>,
+	processor: Processor,
+	format: OutputFormat,
+	output: OutputOptions
+}
+
+impl Default for Assembler {
+	fn default() -> Self {
+		Assembler::new()
+	}
+}
+
+// The file provider is left out, since providers don't have to implement Debug
+impl fmt::Debug for Assembler {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Assembler")
+			.field("sources", &self.sources)
+			.field("first_pass", &self.first_pass)
+			.field("linker_config", &self.linker_config)
+			.field("libraries", &self.libraries)
+			.field("processor", &self.processor)
+			.field("format", &self.format)
+			.field("output", &self.output)
+			.finish_non_exhaustive()
+	}
+}
+
+impl Assembler {
+	// Creates an assembler with no sources that reads files from disk and writes prg files
+	pub fn new() -> Assembler {
+		Assembler {
+			sources: Vec::new(),
+			files: Box::new(FileSystem),
+			first_pass: FirstPassOptions::default(),
+			linker_config: LinkerConfig::default(),
+			libraries: Vec::new(),
+			processor: Processor::Nmos6502,
+			format: OutputFormat::Prg,
+			output: OutputOptions::default()
+		}
+	}
+
+	// Sets where source, included and binary files are read from
+	pub fn file_provider<F: FileProvider + 'static>(mut self, files: F) -> Assembler {
+		self.files = Box::new(files);
+		self
+	}
+
+	// Adds a source file, which is read from the file provider when assembling
+	pub fn source_path(mut self, path: &str) -> Assembler {
+		self.sources.push(Source::Path(String::from(path)));
+		self
+	}
+
+	// Adds a source held in memory, reported under the given name
+	pub fn source(mut self, name: &str, text: &str) -> Assembler {
+		self.sources.push(Source::Text(String::from(name), String::from(text)));
+		self
+	}
+
+	// Defines a constant in every source
+	pub fn define(mut self, label: &str, value: u16) -> Assembler {
+		self.first_pass.defines.push((String::from(label), value));
+		self
+	}
+
+	// Adds a directory to search for included files
+	pub fn include_path(mut self, dir: &str) -> Assembler {
+		self.first_pass.include_paths.push(String::from(dir));
+		self
+	}
+
+	// Sets the memory areas named segments are placed in
+	pub fn linker_config(mut self, config: LinkerConfig) -> Assembler {
+		self.linker_config = config;
+		self
+	}
+
+	// Adds an archive of object files, whose members are linked if a source imports a symbol they export
+	pub fn library(mut self, path: &str) -> Assembler {
+		self.libraries.push(String::from(path));
+		self
+	}
+
+	// Sets the processor
+	pub fn processor(mut self, processor: Processor) -> Assembler {
+		self.processor = processor;
+		self
+	}
+
+	// Sets the output format
+	pub fn format(mut self, format: OutputFormat) -> Assembler {
+		self.format = format;
+		self
+	}
+
+	// Sets the entry point, address range and other settings used by some output formats
+	pub fn output_options(mut self, options: OutputOptions) -> Assembler {
+		self.output = options;
+		self
+	}
+
+	// Assembles every source
+	pub fn assemble(&self) -> Result<Assembly, AssemblerError> {
+		// There's only one processor, so nothing depends on it yet
+		match self.processor {
+			Processor::Nmos6502 => ()
+		}
+
+		// First pass
+		let mut first_passes = Vec::new();
+		let mut diagnostics = Vec::new();
+		for source in self.sources.iter() {
+			let (name, text) = match source {
+				Source::Path(path) => (path.clone(), self.files.read_to_string(path).map_err(|e| AssemblerError::Io(path.clone(), e))?),
+				Source::Text(name, text) => (name.clone(), text.clone())
+			};
+			let result = pass_1::first_pass_with_options(&mut Lexer::new(&name, &text), &self.first_pass, self.files.as_ref())?;
+			diagnostics.extend(result.diagnostics.iter().cloned());
+			first_passes.push(result);
+		}
+
+		let mut libraries = Vec::new();
+		for path in self.libraries.iter() {
+			let bytes = self.files.read(path).map_err(|e| AssemblerError::Io(path.clone(), e))?;
+			libraries.push(archive::read_objects(path, &bytes)?);
+		}
+
+		// Link and generate code the same way as the command line
+		let mut program = link_program(first_passes, libraries, &self.linker_config, self.format)?;
+		let symbols = program.files.iter().flat_map(symbols::collect_symbols).collect();
+		Ok(Assembly {
+			bytes: program.write(&self.output)?,
+			segments: program.segments(),
+			symbols,
+			diagnostics,
+			usage: program.usage
+		})
+	}
+}
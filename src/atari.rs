@@ -0,0 +1,41 @@
+//
+// src
+// atari.rs: Builds Atari 8-bit executables (XEX).
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+// The address of the run vector
+const RUNAD: u16 = 0x02E0;
+
+// The address of the init vector
+const INITAD: u16 = 0x02E2;
+
+// Appends a segment to an XEX file
+fn push_segment(out: &mut Vec<u8>, addr: u16, bytes: &[u8]) {
+	let end = addr.wrapping_add(bytes.len() as u16 - 1);
+	out.extend(&[addr as u8, (addr >> 8) as u8, end as u8, (end >> 8) as u8]);
+	out.extend(bytes);
+}
+
+// Builds an XEX file with one segment per written segment, followed by RUNAD and INITAD segments
+pub fn write_xex(segments: &[(u16, &[u8])], run: Option<u16>, init: Option<u16>) -> Vec<u8> {
+	let mut out = vec![0xFF, 0xFF];
+
+	for (addr, bytes) in segments {
+		if !bytes.is_empty() {
+			push_segment(&mut out, *addr, bytes);
+		}
+	}
+
+	if let Some(init) = init {
+		push_segment(&mut out, INITAD, &[init as u8, (init >> 8) as u8]);
+	}
+
+	if let Some(run) = run {
+		push_segment(&mut out, RUNAD, &[run as u8, (run >> 8) as u8]);
+	}
+
+	out
+}
@@ -0,0 +1,149 @@
+//
+// src
+// cbm.rs: Generates Commodore BASIC stubs and .d64 disk images.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use crate::parser::Machine;
+
+// The BASIC token for SYS
+const TOKEN_SYS: u8 = 0x9E;
+
+impl Machine {
+	// Gets the address where BASIC programs are loaded
+	pub fn basic_start(&self) -> u16 {
+		match self {
+			Machine::C64 => 0x0801,
+			Machine::Vic20 => 0x1001,
+			Machine::Vic20Expanded3k => 0x0401,
+			Machine::Vic20Expanded8k => 0x1201,
+		}
+	}
+}
+
+// Generates a tokenised BASIC program (line SYS target) loaded at addr that jumps to the code directly after it
+pub fn basic_stub(addr: u16, line: u16) -> Vec<u8> {
+	// The size of the stub depends on the number of digits in the target address, which depends on the size of the stub
+	let mut digits = 4;
+	let target = loop {
+		let target = addr as u32 + 8 + digits;
+		let len = target.to_string().len() as u32;
+		if len == digits {
+			break target as u16;
+		}
+		digits = len;
+	};
+	let next = target - 2;
+
+	let mut stub = vec![next as u8, (next >> 8) as u8, line as u8, (line >> 8) as u8, TOKEN_SYS];
+	stub.extend(target.to_string().bytes());
+
+	// End of line and end of program
+	stub.extend(&[0, 0, 0]);
+	stub
+}
+
+// The number of sectors in each of the 35 tracks of a disk
+fn sectors_in_track(track: u8) -> u8 {
+	match track {
+		1..=17 => 21,
+		18..=24 => 19,
+		25..=30 => 18,
+		_ => 17
+	}
+}
+
+// Gets the offset of a sector in the disk image
+fn sector_offset(track: u8, sector: u8) -> usize {
+	let before: usize = (1..track).map(|t| sectors_in_track(t) as usize).sum();
+	(before + sector as usize) * 256
+}
+
+// Converts a string to padded uppercase PETSCII
+fn petscii_name(name: &str) -> [u8; 16] {
+	let mut out = [0xA0u8; 16];
+	for (i, c) in name.chars().take(16).enumerate() {
+		let c = c.to_ascii_uppercase();
+		out[i] = if (' '..='Z').contains(&c) { c as u8 } else { b'?' };
+	}
+	out
+}
+
+// The track holding the BAM and directory
+const DIRECTORY_TRACK: u8 = 18;
+
+// Builds a 35 track .d64 disk image containing a single PRG file (including its load address)
+pub fn write_d64(name: &str, prg: &[u8]) -> Result<Vec<u8>, String> {
+	let mut disk = vec![0u8; sector_offset(36, 0)];
+	let mut used: Vec<(u8, u8)> = vec![(DIRECTORY_TRACK, 0), (DIRECTORY_TRACK, 1)];
+
+	// Allocate data sectors in order, skipping the directory track
+	let chunks: Vec<&[u8]> = prg.chunks(254).collect();
+	let mut sectors: Vec<(u8, u8)> = Vec::new();
+	'alloc: for track in (1..=35).filter(|v| *v != DIRECTORY_TRACK) {
+		for sector in 0..sectors_in_track(track) {
+			if sectors.len() == chunks.len() {
+				break 'alloc;
+			}
+			sectors.push((track, sector));
+		}
+	}
+	if sectors.len() < chunks.len() {
+		return Err(format!("{} is too large to fit on a disk", name));
+	}
+
+	// Write data sectors, each linked to the next
+	for (i, chunk) in chunks.iter().enumerate() {
+		let offset = sector_offset(sectors[i].0, sectors[i].1);
+		match sectors.get(i + 1) {
+			Some((track, sector)) => {
+				disk[offset] = *track;
+				disk[offset + 1] = *sector;
+			}
+			None => {
+				disk[offset] = 0;
+				disk[offset + 1] = chunk.len() as u8 + 1;
+			}
+		}
+		disk[offset + 2..offset + 2 + chunk.len()].copy_from_slice(chunk);
+	}
+	used.extend(&sectors);
+
+	// Directory with a single entry
+	let dir = sector_offset(DIRECTORY_TRACK, 1);
+	disk[dir] = 0;
+	disk[dir + 1] = 0xFF;
+	disk[dir + 2] = 0x82;
+	if let Some((track, sector)) = sectors.first() {
+		disk[dir + 3] = *track;
+		disk[dir + 4] = *sector;
+	}
+	disk[dir + 5..dir + 21].copy_from_slice(&petscii_name(name));
+	disk[dir + 30] = chunks.len() as u8;
+	disk[dir + 31] = (chunks.len() >> 8) as u8;
+
+	// Block availability map
+	let bam = sector_offset(DIRECTORY_TRACK, 0);
+	disk[bam] = DIRECTORY_TRACK;
+	disk[bam + 1] = 1;
+	disk[bam + 2] = b'A';
+	for track in 1..=35u8 {
+		let entry = bam + 4 * track as usize;
+		let mut free = 0u8;
+		for sector in 0..sectors_in_track(track) {
+			if !used.contains(&(track, sector)) {
+				disk[entry + 1 + sector as usize / 8] |= 1 << (sector % 8);
+				free += 1;
+			}
+		}
+		disk[entry] = free;
+	}
+
+	// Disk name, id, and DOS type
+	disk[bam + 0x90..bam + 0xA0].copy_from_slice(&petscii_name(name));
+	disk[bam + 0xA0..bam + 0xAB].copy_from_slice(&[0xA0, 0xA0, b'0', b'1', 0xA0, b'2', b'A', 0xA0, 0xA0, 0xA0, 0xA0]);
+
+	Ok(disk)
+}
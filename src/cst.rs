@@ -0,0 +1,159 @@
+//
+// src
+// cst.rs: A lossless syntax tree that keeps whitespace and comments.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use std::fmt;
+
+use crate::lexer::{
+	Lexer,
+	TokenValue
+};
+
+// A token and the exact text it was lexed from
+#[derive(Debug, PartialEq)]
+pub struct CstToken {
+	pub value: TokenValue,
+	pub text: String,
+	pub lino: u32,
+	pub col: u32
+}
+
+impl CstToken {
+	// Returns whether the token is whitespace, a comment, or a newline
+	pub fn is_trivia(&self) -> bool {
+		matches!(self.value, TokenValue::Whitespace(_) | TokenValue::Comment(_) | TokenValue::Newline)
+	}
+}
+
+// What a line contains
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CstLineKind {
+	// Only whitespace and comments
+	Empty,
+
+	// name:, optionally followed by an instruction or pragma
+	Label,
+
+	// name = value
+	Assignment,
+
+	Instruction,
+	Pragma,
+
+	// Anything else, which won't parse
+	Invalid
+}
+
+// A line of source
+#[derive(Debug, PartialEq)]
+pub struct CstLine {
+	pub lino: u32,
+	pub kind: CstLineKind,
+	pub tokens: Vec<CstToken>
+}
+
+impl CstLine {
+	// Returns the tokens that aren't trivia
+	pub fn code(&self) -> Vec<&CstToken> {
+		self.tokens.iter().filter(|v| !v.is_trivia()).collect()
+	}
+
+	// Returns the comment on the line
+	pub fn comment(&self) -> Option<&CstToken> {
+		self.tokens.iter().find(|v| matches!(v.value, TokenValue::Comment(_)))
+	}
+
+	// Returns the label defined on the line
+	pub fn label(&self) -> Option<&CstToken> {
+		match self.kind {
+			CstLineKind::Label => self.code().first().copied(),
+			_ => None
+		}
+	}
+
+	// Returns the tokens of the instruction or pragma on the line, after any label
+	pub fn statement(&self) -> Vec<&CstToken> {
+		let code = self.code();
+		match self.kind {
+			CstLineKind::Label => code[2..].to_vec(),
+			CstLineKind::Instruction | CstLineKind::Pragma => code,
+			_ => Vec::new()
+		}
+	}
+
+	// Returns whether the line ends with a newline
+	pub fn has_newline(&self) -> bool {
+		matches!(self.tokens.last(), Some(CstToken { value: TokenValue::Newline, .. }))
+	}
+}
+
+// A source file
+#[derive(Debug, PartialEq)]
+pub struct Cst {
+	pub filename: String,
+	pub lines: Vec<CstLine>
+}
+
+// Writes the source back out, byte for byte the same as it was parsed from
+impl fmt::Display for Cst {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for token in self.lines.iter().flat_map(|v| v.tokens.iter()) {
+			write!(f, "{}", token.text)?;
+		}
+		Ok(())
+	}
+}
+
+// Works out what a line contains from its tokens
+fn line_kind(tokens: &[CstToken]) -> CstLineKind {
+	let code: Vec<&TokenValue> = tokens.iter().filter(|v| !v.is_trivia()).map(|v| &v.value).collect();
+	match code.as_slice() {
+		[] => CstLineKind::Empty,
+		[TokenValue::Symbol(_), TokenValue::Colon, ..] => CstLineKind::Label,
+		[TokenValue::Symbol(_), TokenValue::Equals, ..] => CstLineKind::Assignment,
+		[TokenValue::Symbol(_), ..] => CstLineKind::Instruction,
+		[TokenValue::Dot, ..] => CstLineKind::Pragma,
+		_ => CstLineKind::Invalid
+	}
+}
+
+// Splits a source file into lines of tokens, keeping whitespace, comments and newlines with their exact text
+// This never fails: text that doesn't lex is kept as an error token, so any source can be edited and written back
+pub fn parse(filename: &str, src: &str) -> Cst {
+	let mut lexer = Lexer::new(filename, src);
+	let mut tokens = Vec::new();
+	while let Some(token) = lexer.next_with_trivia() {
+		tokens.push(token);
+	}
+
+	// Every token runs until the next one starts
+	let ends: Vec<usize> = tokens.iter().skip(1).map(|v| v.pos).chain(std::iter::once(src.len())).collect();
+	let mut lines = Vec::new();
+	let mut line = Vec::new();
+	for (token, end) in tokens.into_iter().zip(ends) {
+		let newline = token.value == TokenValue::Newline;
+		line.push(CstToken {
+			text: String::from(&src[token.pos..end]),
+			value: token.value,
+			lino: token.lino,
+			col: token.charpos
+		});
+
+		if newline {
+			lines.push(CstLine { lino: line[0].lino, kind: line_kind(&line), tokens: line });
+			line = Vec::new();
+		}
+	}
+	if !line.is_empty() {
+		lines.push(CstLine { lino: line[0].lino, kind: line_kind(&line), tokens: line });
+	}
+
+	Cst {
+		filename: String::from(filename),
+		lines
+	}
+}
@@ -0,0 +1,74 @@
+//
+// src
+// debug_info.rs: Writes debug info mapping addresses back to source locations.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+// The debug info is a JSON object of the form
+// {
+//     "version": 1,
+//     "files": ["main.s", ...],
+//     "ranges": [
+//         {
+//             "start": 8192,     first address of the range
+//             "end": 8193,       last address of the range (inclusive)
+//             "file": 0,         index into "files"
+//             "line": 8,         line number (starting at 1)
+//             "column": 1,       column of the instruction or pragma (starting at 1)
+//             "label": "_start", last label defined before the line, or null
+//             "kind": "code"     "code" for instructions, "data" for .byte, .bytes, and .word
+//         },
+//         ...
+//     ]
+// }
+// Ranges are sorted by start address.
+//
+
+use crate::json::JsonValue;
+use crate::pass_1::LineKind;
+use crate::pass_2::AssemblerResult;
+
+// The version of the debug info format
+pub const DEBUG_INFO_VERSION: i64 = 1;
+
+// Generates the debug info for an assembled result
+pub fn generate_debug_info(result: &AssemblerResult) -> String {
+	let mut files: Vec<&String> = Vec::new();
+	let mut ranges: Vec<_> = result.debug.iter().collect();
+	ranges.sort_by_key(|v| v.start);
+
+	let ranges = ranges.into_iter().map(|range| {
+		// Get file index
+		let file = match files.iter().position(|v| **v == range.filename) {
+			Some(i) => i,
+			None => {
+				files.push(&range.filename);
+				files.len() - 1
+			}
+		};
+
+		JsonValue::object(vec![
+			("start", JsonValue::Number(range.start as i64)),
+			("end", JsonValue::Number(range.end as i64)),
+			("file", JsonValue::Number(file as i64)),
+			("line", JsonValue::Number(range.lino as i64)),
+			("column", JsonValue::Number(range.col as i64 + 1)),
+			("label", match &range.label {
+				Some(label) => JsonValue::string(label),
+				None => JsonValue::Null
+			}),
+			("kind", JsonValue::string(match range.kind {
+				LineKind::Code => "code",
+				LineKind::Data => "data"
+			})),
+		])
+	}).collect();
+
+	let json = JsonValue::object(vec![
+		("version", JsonValue::Number(DEBUG_INFO_VERSION)),
+		("files", JsonValue::Array(files.into_iter().map(|v| JsonValue::string(v)).collect())),
+		("ranges", JsonValue::Array(ranges)),
+	]);
+	format!("{}\n", json)
+}
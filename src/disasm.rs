@@ -0,0 +1,518 @@
+//
+// src
+// disasm.rs: Disassembles binaries into source that can be assembled again.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::OnceLock;
+
+use crate::lexer::Lexer;
+use crate::parser;
+use crate::parser::{
+	Address,
+	AddressingMode,
+	ImmediateValue,
+	Instruction,
+	Line,
+	LineValue
+};
+use crate::pass_1;
+use crate::pass_1::{
+	AnnotatedLine,
+	Bank,
+	InstructionArg,
+	LineKind
+};
+
+// The kind of operand an opcode takes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+	Implicit,
+	Immediate,
+	ZeroPage,
+	ZeroPageX,
+	ZeroPageY,
+	Absolute,
+	AbsoluteX,
+	AbsoluteY,
+	IndirectX,
+	IndirectY,
+	Indirect,
+
+	// A branch, whose operand is an offset from the next instruction
+	Relative
+}
+
+// The addressing modes tried when building the opcode table; absolute modes come first so that opcodes the first pass emits for more than one mode (lda $zp, y is lda $addr, y) decode as the mode they really are
+const OPERAND_KINDS: [OperandKind; 11] = [
+	OperandKind::Implicit,
+	OperandKind::Immediate,
+	OperandKind::Absolute,
+	OperandKind::AbsoluteX,
+	OperandKind::AbsoluteY,
+	OperandKind::Indirect,
+	OperandKind::ZeroPage,
+	OperandKind::ZeroPageX,
+	OperandKind::ZeroPageY,
+	OperandKind::IndirectX,
+	OperandKind::IndirectY
+];
+
+// What an opcode decodes to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodeInfo {
+	pub mnemonic: &'static str,
+	pub kind: OperandKind,
+
+	// The number of bytes the assembler reserves for the instruction (brk reserves 2)
+	pub size: u16,
+
+	// The number of cycles the instruction takes on an NMOS 6502, and whether it takes one more when indexing crosses a page
+	// Branches take one more cycle when taken, and another when the target is on another page
+	pub cycles: u8,
+	pub page_penalty: bool
+}
+
+// A decoded instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decoded {
+	pub addr: u16,
+	pub opcode: u8,
+	pub info: OpcodeInfo,
+
+	// The operand; for branches this is the target address
+	pub operand: u16
+}
+
+impl Decoded {
+	// Converts the instruction into one the parser would produce, naming addresses with the given function
+	pub fn to_instruction<F: FnMut(u16) -> Option<String>>(&self, mut name: F) -> Instruction {
+		let mut addr = |n: u16| match name(n) {
+			Some(label) => Address::Label(label),
+			None => Address::Literal(n)
+		};
+
+		let addr_mode = match self.info.kind {
+			OperandKind::Implicit => AddressingMode::Implicit,
+			OperandKind::Immediate => AddressingMode::Immediate(ImmediateValue::Literal(self.operand as u8)),
+			OperandKind::ZeroPage => AddressingMode::ZeroPage(addr(self.operand)),
+			OperandKind::ZeroPageX => AddressingMode::ZeroPageX(addr(self.operand)),
+			OperandKind::ZeroPageY => AddressingMode::ZeroPageY(addr(self.operand)),
+			OperandKind::Absolute | OperandKind::Relative => AddressingMode::Absolute(addr(self.operand)),
+			OperandKind::AbsoluteX => AddressingMode::AbsoluteX(addr(self.operand)),
+			OperandKind::AbsoluteY => AddressingMode::AbsoluteY(addr(self.operand)),
+			OperandKind::IndirectX => AddressingMode::IndirectX(addr(self.operand)),
+			OperandKind::IndirectY => AddressingMode::IndirectY(addr(self.operand)),
+			OperandKind::Indirect => AddressingMode::Indirect(addr(self.operand))
+		};
+
+		Instruction {
+			opcode: String::from(self.info.mnemonic),
+			addr_mode
+		}
+	}
+}
+
+// Creates a line for encode_instruction to fill in
+fn empty_line() -> AnnotatedLine {
+	AnnotatedLine {
+		lino: 0,
+		col: 0,
+		kind: LineKind::Code,
+		bank: Bank::None,
+		segment: None,
+		addr: 0,
+		opcode: 0,
+		arg: InstructionArg::NoArgs
+	}
+}
+
+// Gets the number of cycles an instruction takes and whether it has a page crossing penalty
+fn timing(mnemonic: &str, kind: OperandKind) -> (u8, bool) {
+	let modifies = matches!(mnemonic, "asl" | "rol" | "lsr" | "ror" | "inc" | "dec");
+	let stores = matches!(mnemonic, "sta" | "stx" | "sty");
+
+	match kind {
+		OperandKind::Implicit => (match mnemonic {
+			"pha" | "php" => 3,
+			"pla" | "plp" => 4,
+			"rts" | "rti" => 6,
+			"brk" => 7,
+			_ => 2
+		}, false),
+		OperandKind::Immediate | OperandKind::Relative => (2, false),
+		OperandKind::ZeroPage => (if modifies { 5 } else { 3 }, false),
+		OperandKind::ZeroPageX | OperandKind::ZeroPageY => (if modifies { 6 } else { 4 }, false),
+		OperandKind::Absolute => (match mnemonic {
+			"jmp" => 3,
+			"jsr" => 6,
+			_ if modifies => 6,
+			_ => 4
+		}, false),
+		OperandKind::AbsoluteX | OperandKind::AbsoluteY if modifies => (7, false),
+		OperandKind::AbsoluteX | OperandKind::AbsoluteY if stores => (5, false),
+		OperandKind::AbsoluteX | OperandKind::AbsoluteY => (4, true),
+		OperandKind::IndirectX => (6, false),
+		OperandKind::IndirectY if stores => (6, false),
+		OperandKind::IndirectY => (5, true),
+		OperandKind::Indirect => (5, false)
+	}
+}
+
+// Builds a table of what every opcode decodes to by encoding every mnemonic with every addressing mode
+pub fn opcode_table() -> Vec<Option<OpcodeInfo>> {
+	let lexer = Lexer::new("", "");
+	let mut table = vec![None; 256];
+
+	for mnemonic in pass_1::MNEMONICS {
+		for kind in OPERAND_KINDS.iter() {
+			let label = || Address::Label(String::from("x"));
+			let addr_mode = match kind {
+				OperandKind::Implicit => AddressingMode::Implicit,
+				OperandKind::Immediate => AddressingMode::Immediate(ImmediateValue::Literal(0)),
+				OperandKind::ZeroPage => AddressingMode::ZeroPage(label()),
+				OperandKind::ZeroPageX => AddressingMode::ZeroPageX(label()),
+				OperandKind::ZeroPageY => AddressingMode::ZeroPageY(label()),
+				OperandKind::Absolute => AddressingMode::Absolute(label()),
+				OperandKind::AbsoluteX => AddressingMode::AbsoluteX(label()),
+				OperandKind::AbsoluteY => AddressingMode::AbsoluteY(label()),
+				OperandKind::IndirectX => AddressingMode::IndirectX(label()),
+				OperandKind::IndirectY => AddressingMode::IndirectY(label()),
+				OperandKind::Indirect => AddressingMode::Indirect(label()),
+				OperandKind::Relative => unreachable!()
+			};
+
+			let instr = Instruction {
+				opcode: String::from(*mnemonic),
+				addr_mode
+			};
+			let mut line = empty_line();
+
+			// Skip modes the opcode doesn't have and opcodes already decoded from an earlier mode
+			let size = match pass_1::encode_instruction(&lexer, instr, &mut line) {
+				Ok(size) => size,
+				Err(_) => continue
+			};
+			let entry = &mut table[line.opcode as usize];
+			if entry.is_some() {
+				continue;
+			}
+
+			let kind = match line.arg {
+				InstructionArg::RelativeLabelArg(_) => OperandKind::Relative,
+				_ => *kind
+			};
+			let (cycles, page_penalty) = timing(mnemonic, kind);
+			*entry = Some(OpcodeInfo {
+				mnemonic,
+				kind,
+				size,
+				cycles,
+				page_penalty
+			});
+		}
+	}
+
+	table
+}
+
+// Returns the opcode table, which is only built the first time it's needed
+pub fn opcodes() -> &'static [Option<OpcodeInfo>] {
+	static TABLE: OnceLock<Vec<Option<OpcodeInfo>>> = OnceLock::new();
+	TABLE.get_or_init(opcode_table)
+}
+
+// Decodes the instruction at the start of bytes, which is at addr; returns None if the opcode is invalid or the instruction is cut off
+pub fn decode(table: &[Option<OpcodeInfo>], bytes: &[u8], addr: u16) -> Option<Decoded> {
+	let opcode = *bytes.first()?;
+	let info = table[opcode as usize]?;
+
+	let operand = match info.kind {
+		OperandKind::Implicit => 0,
+		OperandKind::Relative => {
+			let offset = *bytes.get(1)? as i8;
+			addr.wrapping_add(2).wrapping_add(offset as u16)
+		}
+		_ if info.size == 2 => *bytes.get(1)? as u16,
+		_ => u16::from_le_bytes([*bytes.get(1)?, *bytes.get(2)?])
+	};
+
+	Some(Decoded {
+		addr,
+		opcode,
+		info,
+		operand
+	})
+}
+
+// Checks whether the source of an instruction assembles back to the opcode it was decoded from
+fn reassembles(text: &str, d: &Decoded) -> bool {
+	let mut lexer = Lexer::new("", &format!("{}\n", text));
+	let instr = match parser::parse_line(&mut lexer) {
+		Ok(Some(Line { value: LineValue::Instruction(instr), .. })) => instr,
+		_ => return false
+	};
+
+	let mut line = empty_line();
+	match pass_1::encode_instruction(&lexer, instr, &mut line) {
+		Ok(size) => size == d.info.size && line.opcode == d.opcode,
+		Err(_) => false
+	}
+}
+
+// Gets the NMI, reset, and IRQ vectors if the binary covers $FFFA-$FFFF
+pub fn vectors(bytes: &[u8], load: u16) -> Vec<u16> {
+	let start = match 0xFFFA_usize.checked_sub(load as usize) {
+		Some(start) if start + 6 <= bytes.len() => start,
+		_ => return Vec::new()
+	};
+	bytes[start..start + 6].chunks(2).map(|v| u16::from_le_bytes([v[0], v[1]])).collect()
+}
+
+// Finds which bytes are the start of an instruction by following the flow of code from the entry points; bytes that aren't reached are left as None, and operands of instructions are Some(false)
+pub fn trace(table: &[Option<OpcodeInfo>], bytes: &[u8], load: u16, entries: &[u16]) -> Vec<Option<bool>> {
+	let mut code = vec![None; bytes.len()];
+	let offset = |addr: u16| Some(addr.checked_sub(load)? as usize).filter(|v| *v < bytes.len());
+	let mut stack = entries.to_vec();
+
+	while let Some(mut pc) = stack.pop() {
+		while let Some(i) = offset(pc) {
+			// Stop at invalid opcodes and anything that overlaps code found earlier
+			let d = match decode(table, &bytes[i..], pc) {
+				Some(d) => d,
+				None => break
+			};
+			let size = d.info.size as usize;
+			if i + size > bytes.len() || code[i..i + size].iter().any(|v| v.is_some()) {
+				break;
+			}
+			code[i] = Some(true);
+			for v in code[i + 1..i + size].iter_mut() {
+				*v = Some(false);
+			}
+
+			// Follow branches, jumps, and subroutine calls
+			match (d.info.mnemonic, d.info.kind) {
+				(_, OperandKind::Relative) | ("jsr", _) => stack.push(d.operand),
+				("jmp", OperandKind::Absolute) => {
+					stack.push(d.operand);
+					break;
+				}
+				("jmp", OperandKind::Indirect) => {
+					if let (Some(lo), Some(hi)) = (offset(d.operand), offset(d.operand.wrapping_add(1))) {
+						stack.push(u16::from_le_bytes([bytes[lo], bytes[hi]]));
+					}
+					break;
+				}
+				("rts", _) | ("rti", _) | ("brk", _) => break,
+				_ => ()
+			}
+			pc = pc.wrapping_add(d.info.size);
+		}
+	}
+
+	code
+}
+
+// An item of a disassembly
+#[derive(Debug)]
+enum Item {
+	Instruction(Decoded),
+	Byte(u16, u8),
+
+	// A vector at $FFFA-$FFFF
+	Word(u16, u16)
+}
+
+impl Item {
+	fn addr(&self) -> u16 {
+		match self {
+			Item::Instruction(d) => d.addr,
+			Item::Byte(addr, _) | Item::Word(addr, _) => *addr
+		}
+	}
+}
+
+// Disassembles a binary loaded at the given address into source that assembles back to the same bytes, using symbols to name addresses; labels are synthesised for the targets of branches, jumps, and vectors
+// With no entry points every byte is decoded in order; otherwise only the code reached from the entry points is, and everything else is written as .bytes (apart from the vectors)
+// It is an error for the binary to run past $FFFF
+pub fn disassemble(bytes: &[u8], load: u16, symbols: &[(String, u16)], entries: &[u16]) -> Result<String, String> {
+	let table = opcodes();
+	let end = load as usize + bytes.len();
+	if end > 0x10000 {
+		return Err(format!("{} bytes loaded at ${:04X} run past $FFFF", bytes.len(), load));
+	}
+	let code = if entries.is_empty() {
+		None
+	} else {
+		Some(trace(table, bytes, load, entries))
+	};
+	let vectors = match 0xFFFA_usize.checked_sub(load as usize) {
+		Some(start) => start..start + 6,
+		None => 0..0
+	};
+
+	// Decode every instruction, falling back to bytes for anything invalid or unreached
+	let mut items = Vec::new();
+	let mut i = 0;
+	while i < bytes.len() {
+		let addr = load.wrapping_add(i as u16);
+		let decoded = match &code {
+			Some(code) if code[i] != Some(true) => None,
+			_ => decode(table, &bytes[i..], addr)
+		};
+
+		match decoded {
+			// brk skips a byte, which is only reassembled the same if it's 0 and something follows it
+			Some(d) if d.opcode == 0x00 && (bytes.get(i + 1) != Some(&0) || i + 2 >= bytes.len()) => {
+				items.push(Item::Byte(addr, bytes[i]));
+				i += 1;
+			}
+
+			Some(d) => {
+				items.push(Item::Instruction(d));
+				i += d.info.size as usize;
+			}
+
+			// Vectors that aren't code
+			None if code.is_some() && vectors.contains(&i) && (i - vectors.start).is_multiple_of(2) && bytes.len() - i >= 2 => {
+				items.push(Item::Word(addr, u16::from_le_bytes([bytes[i], bytes[i + 1]])));
+				i += 2;
+			}
+
+			None => {
+				items.push(Item::Byte(addr, bytes[i]));
+				i += 1;
+			}
+		}
+	}
+
+	// Name addresses from the symbol file, keeping the first name for each address
+	let mut names: HashMap<u16, String> = HashMap::new();
+	for (name, value) in symbols {
+		names.entry(*value).or_insert_with(|| name.clone());
+	}
+
+	// Labels can only be placed at the start of an item, so symbols in the middle of one aren't used
+	let starts: Vec<u16> = items.iter().map(|v| v.addr()).collect();
+	let in_binary = |addr: u16| (load as usize..end).contains(&(addr as usize));
+	names.retain(|k, _| !in_binary(*k) || starts.binary_search(k).is_ok());
+
+	// Synthesise labels for branch, jump, and vector targets
+	for item in items.iter_mut() {
+		let target = match item {
+			Item::Instruction(d) => match (d.info.kind, d.info.mnemonic) {
+				(OperandKind::Relative, _) | (OperandKind::Absolute, "jmp") | (OperandKind::Absolute, "jsr") => d.operand,
+				_ => continue
+			}
+			Item::Word(_, value) => *value,
+			Item::Byte(..) => continue
+		};
+
+		// Branches into the middle of an item stay as bytes
+		if in_binary(target) && starts.binary_search(&target).is_err() {
+			if let Item::Instruction(d) = item {
+				if d.info.kind == OperandKind::Relative {
+					*item = Item::Byte(d.addr, d.opcode);
+				}
+			}
+			continue;
+		}
+		names.entry(target).or_insert_with(|| format!("L{:04X}", target));
+	}
+
+	// Write every item, grouping bytes into .bytes lines
+	let mut out = String::new();
+	let mut used: Vec<u16> = Vec::new();
+	let mut data: Vec<u8> = Vec::new();
+	let mut data_addr = load;
+	let mut addr = load;
+	let mut iter = items.into_iter();
+	loop {
+		let item = iter.next();
+
+		// Branches turned into bytes leave their offset behind, which is also written as data
+		let next = item.as_ref().map(|v| v.addr()).unwrap_or(addr);
+		while addr < next {
+			if data.is_empty() {
+				data_addr = addr;
+			}
+			data.push(bytes[(addr - load) as usize]);
+			addr = addr.wrapping_add(1);
+		}
+
+		// Flush data before labels and instructions, every eight bytes, and at the end
+		let label = item.as_ref().and_then(|v| names.get(&v.addr()).filter(|_| in_binary(v.addr())));
+		let flush = match item {
+			Some(Item::Byte(..)) => label.is_some() || data.len() >= 8,
+			_ => true
+		};
+		if flush && !data.is_empty() {
+			let bytes: Vec<String> = data.iter().map(|v| format!("${:02X}", v)).collect();
+			let _ = writeln!(out, "\t.bytes {}\t; ${:04X}", bytes.join(", "), data_addr);
+			data.clear();
+		}
+
+		let item = match item {
+			Some(item) => item,
+			None => break
+		};
+
+		if let Some(label) = label {
+			let _ = writeln!(out, "{}:", label);
+		}
+
+		// Names outside the binary are only defined if they're used
+		let mut named = |n: u16| {
+			let name = names.get(&n).cloned();
+			if name.is_some() && !in_binary(n) {
+				used.push(n);
+			}
+			name
+		};
+
+		match item {
+			Item::Instruction(d) => {
+				let instr = d.to_instruction(&mut named);
+
+				// Instructions the parser would read as another instruction are written as bytes
+				let text = instr.to_string();
+				if reassembles(&text, &d) {
+					let _ = writeln!(out, "\t{}\t; ${:04X}", text, d.addr);
+				} else {
+					let start = (d.addr - load) as usize;
+					let bytes: Vec<String> = bytes[start..start + d.info.size as usize].iter().map(|v| format!("${:02X}", v)).collect();
+					let _ = writeln!(out, "\t.bytes {}\t; {}", bytes.join(", "), text);
+				}
+				addr = d.addr.wrapping_add(d.info.size);
+			}
+
+			Item::Word(a, value) => {
+				let _ = writeln!(out, "\t.word {}\t; ${:04X}", named(value).unwrap_or_else(|| format!("${:04X}", value)), a);
+				addr = a.wrapping_add(2);
+			}
+
+			Item::Byte(a, byte) => {
+				if data.is_empty() {
+					data_addr = a;
+				}
+				data.push(byte);
+				addr = a.wrapping_add(1);
+			}
+		}
+	}
+
+	// Define the names outside the binary before the code
+	used.sort_unstable();
+	used.dedup();
+	let mut header = String::new();
+	for value in used {
+		let _ = writeln!(header, ".define {} ${:04X}", names[&value], value);
+	}
+	let _ = writeln!(header, ".origin ${:04X}", load);
+	Ok(header + &out)
+}
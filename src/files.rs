@@ -0,0 +1,76 @@
+//
+// src
+// files.rs: Reads source and binary files from disk or from memory.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Something files can be read from, such as the sources and the .include and .incbin files of the first pass
+pub trait FileProvider {
+	// Reads the bytes of a file
+	fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+
+	// Returns whether a file exists
+	fn exists(&self, path: &str) -> bool;
+
+	// Reads a file as UTF-8 text
+	fn read_to_string(&self, path: &str) -> io::Result<String> {
+		String::from_utf8(self.read(path)?).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "file is not valid UTF-8"))
+	}
+}
+
+// Files on disk, with paths relative to the working directory
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSystem;
+
+impl FileProvider for FileSystem {
+	fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		fs::read(path)
+	}
+
+	fn exists(&self, path: &str) -> bool {
+		Path::new(path).is_file()
+	}
+}
+
+// Files held in memory, looked up by their exact path
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem {
+	files: HashMap<String, Vec<u8>>
+}
+
+impl MemoryFileSystem {
+	// Creates an empty file system
+	pub fn new() -> MemoryFileSystem {
+		MemoryFileSystem::default()
+	}
+
+	// Adds or replaces a file
+	pub fn insert<C: Into<Vec<u8>>>(&mut self, path: &str, contents: C) {
+		self.files.insert(String::from(path), contents.into());
+	}
+
+	// Removes a file, returning its contents
+	pub fn remove(&mut self, path: &str) -> Option<Vec<u8>> {
+		self.files.remove(path)
+	}
+}
+
+impl FileProvider for MemoryFileSystem {
+	fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		match self.files.get(path) {
+			Some(contents) => Ok(contents.clone()),
+			None => Err(io::Error::new(io::ErrorKind::NotFound, "no such file in memory"))
+		}
+	}
+
+	fn exists(&self, path: &str) -> bool {
+		self.files.contains_key(path)
+	}
+}
@@ -0,0 +1,189 @@
+//
+// src
+// formatter.rs: Reformats assembly sources in a consistent style.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use crate::cst::{
+	self,
+	CstLineKind,
+	CstToken
+};
+use crate::lexer::{
+	Lexer,
+	TokenValue
+};
+use crate::parser::{
+	self,
+	ParseError
+};
+
+// The width of a tab
+const TAB_WIDTH: usize = 8;
+
+// How mnemonics are cased
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MnemonicCase {
+	Lower,
+	Upper,
+	Preserve
+}
+
+// The layout of formatted code
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+	pub case: MnemonicCase,
+	pub label_column: usize,
+	pub instruction_column: usize,
+	pub operand_column: usize,
+	pub comment_column: usize,
+
+	// Whether to pad with tabs (as far as possible) instead of spaces
+	pub tabs: bool
+}
+
+impl Default for FormatOptions {
+	fn default() -> Self {
+		FormatOptions {
+			case: MnemonicCase::Lower,
+			label_column: 0,
+			instruction_column: 8,
+			operand_column: 12,
+			comment_column: 32,
+			tabs: true
+		}
+	}
+}
+
+// Returns the width of a line, expanding tabs
+fn width(line: &str) -> usize {
+	line.chars().fold(0, |w, c| if c == '\t' { (w / TAB_WIDTH + 1) * TAB_WIDTH } else { w + 1 })
+}
+
+// Pads a line up to a column, leaving at least one space after any text already there
+fn pad(line: &mut String, column: usize, tabs: bool) {
+	let mut w = width(line);
+	if w > 0 && w >= column {
+		line.push(' ');
+		return;
+	}
+
+	if tabs {
+		while (w / TAB_WIDTH + 1) * TAB_WIDTH <= column {
+			line.push('\t');
+			w = (w / TAB_WIDTH + 1) * TAB_WIDTH;
+		}
+	}
+	while w < column {
+		line.push(' ');
+		w += 1;
+	}
+}
+
+// Joins operand tokens; comparisons (as in .assert) get spaces around them, while < and > are low and high byte prefixes otherwise
+fn join(tokens: &[&CstToken], comparison: bool) -> String {
+	let is_operator = |v: &TokenValue| comparison && matches!(v, TokenValue::LT | TokenValue::GT | TokenValue::Equals | TokenValue::Bang);
+
+	let mut out = String::new();
+	let mut prev: Option<&TokenValue> = None;
+	for token in tokens.iter() {
+		let value = &token.value;
+		let space = match prev {
+			None => false,
+			Some(TokenValue::LT | TokenValue::GT | TokenValue::Bang) if is_operator(value) => false,
+			Some(p) if is_operator(p) || is_operator(value) => true,
+			Some(p) => !matches!(p, TokenValue::LParen | TokenValue::Hash | TokenValue::LT | TokenValue::GT | TokenValue::Colon)
+				&& !matches!(value, TokenValue::Comma | TokenValue::RParen | TokenValue::Colon)
+		};
+		if space {
+			out.push(' ');
+		}
+		out.push_str(&token.text);
+		prev = Some(value);
+	}
+	out
+}
+
+// Appends an instruction or pragma to a line
+fn format_statement(line: &mut String, tokens: &[&CstToken], options: &FormatOptions) {
+	if tokens.is_empty() {
+		return;
+	}
+	pad(line, options.instruction_column, options.tabs);
+
+	let (args, comparison) = match tokens[0].value {
+		// Pragmas
+		TokenValue::Dot => {
+			line.push('.');
+			let name = tokens.get(1).map(|v| v.text.as_str()).unwrap_or("");
+			line.push_str(name);
+			(tokens.get(2..).unwrap_or(&[]), name == "assert")
+		}
+
+		// Instructions
+		_ => {
+			let mnemonic = &tokens[0].text;
+			line.push_str(&match options.case {
+				MnemonicCase::Lower => mnemonic.to_lowercase(),
+				MnemonicCase::Upper => mnemonic.to_uppercase(),
+				MnemonicCase::Preserve => mnemonic.clone()
+			});
+			(&tokens[1..], false)
+		}
+	};
+
+	if !args.is_empty() {
+		pad(line, options.operand_column, options.tabs);
+		line.push_str(&join(args, comparison));
+	}
+}
+
+// Formats a source file, erroring if it doesn't parse
+pub fn format_source(filename: &str, src: &str, options: &FormatOptions) -> Result<String, ParseError> {
+	parser::parse(&mut Lexer::new(filename, src))?;
+
+	let mut out = String::new();
+	for line in cst::parse(filename, src).lines.iter() {
+		let code = line.code();
+		let mut text = String::new();
+
+		match line.kind {
+			// Labels
+			CstLineKind::Label => {
+				pad(&mut text, options.label_column, options.tabs);
+				text.push_str(&code[0].text);
+				text.push(':');
+				format_statement(&mut text, &line.statement(), options);
+			}
+
+			// Assignments (name = value)
+			CstLineKind::Assignment => {
+				pad(&mut text, options.label_column, options.tabs);
+				text.push_str(&code[0].text);
+				text.push_str(" = ");
+				text.push_str(&join(&code[2..], false));
+			}
+
+			_ => format_statement(&mut text, &code, options)
+		}
+
+		// Comments on their own line stay at the start of the line or are indented like instructions
+		if let Some(comment) = line.comment() {
+			if !code.is_empty() {
+				pad(&mut text, options.comment_column, options.tabs);
+			} else if !matches!(line.tokens[0].value, TokenValue::Comment(_)) {
+				pad(&mut text, options.instruction_column, options.tabs);
+			}
+			text.push_str(comment.text.trim_end());
+		}
+
+		out.push_str(text.trim_end());
+		if line.has_newline() {
+			out.push('\n');
+		}
+	}
+
+	Ok(out)
+}
@@ -0,0 +1,145 @@
+//
+// src
+// ines.rs: Builds iNES and NES 2.0 ROM images from banked code.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use crate::parser::{
+	InesField,
+	Mirroring
+};
+use crate::pass_1::Bank;
+use crate::pass_2::AssemblerResult;
+
+// The size of a PRG bank
+pub const PRG_BANK_SIZE: usize = 0x4000;
+
+// The size of a CHR bank
+pub const CHR_BANK_SIZE: usize = 0x2000;
+
+// Represents the header of an iNES ROM
+#[derive(Debug, Clone)]
+pub struct InesHeader {
+	pub mapper: u16,
+	pub submapper: u8,
+	pub mirroring: Mirroring,
+	pub prg_banks: Option<u16>,
+	pub chr_banks: Option<u16>,
+	pub battery: bool,
+	pub nes2: bool,
+}
+
+impl Default for InesHeader {
+	fn default() -> InesHeader {
+		InesHeader {
+			mapper: 0,
+			submapper: 0,
+			mirroring: Mirroring::Horizontal,
+			prg_banks: None,
+			chr_banks: None,
+			battery: false,
+			nes2: false,
+		}
+	}
+}
+
+impl InesHeader {
+	// Sets a field of the header
+	pub fn set(&mut self, field: InesField) {
+		match field {
+			InesField::Mapper(n) => self.mapper = n,
+			InesField::Submapper(n) => self.submapper = n,
+			InesField::Mirroring(m) => self.mirroring = m,
+			InesField::PrgBanks(n) => self.prg_banks = Some(n),
+			InesField::ChrBanks(n) => self.chr_banks = Some(n),
+			InesField::Battery => self.battery = true,
+			InesField::Nes2 => self.nes2 = true,
+		}
+	}
+
+	// Serialises the header into its 16 bytes
+	fn bytes(&self, prg_banks: u16, chr_banks: u16) -> [u8; 16] {
+		let mut header = [0u8; 16];
+		header[0..4].copy_from_slice(b"NES\x1A");
+		header[4] = prg_banks as u8;
+		header[5] = chr_banks as u8;
+
+		// Flags 6: mirroring, battery, four screen, and lower nybble of the mapper
+		header[6] = ((self.mapper as u8 & 0x0F) << 4)
+			| if self.mirroring == Mirroring::Vertical { 0x01 } else { 0 }
+			| if self.battery { 0x02 } else { 0 }
+			| if self.mirroring == Mirroring::FourScreen { 0x08 } else { 0 };
+
+		// Flags 7: upper nybble of the mapper
+		header[7] = self.mapper as u8 & 0xF0;
+
+		// NES 2.0 extensions
+		if self.nes2 {
+			header[7] |= 0x08;
+			header[8] = ((self.mapper >> 8) as u8 & 0x0F) | (self.submapper << 4);
+			header[9] = ((prg_banks >> 8) as u8 & 0x0F) | (((chr_banks >> 8) as u8 & 0x0F) << 4);
+		}
+
+		header
+	}
+}
+
+// Builds a .nes file from the header and the assembled banks
+pub fn write_ines(header: &InesHeader, banks: &[(Bank, AssemblerResult)]) -> Result<Vec<u8>, String> {
+	// Count banks
+	let used_prg = banks.iter().filter_map(|v| if let Bank::Prg(n) = v.0 { Some(n as u16 + 1) } else { None }).max().unwrap_or(0);
+	let used_chr = banks.iter().filter_map(|v| if let Bank::Chr(n) = v.0 { Some(n as u16 + 1) } else { None }).max().unwrap_or(0);
+	let prg_banks = header.prg_banks.unwrap_or_else(|| used_prg.max(1));
+	let chr_banks = header.chr_banks.unwrap_or(used_chr);
+
+	if used_prg > prg_banks {
+		return Err(format!("PRG bank {} used but the header only has {} PRG banks", used_prg - 1, prg_banks));
+	} else if used_chr > chr_banks {
+		return Err(format!("CHR bank {} used but the header only has {} CHR banks", used_chr - 1, chr_banks));
+	} else if !header.nes2 && (prg_banks > 255 || chr_banks > 255) {
+		return Err(String::from("More than 255 banks requires an NES 2.0 header (.ines nes2)"));
+	}
+
+	// Code outside of any bank is not allowed
+	if let Some((_, result)) = banks.iter().find(|v| v.0 == Bank::None && !v.1.segments.is_empty()) {
+		return Err(format!("Code in {} at ${:04X} is not inside a .bank or .chr_bank", result.filename, result.start().unwrap_or(0)));
+	}
+
+	let mut rom = Vec::with_capacity(16 + prg_banks as usize * PRG_BANK_SIZE + chr_banks as usize * CHR_BANK_SIZE);
+	rom.extend(&header.bytes(prg_banks, chr_banks));
+
+	// PRG banks are assembled at either $8000 or $C000
+	for i in 0..prg_banks {
+		match banks.iter().find(|v| v.0 == Bank::Prg(i as u8)) {
+			Some((_, result)) => {
+				let start = result.start().unwrap_or(0x8000) as usize;
+				let end = result.end().unwrap_or(0x8000) as usize;
+				let base: usize = if start >= 0xC000 { 0xC000 } else { 0x8000 };
+				if start < base || end >= base + PRG_BANK_SIZE {
+					return Err(format!("PRG bank {} spans ${:04X}-${:04X}, which does not fit in ${:04X}-${:04X}", i, start, end, base, base + PRG_BANK_SIZE - 1));
+				}
+				rom.extend(&result.image()[base..base + PRG_BANK_SIZE]);
+			}
+
+			None => rom.extend(&[0u8; PRG_BANK_SIZE])
+		}
+	}
+
+	// CHR banks are assembled at $0000
+	for i in 0..chr_banks {
+		match banks.iter().find(|v| v.0 == Bank::Chr(i as u8)) {
+			Some((_, result)) => {
+				if result.end().unwrap_or(0) as usize >= CHR_BANK_SIZE {
+					return Err(format!("CHR bank {} is larger than 8 KiB", i));
+				}
+				rom.extend(&result.image()[0..CHR_BANK_SIZE]);
+			}
+
+			None => rom.extend(&[0u8; CHR_BANK_SIZE])
+		}
+	}
+
+	Ok(rom)
+}
@@ -0,0 +1,277 @@
+//
+// src
+// json.rs: A minimal JSON value used by the machine-readable formats and the language server.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use std::fmt;
+
+// Represents a JSON value
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+	Null,
+	Bool(bool),
+	Number(i64),
+	String(String),
+	Array(Vec<JsonValue>),
+	Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+	// Creates an object from a list of key value pairs
+	pub fn object(pairs: Vec<(&str, JsonValue)>) -> JsonValue {
+		JsonValue::Object(pairs.into_iter().map(|(k, v)| (String::from(k), v)).collect())
+	}
+
+	// Creates a string value
+	pub fn string(s: &str) -> JsonValue {
+		JsonValue::String(String::from(s))
+	}
+
+	// Returns the value of a key if this is an object
+	pub fn get(&self, key: &str) -> Option<&JsonValue> {
+		match self {
+			JsonValue::Object(pairs) => pairs.iter().find(|v| v.0 == key).map(|v| &v.1),
+			_ => None
+		}
+	}
+
+	// Returns the string if this is a string
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			JsonValue::String(s) => Some(s),
+			_ => None
+		}
+	}
+
+	// Returns the number if this is a number
+	pub fn as_i64(&self) -> Option<i64> {
+		match self {
+			JsonValue::Number(n) => Some(*n),
+			_ => None
+		}
+	}
+}
+
+// Parses JSON text
+struct Parser<'a> {
+	chars: std::iter::Peekable<std::str::Chars<'a>>
+}
+
+impl Parser<'_> {
+	// Skips whitespace and returns the next character without consuming it
+	fn peek(&mut self) -> Option<char> {
+		while let Some(' ' | '\t' | '\n' | '\r') = self.chars.peek() {
+			self.chars.next();
+		}
+		self.chars.peek().copied()
+	}
+
+	// Consumes a character, erroring if it is not the expected one
+	fn expect(&mut self, c: char) -> Result<(), String> {
+		match self.peek() {
+			Some(v) if v == c => {
+				self.chars.next();
+				Ok(())
+			}
+			Some(v) => Err(format!("Expected '{}', found '{}'", c, v)),
+			None => Err(format!("Expected '{}', found end of input", c))
+		}
+	}
+
+	// Consumes a keyword (true, false, or null)
+	fn keyword(&mut self, word: &str, value: JsonValue) -> Result<JsonValue, String> {
+		for c in word.chars() {
+			if self.chars.next() != Some(c) {
+				return Err(format!("Expected '{}'", word));
+			}
+		}
+		Ok(value)
+	}
+
+	fn value(&mut self) -> Result<JsonValue, String> {
+		match self.peek() {
+			Some('n') => self.keyword("null", JsonValue::Null),
+			Some('t') => self.keyword("true", JsonValue::Bool(true)),
+			Some('f') => self.keyword("false", JsonValue::Bool(false)),
+			Some('"') => Ok(JsonValue::String(self.string()?)),
+			Some('-' | '0'..='9') => self.number(),
+
+			Some('[') => {
+				self.chars.next();
+				let mut values = Vec::new();
+				if self.peek() == Some(']') {
+					self.chars.next();
+					return Ok(JsonValue::Array(values));
+				}
+				loop {
+					values.push(self.value()?);
+					match self.peek() {
+						Some(',') => { self.chars.next(); }
+						_ => break
+					}
+				}
+				self.expect(']')?;
+				Ok(JsonValue::Array(values))
+			}
+
+			Some('{') => {
+				self.chars.next();
+				let mut pairs = Vec::new();
+				if self.peek() == Some('}') {
+					self.chars.next();
+					return Ok(JsonValue::Object(pairs));
+				}
+				loop {
+					if self.peek() != Some('"') {
+						return Err(String::from("Expected string as object key"));
+					}
+					let key = self.string()?;
+					self.expect(':')?;
+					pairs.push((key, self.value()?));
+					match self.peek() {
+						Some(',') => { self.chars.next(); }
+						_ => break
+					}
+				}
+				self.expect('}')?;
+				Ok(JsonValue::Object(pairs))
+			}
+
+			Some(c) => Err(format!("Unexpected '{}'", c)),
+			None => Err(String::from("Unexpected end of input"))
+		}
+	}
+
+	// Parses a number; fractions are truncated since only integers are represented
+	fn number(&mut self) -> Result<JsonValue, String> {
+		let mut text = String::new();
+		while let Some(c) = self.chars.peek().copied() {
+			if !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9') {
+				break;
+			}
+			text.push(c);
+			self.chars.next();
+		}
+
+		match text.parse::<i64>() {
+			Ok(n) => Ok(JsonValue::Number(n)),
+			Err(_) => match text.parse::<f64>() {
+				Ok(n) => Ok(JsonValue::Number(n as i64)),
+				Err(_) => Err(format!("Invalid number '{}'", text))
+			}
+		}
+	}
+
+	// Parses a string, including the quotes
+	fn string(&mut self) -> Result<String, String> {
+		self.expect('"')?;
+		let mut s = String::new();
+		loop {
+			match self.chars.next() {
+				Some('"') => return Ok(s),
+				Some('\\') => match self.chars.next() {
+					Some('"') => s.push('"'),
+					Some('\\') => s.push('\\'),
+					Some('/') => s.push('/'),
+					Some('b') => s.push('\u{8}'),
+					Some('f') => s.push('\u{c}'),
+					Some('n') => s.push('\n'),
+					Some('r') => s.push('\r'),
+					Some('t') => s.push('\t'),
+					Some('u') => {
+						let mut code = self.hex4()?;
+
+						// Characters outside the basic multilingual plane are written as surrogate pairs
+						if (0xD800..0xDC00).contains(&code) {
+							if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+								return Err(String::from("Expected low surrogate"));
+							}
+							let low = self.hex4()?;
+							if !(0xDC00..0xE000).contains(&low) {
+								return Err(String::from("Expected low surrogate"));
+							}
+							code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+						}
+						s.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+					}
+					_ => return Err(String::from("Invalid escape in string"))
+				},
+				Some(c) => s.push(c),
+				None => return Err(String::from("Unterminated string"))
+			}
+		}
+	}
+
+	// Parses the four hex digits of a \u escape
+	fn hex4(&mut self) -> Result<u32, String> {
+		let digits: String = (0..4).filter_map(|_| self.chars.next()).collect();
+		match u32::from_str_radix(&digits, 16) {
+			Ok(n) if digits.len() == 4 => Ok(n),
+			_ => Err(format!("Invalid unicode escape '{}'", digits))
+		}
+	}
+}
+
+// Parses JSON text into a value
+pub fn parse(text: &str) -> Result<JsonValue, String> {
+	let mut parser = Parser { chars: text.chars().peekable() };
+	let value = parser.value()?;
+	match parser.peek() {
+		Some(c) => Err(format!("Unexpected '{}' after value", c)),
+		None => Ok(value)
+	}
+}
+
+// Writes a string with JSON escapes
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+	write!(f, "\"")?;
+	for c in s.chars() {
+		match c {
+			'"' => write!(f, "\\\"")?,
+			'\\' => write!(f, "\\\\")?,
+			'\n' => write!(f, "\\n")?,
+			'\r' => write!(f, "\\r")?,
+			'\t' => write!(f, "\\t")?,
+			c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+			c => write!(f, "{}", c)?,
+		}
+	}
+	write!(f, "\"")
+}
+
+impl fmt::Display for JsonValue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			JsonValue::Null => write!(f, "null"),
+			JsonValue::Bool(b) => write!(f, "{}", b),
+			JsonValue::Number(n) => write!(f, "{}", n),
+			JsonValue::String(s) => write_string(f, s),
+
+			JsonValue::Array(values) => {
+				write!(f, "[")?;
+				for (i, v) in values.iter().enumerate() {
+					if i != 0 {
+						write!(f, ",")?;
+					}
+					write!(f, "{}", v)?;
+				}
+				write!(f, "]")
+			}
+
+			JsonValue::Object(pairs) => {
+				write!(f, "{{")?;
+				for (i, (k, v)) in pairs.iter().enumerate() {
+					if i != 0 {
+						write!(f, ",")?;
+					}
+					write_string(f, k)?;
+					write!(f, ":{}", v)?;
+				}
+				write!(f, "}}")
+			}
+		}
+	}
+}
@@ -25,6 +25,8 @@ pub enum TokenValue {
 	GT,
 	Dot,
 	Hash,
+	Equals,
+	Bang,
 
 	// Symbol (labels, opcodes, pragmas, etc)
 	Symbol(String),
@@ -35,6 +37,10 @@ pub enum TokenValue {
 	Dec(u16),
 	Hex(u16),
 	String(String),
+
+	// Trivia, only returned by next_with_trivia
+	Whitespace(String),
+	Comment(String),
 }
 
 // Represents a token.
@@ -97,16 +103,13 @@ impl Lexer {
 		let mut in_comment = false;
 
 		for c in self.string[self.state.pos..].char_indices() {
-			// Comments end with a newline
+			// Comments end with a newline, which is left as a token
 			if in_comment && c.1 == '\n' {
-				self.state.pos += 1;
-				self.state.charpos = 0;
-				self.state.lino += 1;
-				in_comment = false
+				break;
 
 			// Skip whitespace and comments
 			} else if c.1 == ' ' || c.1 == '\t' || in_comment {
-				self.state.pos += 1;
+				self.state.pos += c.1.len_utf8();
 				self.state.charpos += 1;
 
 			// Mark semicolons as the start of a comment
@@ -130,6 +133,36 @@ impl Lexer {
 		&self.filename
 	}
 
+	// Returns the source being lexed (without the padding added by new)
+	pub fn get_source(&self) -> &str {
+		&self.string[..self.string.len() - 1]
+	}
+
+	// Returns the next token, returning runs of spaces and tabs and comments as tokens instead of skipping them
+	pub fn next_with_trivia(&mut self) -> Option<Token> {
+		let rest = &self.string[self.state.pos..self.string.len() - 1];
+		let len = match rest.chars().next()? {
+			';' => rest.find('\n').unwrap_or(rest.len()),
+			' ' | '\t' => rest.find(|c| c != ' ' && c != '\t').unwrap_or(rest.len()),
+			_ => return self.next()
+		};
+
+		let text = String::from(&rest[..len]);
+		let token = Token {
+			pos: self.state.pos,
+			lino: self.state.lino,
+			charpos: self.state.charpos,
+			value: if text.starts_with(';') {
+				TokenValue::Comment(text.clone())
+			} else {
+				TokenValue::Whitespace(text.clone())
+			},
+		};
+		self.state.pos += len;
+		self.state.charpos += text.chars().count() as u32;
+		Some(token)
+	}
+
 	pub fn save(&self) -> LexerState {
 		self.state
 	}
@@ -191,6 +224,10 @@ impl Iterator for Lexer {
 						token.value = TokenValue::Dot;
 					} else if c.1 == '#' {
 						token.value = TokenValue::Hash;
+					} else if c.1 == '=' {
+						token.value = TokenValue::Equals;
+					} else if c.1 == '!' {
+						token.value = TokenValue::Bang;
 
 					// Symbols
 					} else if ('a' <= c.1 && c.1 <= 'z') || ('A' <= c.1 && c.1 <= 'Z') || c.1 == '_'
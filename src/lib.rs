@@ -6,9 +6,42 @@
 // Created on October 18 2020.
 //
 
+pub mod acorn;
+pub mod apple;
+pub mod archive;
+pub mod assembler;
+pub mod atari;
+pub mod cbm;
+pub mod cst;
+pub mod debug_info;
+pub mod disasm;
+pub mod files;
+pub mod formatter;
+pub mod ines;
+pub mod json;
 pub mod lexer;
+pub mod linker;
+pub mod listing;
+pub mod lsp;
+pub mod object;
+pub mod o65;
 pub mod parser;
+pub mod output;
 pub mod pass_1;
 pub mod pass_2;
+pub mod sim;
+pub mod spec;
+pub mod symbols;
 
 mod tests;
+
+pub use assembler::{
+	Assembler,
+	AssemblerError,
+	Assembly,
+	Processor
+};
+pub use output::{
+	OutputFormat,
+	OutputOptions
+};
@@ -0,0 +1,438 @@
+//
+// src
+// linker.rs: Places named segments into memory areas according to a linker config.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+// A linker config lists memory areas and the segments placed in them, in order:
+//
+// MEMORY {
+//     ZP:  start = $0000, size = $0100;
+//     ROM: start = $8000, size = $8000, fill = yes, fillval = $FF;
+// }
+// SEGMENTS {
+//     ZEROPAGE: load = ZP;
+//     CODE:     load = ROM;
+//     RODATA:   load = ROM, align = $100;
+//     VECTORS:  load = ROM, start = $FFFA;
+// }
+//
+// Segments are placed one after the other within their area, with the parts of a segment from
+// each file placed in the order the files were given. Comments start with #.
+//
+
+use std::collections::{
+	HashMap,
+	HashSet
+};
+
+use crate::pass_1::{
+	FirstPassResult,
+	SymbolKind
+};
+use crate::pass_2::AssemblerResult;
+
+// A range of memory that segments are placed in
+#[derive(Debug, Clone)]
+pub struct MemoryArea {
+	pub name: String,
+	pub start: u16,
+	pub size: u32,
+	pub fill: Option<u8>
+}
+
+impl MemoryArea {
+	// Returns the last address of the area
+	pub fn end(&self) -> u16 {
+		(self.start as u32 + self.size - 1) as u16
+	}
+}
+
+// Where a segment is placed
+#[derive(Debug, Clone)]
+pub struct SegmentRule {
+	pub name: String,
+	pub area: usize,
+	pub align: u16,
+	pub start: Option<u16>
+}
+
+// Represents a linker config
+#[derive(Debug, Clone, Default)]
+pub struct LinkerConfig {
+	pub areas: Vec<MemoryArea>,
+	pub segments: Vec<SegmentRule>
+}
+
+// How much of a memory area is used after linking
+#[derive(Debug, Clone, PartialEq)]
+pub struct AreaUsage {
+	pub name: String,
+	pub start: u16,
+	pub size: u32,
+	pub used: u32
+}
+
+// A token of a linker config
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Word(String),
+	Number(u32),
+	Punct(char)
+}
+
+// Splits a linker config into tokens with their line numbers
+fn tokenise(filename: &str, source: &str) -> Result<Vec<(u32, Token)>, String> {
+	let mut tokens = Vec::new();
+	for (lino, line) in source.lines().enumerate() {
+		let lino = lino as u32 + 1;
+		let line = line.split('#').next().unwrap_or("");
+		let mut chars = line.chars().peekable();
+
+		while let Some(&c) = chars.peek() {
+			if c.is_whitespace() {
+				chars.next();
+			} else if "{}:;=,".contains(c) {
+				chars.next();
+				tokens.push((lino, Token::Punct(c)));
+			} else if c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '%' {
+				let mut word = String::new();
+				while let Some(&c) = chars.peek() {
+					if c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '%' {
+						word.push(c);
+						chars.next();
+					} else {
+						break;
+					}
+				}
+
+				// Numbers may be decimal, $hex, or %binary
+				let number = if let Some(hex) = word.strip_prefix('$') {
+					Some(u32::from_str_radix(hex, 16))
+				} else if let Some(bin) = word.strip_prefix('%') {
+					Some(u32::from_str_radix(bin, 2))
+				} else if word.starts_with(|c: char| c.is_ascii_digit()) {
+					Some(word.parse::<u32>())
+				} else {
+					None
+				};
+
+				tokens.push((lino, match number {
+					Some(Ok(n)) => Token::Number(n),
+					Some(Err(_)) => return Err(format!("{}:{}: Invalid number '{}'", filename, lino, word)),
+					None => Token::Word(word)
+				}));
+			} else {
+				return Err(format!("{}:{}: Unexpected character '{}'", filename, lino, c));
+			}
+		}
+	}
+	Ok(tokens)
+}
+
+// Gets the next token, erroring at the end of the file
+fn next(filename: &str, tokens: &[(u32, Token)], i: &mut usize, what: &str) -> Result<(u32, Token), String> {
+	match tokens.get(*i) {
+		Some(t) => {
+			*i += 1;
+			Ok(t.clone())
+		}
+		None => Err(format!("{}:{}: Expected {}, found end of file", filename, tokens.last().map(|v| v.0).unwrap_or(1), what))
+	}
+}
+
+// Parses a linker config
+pub fn parse_config(filename: &str, source: &str) -> Result<LinkerConfig, String> {
+	let tokens = tokenise(filename, source)?;
+	let mut config = LinkerConfig::default();
+	let mut i = 0;
+
+	loop {
+		// Block name
+		let (lino, block) = match tokens.get(i) {
+			Some((lino, Token::Word(w))) => (*lino, w.clone()),
+			Some((lino, t)) => return Err(format!("{}:{}: Expected MEMORY or SEGMENTS, found {:?}", filename, lino, t)),
+			None => break
+		};
+		i += 1;
+		if block != "MEMORY" && block != "SEGMENTS" {
+			return Err(format!("{}:{}: Expected MEMORY or SEGMENTS, found {}", filename, lino, block));
+		}
+		match next(filename, &tokens, &mut i, "'{'")? {
+			(_, Token::Punct('{')) => {}
+			(lino, _) => return Err(format!("{}:{}: Expected '{{' after {}", filename, lino, block))
+		}
+
+		// Entries of the form name: key = value, ...;
+		loop {
+			let (lino, name) = match next(filename, &tokens, &mut i, "'}'")? {
+				(_, Token::Punct('}')) => break,
+				(lino, Token::Word(w)) => (lino, w),
+				(lino, _) => return Err(format!("{}:{}: Expected a name", filename, lino))
+			};
+			match next(filename, &tokens, &mut i, "':'")? {
+				(_, Token::Punct(':')) => {}
+				(lino, _) => return Err(format!("{}:{}: Expected ':' after {}", filename, lino, name))
+			}
+
+			let mut attrs: Vec<(String, Token)> = Vec::new();
+			loop {
+				let key = match next(filename, &tokens, &mut i, "an attribute")? {
+					(_, Token::Punct(';')) => break,
+					(_, Token::Word(w)) => w,
+					(lino, _) => return Err(format!("{}:{}: Expected an attribute of {}", filename, lino, name))
+				};
+				match next(filename, &tokens, &mut i, "'='")? {
+					(_, Token::Punct('=')) => {}
+					(lino, _) => return Err(format!("{}:{}: Expected '=' after {}", filename, lino, key))
+				}
+				match next(filename, &tokens, &mut i, "a value")? {
+					(lino, Token::Punct(c)) => return Err(format!("{}:{}: Expected a value for {}, found '{}'", filename, lino, key, c)),
+					(_, value) => attrs.push((key, value))
+				}
+				match next(filename, &tokens, &mut i, "';'")? {
+					(_, Token::Punct(',')) => {}
+					(_, Token::Punct(';')) => break,
+					(lino, _) => return Err(format!("{}:{}: Expected ',' or ';'", filename, lino))
+				}
+			}
+
+			if block == "MEMORY" {
+				config.areas.push(parse_area(filename, lino, name, attrs)?);
+			} else {
+				let rule = parse_rule(filename, lino, name, attrs, &config)?;
+				config.segments.push(rule);
+			}
+		}
+	}
+
+	Ok(config)
+}
+
+// Gets a number attribute that must fit in max
+fn number(filename: &str, lino: u32, key: &str, value: &Token, max: u32) -> Result<u32, String> {
+	match value {
+		Token::Number(n) if *n <= max => Ok(*n),
+		_ => Err(format!("{}:{}: {} must be a number up to ${:X}", filename, lino, key, max))
+	}
+}
+
+// Parses the attributes of a memory area
+fn parse_area(filename: &str, lino: u32, name: String, attrs: Vec<(String, Token)>) -> Result<MemoryArea, String> {
+	let mut start = None;
+	let mut size = None;
+	let mut fill = false;
+	let mut fillval = 0u8;
+
+	for (key, value) in attrs.iter() {
+		match key.as_str() {
+			"start" => start = Some(number(filename, lino, key, value, 0xFFFF)? as u16),
+			"size" => size = Some(number(filename, lino, key, value, 0x10000)?),
+			"fill" => fill = match value {
+				Token::Word(w) if w == "yes" => true,
+				Token::Word(w) if w == "no" => false,
+				_ => return Err(format!("{}:{}: fill must be yes or no", filename, lino))
+			},
+			"fillval" => fillval = number(filename, lino, key, value, 0xFF)? as u8,
+			_ => return Err(format!("{}:{}: Unknown memory area attribute '{}'", filename, lino, key))
+		}
+	}
+
+	let start = start.ok_or_else(|| format!("{}:{}: Memory area {} has no start", filename, lino, name))?;
+	let size = size.ok_or_else(|| format!("{}:{}: Memory area {} has no size", filename, lino, name))?;
+	if size == 0 || start as u32 + size > 0x10000 {
+		return Err(format!("{}:{}: Memory area {} does not fit in the address space", filename, lino, name));
+	}
+
+	Ok(MemoryArea {
+		name,
+		start,
+		size,
+		fill: if fill { Some(fillval) } else { None }
+	})
+}
+
+// Parses the attributes of a segment
+fn parse_rule(filename: &str, lino: u32, name: String, attrs: Vec<(String, Token)>, config: &LinkerConfig) -> Result<SegmentRule, String> {
+	if config.segments.iter().any(|v| v.name == name) {
+		return Err(format!("{}:{}: Segment {} is listed twice", filename, lino, name));
+	}
+
+	let mut area = None;
+	let mut align = 1u16;
+	let mut start = None;
+
+	for (key, value) in attrs.iter() {
+		match key.as_str() {
+			"load" => area = match value {
+				Token::Word(w) => match config.areas.iter().position(|v| v.name == *w) {
+					Some(i) => Some(i),
+					None => return Err(format!("{}:{}: Unknown memory area {}", filename, lino, w))
+				},
+				_ => return Err(format!("{}:{}: load must be the name of a memory area", filename, lino))
+			},
+			"align" => align = match number(filename, lino, key, value, 0x8000)? as u16 {
+				n if n.is_power_of_two() => n,
+				_ => return Err(format!("{}:{}: align must be a power of two", filename, lino))
+			},
+			"start" => start = Some(number(filename, lino, key, value, 0xFFFF)? as u16),
+			_ => return Err(format!("{}:{}: Unknown segment attribute '{}'", filename, lino, key))
+		}
+	}
+
+	match area {
+		Some(area) => Ok(SegmentRule { name, area, align, start }),
+		None => Err(format!("{}:{}: Segment {} has no load area", filename, lino, name))
+	}
+}
+
+// Assigns addresses to the named segments of every file, relocates them, and resolves imports, returning the usage of each memory area
+pub fn link(config: &LinkerConfig, files: &mut [FirstPassResult]) -> Result<Vec<AreaUsage>, String> {
+	let usage = place(config, files)?;
+	if let Some((name, filename, lino)) = resolve_imports(files)?.first() {
+		return Err(format!("Undefined symbol '{}' imported at {}:{}", name, filename, lino));
+	}
+	Ok(usage)
+}
+
+// Assigns addresses to the named segments of every file and relocates them, returning the usage of each memory area
+pub fn place(config: &LinkerConfig, files: &mut [FirstPassResult]) -> Result<Vec<AreaUsage>, String> {
+	// Every named segment must be placed
+	for file in files.iter() {
+		for segment in file.segments.iter() {
+			if !config.segments.iter().any(|v| v.name == segment.name) {
+				return Err(format!("Segment {} in {} is not placed by the linker config", segment.name, file.filename));
+			}
+		}
+	}
+
+	let mut bases: Vec<Vec<u16>> = files.iter().map(|v| vec![0; v.segments.len()]).collect();
+	let mut usage = Vec::new();
+
+	for (a, area) in config.areas.iter().enumerate() {
+		let end = area.start as u32 + area.size;
+		let mut addr = area.start as u32;
+		let mut used = 0;
+
+		for rule in config.segments.iter().filter(|v| v.area == a) {
+			// Fixed start or alignment
+			if let Some(start) = rule.start {
+				if (start as u32) < addr || start as u32 >= end {
+					return Err(format!("Segment {} cannot start at ${:04X} in memory area {} (next free address is ${:04X})", rule.name, start, area.name, addr));
+				}
+				addr = start as u32;
+			}
+			let align = rule.align as u32;
+			addr = addr.div_ceil(align) * align;
+
+			// Place the part from each file
+			for (f, file) in files.iter().enumerate() {
+				if let Some(i) = file.segments.iter().position(|v| v.name == rule.name) {
+					let align = file.segments[i].align.max(1) as u32;
+					addr = addr.div_ceil(align) * align;
+					bases[f][i] = addr as u16;
+					addr += file.segments[i].size as u32;
+					used += file.segments[i].size as u32;
+				}
+			}
+
+			if addr > end {
+				return Err(format!("Segment {} overflows memory area {} by {} bytes", rule.name, area.name, addr - end));
+			}
+		}
+
+		usage.push(AreaUsage {
+			name: area.name.clone(),
+			start: area.start,
+			size: area.size,
+			used
+		});
+	}
+
+	for (file, bases) in files.iter_mut().zip(bases.iter()) {
+		file.relocate(bases);
+	}
+	Ok(usage)
+}
+
+// Adds the library members that export a symbol imported by the files, repeating until no more members are needed
+pub fn add_library_members(files: &mut Vec<FirstPassResult>, libraries: Vec<Vec<FirstPassResult>>) {
+	let mut members: Vec<FirstPassResult> = libraries.into_iter().flatten().collect();
+	loop {
+		let exported: HashSet<&String> = files.iter().flat_map(|v| v.exports.iter()).collect();
+		let undefined: HashSet<&String> = files.iter()
+			.flat_map(|v| v.symbol_defs.iter())
+			.filter(|(name, def)| def.kind == SymbolKind::Import && !exported.contains(name))
+			.map(|v| v.0)
+			.collect();
+
+		// Members are searched in the order the libraries were given
+		match members.iter().position(|m| m.exports.iter().any(|v| undefined.contains(v))) {
+			Some(i) => {
+				let member = members.remove(i);
+				files.push(member);
+			}
+			None => break
+		}
+	}
+}
+
+// Gives the imported symbols of every file the values exported by the other files, returning the undefined imports as (name, file, line)
+pub fn resolve_imports(files: &mut [FirstPassResult]) -> Result<Vec<(String, String, u32)>, String> {
+	// Collect exports
+	let mut exported: HashMap<&String, (&String, u16)> = HashMap::new();
+	for file in files.iter() {
+		for name in file.exports.iter() {
+			if let Some((other, _)) = exported.get(name) {
+				return Err(format!("Symbol '{}' is exported by both {} and {}", name, other, file.filename));
+			}
+			exported.insert(name, (&file.filename, file.symbol_table[name]));
+		}
+	}
+
+	// Resolve imports, sorted by line
+	let mut resolved: Vec<Vec<(String, u16)>> = Vec::new();
+	let mut undefined = Vec::new();
+	for file in files.iter() {
+		let mut imports: Vec<(&String, u32)> = file.symbol_defs.iter()
+			.filter(|(_, def)| def.kind == SymbolKind::Import)
+			.map(|(name, def)| (name, def.lino))
+			.collect();
+		imports.sort_by_key(|v| (v.1, v.0));
+
+		let mut values = Vec::new();
+		for (name, lino) in imports {
+			match exported.get(name) {
+				Some((_, value)) => values.push((name.clone(), *value)),
+				None => undefined.push((name.clone(), file.filename.clone(), lino))
+			}
+		}
+		resolved.push(values);
+	}
+
+	for (file, values) in files.iter_mut().zip(resolved) {
+		file.symbol_table.extend(values);
+	}
+	Ok(undefined)
+}
+
+// Fills the unwritten bytes of memory areas with fill set
+pub fn fill_areas(config: &LinkerConfig, result: &mut AssemblerResult) {
+	for area in config.areas.iter() {
+		if let Some(value) = area.fill {
+			result.fill(area.start, area.end(), value, &area.name);
+		}
+	}
+}
+
+// Formats the usage of every memory area
+pub fn format_usage(usage: &[AreaUsage]) -> String {
+	let width = usage.iter().map(|v| v.name.len()).max().unwrap_or(0);
+	let mut out = String::new();
+	for area in usage {
+		out.push_str(&format!("{:width$}  ${:04X}-${:04X}  {:5} of {:5} bytes used ({}%)\n",
+			area.name, area.start, area.start as u32 + area.size - 1, area.used, area.size, area.used * 100 / area.size, width = width));
+	}
+	out
+}
@@ -0,0 +1,129 @@
+//
+// src
+// listing.rs: Generates assembly listings with addresses, bytes, and source side by side.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::pass_1::{
+	Bank,
+	FirstPassResult,
+	SymbolKind
+};
+use crate::pass_2::{AssemblerResult, CycleReport};
+
+// The number of bytes shown on a single row of the listing
+const BYTES_PER_ROW: usize = 8;
+
+// Collects the bytes emitted for each line number, keyed by line number
+fn bytes_by_line(first_pass: &FirstPassResult, results: &[(Bank, &AssemblerResult)]) -> HashMap<u32, (u16, Vec<u8>)> {
+	let mut map: HashMap<u32, (u16, Vec<u8>)> = HashMap::new();
+	let images: Vec<(Bank, Vec<u8>)> = results.iter().map(|v| (v.0, v.1.image())).collect();
+
+	for line in first_pass.lines.iter() {
+		// Get the memory image of the bank the line is in
+		let image = match images.iter().find(|v| v.0 == line.bank) {
+			Some(v) => &v.1,
+			None => &images[0].1
+		};
+
+		let entry = map.entry(line.lino).or_insert_with(|| (line.addr, Vec::new()));
+		for i in 0..line.size() {
+			entry.1.push(image[line.addr.wrapping_add(i) as usize]);
+		}
+	}
+
+	map
+}
+
+// Appends the lines of a file to the listing
+fn list_file(out: &mut String, first_pass: &FirstPassResult, results: &[(Bank, &AssemblerResult)], marker: Option<&str>) {
+	let bytes = if results.is_empty() {
+		HashMap::new()
+	} else {
+		bytes_by_line(first_pass, results)
+	};
+	let cycles: HashMap<u32, String> = results.iter()
+		.flat_map(|v| v.1.cycles.iter())
+		.filter(|v| v.filename == first_pass.filename)
+		.map(|v| (v.lino, v.cycles.to_string()))
+		.collect();
+
+	for (i, source) in first_pass.source.lines().enumerate() {
+		let lino = i as u32 + 1;
+		let source = match marker {
+			Some(name) => format!("[{}] {}", name, source),
+			None => String::from(source)
+		};
+
+		// Lines with emitted code
+		if let Some((addr, bytes)) = bytes.get(&lino) {
+			for (row, chunk) in bytes.chunks(BYTES_PER_ROW).enumerate() {
+				let hex = chunk.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ");
+				let addr = addr.wrapping_add((row * BYTES_PER_ROW) as u16);
+				if row == 0 {
+					let cycles = cycles.get(&lino).map(|v| v.as_str()).unwrap_or("");
+					let _ = writeln!(out, "{:>5}  {:04X}  {:<24}  {:<5}  {}", lino, addr, hex, cycles, source);
+				} else {
+					let _ = writeln!(out, "{:>5}  {:04X}  {}", "", addr, hex);
+				}
+			}
+
+		// Lines without any code
+		} else {
+			let row = format!("{:>5}  {:4}  {:<24}  {:<5}  {}", lino, "", "", "", source);
+			let _ = writeln!(out, "{}", row.trim_end());
+		}
+
+		// List the contents of included files after the .include line
+		for include in first_pass.includes.iter().filter(|v| v.lino == lino) {
+			list_file(out, &include.result, &[], Some(&include.result.filename));
+		}
+	}
+}
+
+// Generates a listing for a file that has gone through both passes
+pub fn generate_listing(first_pass: &FirstPassResult, result: &AssemblerResult) -> String {
+	generate_banked_listing(first_pass, &[(Bank::None, result)])
+}
+
+// Generates a listing for a file whose banks went through the second pass separately
+pub fn generate_banked_listing(first_pass: &FirstPassResult, results: &[(Bank, &AssemblerResult)]) -> String {
+	let mut out = String::new();
+	let _ = writeln!(out, "; {}", first_pass.filename);
+	let _ = writeln!(out, "{:>5}  {:4}  {:<24}  {:<5}  Source", "Line", "Addr", "Code", "Cyc");
+	list_file(&mut out, first_pass, results, None);
+
+	// Cycle regions
+	let reports: Vec<&CycleReport> = results.iter().flat_map(|v| v.1.cycle_reports.iter()).collect();
+	if !reports.is_empty() {
+		let _ = writeln!(out);
+		let _ = writeln!(out, "; Cycles");
+		for report in reports {
+			let _ = writeln!(out, "{}", report);
+		}
+	}
+
+	// Symbol table sorted by name
+	let mut symbols: Vec<(&String, &u16)> = first_pass.symbol_table.iter().collect();
+	symbols.sort();
+
+	let _ = writeln!(out);
+	let _ = writeln!(out, "; Symbols");
+	for (name, value) in symbols {
+		let def = &first_pass.symbol_defs[name];
+		let kind = match def.kind {
+			SymbolKind::Label => "label",
+			SymbolKind::Constant => "define",
+			SymbolKind::Variable => "variable",
+			SymbolKind::Import => "import"
+		};
+		let _ = writeln!(out, "{:<24}  ${:04X}  {:<8}  {}:{}", name, value, kind, def.filename, def.lino);
+	}
+
+	out
+}
@@ -0,0 +1,686 @@
+//
+// src
+// lsp.rs: A language server speaking JSON-RPC over stdin and stdout.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{
+	self,
+	BufRead,
+	Write
+};
+use std::path::Path;
+
+use crate::disasm::{
+	self,
+	OperandKind
+};
+use crate::files::{
+	FileProvider,
+	FileSystem
+};
+use crate::json::{
+	self,
+	JsonValue
+};
+use crate::lexer::{
+	Lexer,
+	TokenValue
+};
+use crate::parser::{
+	self,
+	ParseError
+};
+use crate::pass_1::{
+	self,
+	DiagnosticKind,
+	FirstPassOptions,
+	FirstPassResult,
+	SymbolKind
+};
+use crate::pass_2;
+
+// Diagnostic severities
+const SEVERITY_ERROR: i64 = 1;
+const SEVERITY_WARNING: i64 = 2;
+const SEVERITY_INFORMATION: i64 = 3;
+
+// Completion item kinds
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_KEYWORD: i64 = 14;
+const COMPLETION_CONSTANT: i64 = 21;
+
+// The error code for requests the server doesn't handle
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// Converts a file URI to a path
+pub fn uri_to_path(uri: &str) -> String {
+	let path = uri.strip_prefix("file://").unwrap_or(uri).as_bytes();
+
+	// Decode percent escapes
+	let mut bytes = Vec::new();
+	let mut i = 0;
+	while i < path.len() {
+		if path[i] == b'%' && i + 2 < path.len() {
+			if let Some(n) = std::str::from_utf8(&path[i + 1..i + 3]).ok().and_then(|v| u8::from_str_radix(v, 16).ok()) {
+				bytes.push(n);
+				i += 3;
+				continue;
+			}
+		}
+		bytes.push(path[i]);
+		i += 1;
+	}
+	String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// Converts a path to a file URI, relative paths being relative to the current directory
+pub fn path_to_uri(path: &str) -> String {
+	let path = match env::current_dir() {
+		Ok(dir) if Path::new(path).is_relative() => dir.join(path).to_string_lossy().into_owned(),
+		_ => String::from(path)
+	};
+
+	let mut uri = String::from("file://");
+	for b in path.bytes() {
+		if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+			uri.push(b as char);
+		} else {
+			uri.push_str(&format!("%{:02X}", b));
+		}
+	}
+	uri
+}
+
+// Returns the text of a line, without its newline
+fn line_text(source: &str, line: u32) -> &str {
+	source.split('\n').nth(line as usize).unwrap_or("").trim_end_matches('\r')
+}
+
+// Converts a column counted in characters to UTF-16 code units, which LSP positions count in
+fn to_utf16(source: &str, line: u32, col: u32) -> u32 {
+	line_text(source, line).chars().take(col as usize).map(|c| c.len_utf16() as u32).sum()
+}
+
+// Converts a column counted in UTF-16 code units to characters
+fn from_utf16(source: &str, line: u32, col: u32) -> u32 {
+	let mut units = 0;
+	let mut chars = 0;
+	for c in line_text(source, line).chars() {
+		if units >= col {
+			break;
+		}
+		units += c.len_utf16() as u32;
+		chars += 1;
+	}
+	chars
+}
+
+// Returns the length of a line in UTF-16 code units
+fn line_len(source: &str, line: u32) -> u32 {
+	line_text(source, line).encode_utf16().count() as u32
+}
+
+// Returns the symbol under a position and the column it starts at, both in UTF-16 code units
+fn word_at(source: &str, line: u32, col: u32) -> Option<(String, u32)> {
+	let chars: Vec<char> = line_text(source, line).chars().collect();
+	let is_word = |c: &char| c.is_ascii_alphanumeric() || *c == '_';
+
+	let mut start = (from_utf16(source, line, col) as usize).min(chars.len());
+	while start > 0 && is_word(&chars[start - 1]) {
+		start -= 1;
+	}
+	let mut end = start;
+	while end < chars.len() && is_word(&chars[end]) {
+		end += 1;
+	}
+
+	if start == end || chars[start].is_ascii_digit() {
+		None
+	} else {
+		Some((chars[start..end].iter().collect(), to_utf16(source, line, start as u32)))
+	}
+}
+
+// Returns the (line, column) of every use of a symbol in a source, skipping pragma names; columns are in UTF-16 code units
+fn symbol_uses(path: &str, source: &str, name: &str) -> Vec<(u32, u32)> {
+	let mut uses = Vec::new();
+	let mut after_dot = false;
+	for token in Lexer::new(path, source) {
+		if let TokenValue::Symbol(s) = &token.value {
+			if s == name && !after_dot {
+				let line_start = source[..token.pos].rfind('\n').map(|v| v + 1).unwrap_or(0);
+				uses.push((token.lino - 1, source[line_start..token.pos].encode_utf16().count() as u32));
+			}
+		}
+		after_dot = token.value == TokenValue::Dot;
+	}
+	uses
+}
+
+// Creates an LSP range on a single line
+fn range(line: u32, start: u32, end: u32) -> JsonValue {
+	let position = |col: u32| JsonValue::object(vec![
+		("line", JsonValue::Number(line as i64)),
+		("character", JsonValue::Number(col as i64))
+	]);
+	JsonValue::object(vec![
+		("start", position(start)),
+		("end", position(end))
+	])
+}
+
+// Creates an LSP location
+fn location(path: &str, line: u32, start: u32, end: u32) -> JsonValue {
+	JsonValue::object(vec![
+		("uri", JsonValue::String(path_to_uri(path))),
+		("range", range(line, start, end))
+	])
+}
+
+// Returns how an addressing mode is written
+fn mode_syntax(kind: OperandKind) -> &'static str {
+	match kind {
+		OperandKind::Implicit => "",
+		OperandKind::Immediate => "#imm",
+		OperandKind::ZeroPage => "zp",
+		OperandKind::ZeroPageX => "zp, x",
+		OperandKind::ZeroPageY => "zp, y",
+		OperandKind::Absolute => "abs",
+		OperandKind::AbsoluteX => "abs, x",
+		OperandKind::AbsoluteY => "abs, y",
+		OperandKind::IndirectX => "(zp, x)",
+		OperandKind::IndirectY => "(zp), y",
+		OperandKind::Indirect => "(abs)",
+		OperandKind::Relative => "label"
+	}
+}
+
+// Describes the addressing modes and timing of a mnemonic as markdown
+fn describe_mnemonic(mnemonic: &str) -> String {
+	let mut out = format!("**{}**\n\n| Operand | Opcode | Bytes | Cycles |\n|---|---|---|---|\n", mnemonic);
+	for (opcode, info) in disasm::opcodes().iter().enumerate() {
+		if let Some(info) = info.as_ref().filter(|v| v.mnemonic == mnemonic) {
+			let extra = if info.page_penalty || info.kind == OperandKind::Relative { "+" } else { "" };
+			out.push_str(&format!("| `{} {}` | ${:02X} | {} | {}{} |\n", mnemonic, mode_syntax(info.kind), opcode, info.size, info.cycles, extra));
+		}
+	}
+	out.push_str("\n+ takes one more cycle if indexing crosses a page; branches take one more if taken and another if the target is on another page");
+	out
+}
+
+// Describes a symbol as markdown
+fn describe_symbol(result: &FirstPassResult, name: &str) -> Option<String> {
+	let def = result.symbol_defs.get(name)?;
+	let kind = match def.kind {
+		SymbolKind::Label => "label",
+		SymbolKind::Constant => "define",
+		SymbolKind::Variable => "variable",
+		SymbolKind::Import => "import"
+	};
+	Some(match result.symbol_table.get(name) {
+		Some(value) => format!("`{}` = ${:04X} ({}, {}:{})", name, value, kind, def.filename, def.lino),
+		None => format!("`{}` ({}, {}:{})", name, kind, def.filename, def.lino)
+	})
+}
+
+// Collects the sources of a file's includes, recursively
+fn include_sources<'a>(result: &'a FirstPassResult, out: &mut Vec<(&'a str, &'a str)>) {
+	for include in result.includes.iter() {
+		out.push((&include.result.filename, &include.result.source));
+		include_sources(&include.result, out);
+	}
+}
+
+// Open documents, falling back to the files on disk, so included files are read with their unsaved changes
+struct Documents<'a>(&'a HashMap<String, String>);
+
+impl FileProvider for Documents<'_> {
+	fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		match self.0.get(path) {
+			Some(text) => Ok(text.clone().into_bytes()),
+			None => FileSystem.read(path)
+		}
+	}
+
+	fn exists(&self, path: &str) -> bool {
+		self.0.contains_key(path) || FileSystem.exists(path)
+	}
+}
+
+// Represents the state of the language server
+pub struct Server {
+	// The text of every open document, keyed by path
+	documents: HashMap<String, String>,
+
+	// The last first pass of every open document that succeeded
+	results: HashMap<String, FirstPassResult>,
+
+	shutdown: bool,
+	exited: bool
+}
+
+impl Default for Server {
+	fn default() -> Self {
+		Server::new()
+	}
+}
+
+impl Server {
+	// Creates a server with no open documents
+	pub fn new() -> Server {
+		Server {
+			documents: HashMap::new(),
+			results: HashMap::new(),
+			shutdown: false,
+			exited: false
+		}
+	}
+
+	// Returns whether the client sent exit
+	pub fn exited(&self) -> bool {
+		self.exited
+	}
+
+	// Returns the code the server should exit with
+	pub fn exit_code(&self) -> i32 {
+		if self.shutdown { 0 } else { 1 }
+	}
+
+	// Handles a message, returning the messages to send back
+	// Requests: initialize, shutdown, textDocument/definition, textDocument/references, textDocument/hover, textDocument/completion
+	// Notifications: initialized, exit, textDocument/didOpen, textDocument/didChange (full text), textDocument/didClose
+	pub fn handle(&mut self, message: &JsonValue) -> Vec<JsonValue> {
+		let null = JsonValue::Null;
+		let method = message.get("method").and_then(|v| v.as_str()).unwrap_or("");
+		let params = message.get("params").unwrap_or(&null);
+
+		// Requests
+		if let Some(id) = message.get("id") {
+			let result = match method {
+				"initialize" => JsonValue::object(vec![
+					("capabilities", JsonValue::object(vec![
+						("positionEncoding", JsonValue::string("utf-16")),
+						("textDocumentSync", JsonValue::Number(1)),
+						("definitionProvider", JsonValue::Bool(true)),
+						("referencesProvider", JsonValue::Bool(true)),
+						("hoverProvider", JsonValue::Bool(true)),
+						("completionProvider", JsonValue::object(vec![
+							("triggerCharacters", JsonValue::Array(vec![JsonValue::string(".")]))
+						]))
+					])),
+					("serverInfo", JsonValue::object(vec![
+						("name", JsonValue::string("asm6502"))
+					]))
+				]),
+
+				"shutdown" => {
+					self.shutdown = true;
+					JsonValue::Null
+				}
+
+				"textDocument/definition" => self.definition(params),
+				"textDocument/references" => self.references(params),
+				"textDocument/hover" => self.hover(params),
+				"textDocument/completion" => self.completion(params),
+
+				_ => return vec![JsonValue::object(vec![
+					("jsonrpc", JsonValue::string("2.0")),
+					("id", id.clone()),
+					("error", JsonValue::object(vec![
+						("code", JsonValue::Number(METHOD_NOT_FOUND)),
+						("message", JsonValue::String(format!("Unknown method '{}'", method)))
+					]))
+				])]
+			};
+
+			return vec![JsonValue::object(vec![
+				("jsonrpc", JsonValue::string("2.0")),
+				("id", id.clone()),
+				("result", result)
+			])];
+		}
+
+		// Notifications
+		let document = params.get("textDocument");
+		let path = document.and_then(|v| v.get("uri")).and_then(|v| v.as_str()).map(uri_to_path);
+		match (method, path) {
+			("exit", _) => self.exited = true,
+
+			("textDocument/didOpen", Some(path)) => {
+				let text = document.and_then(|v| v.get("text")).and_then(|v| v.as_str()).unwrap_or("");
+				self.documents.insert(path.clone(), String::from(text));
+				return vec![self.diagnose(&path)];
+			}
+
+			("textDocument/didChange", Some(path)) => {
+				// Only full text changes are supported, so the last one is the whole document
+				if let Some(JsonValue::Array(changes)) = params.get("contentChanges") {
+					if let Some(text) = changes.last().and_then(|v| v.get("text")).and_then(|v| v.as_str()) {
+						self.documents.insert(path.clone(), String::from(text));
+						return vec![self.diagnose(&path)];
+					}
+				}
+			}
+
+			("textDocument/didClose", Some(path)) => {
+				self.documents.remove(&path);
+				self.results.remove(&path);
+				return vec![publish_diagnostics(&path, Vec::new())];
+			}
+
+			_ => {}
+		}
+		Vec::new()
+	}
+
+	// Assembles a document and returns its diagnostics
+	fn diagnose(&mut self, path: &str) -> JsonValue {
+		let source = &self.documents[path];
+		let mut diagnostics = Vec::new();
+		let mut error = |e: ParseError| {
+			// Errors in included files are shown at the top of the document
+			if e.filename == path {
+				let line = e.lino.saturating_sub(1);
+				diagnostics.push(diagnostic(range(line, 0, line_len(source, line)), SEVERITY_ERROR, &e.message));
+			} else {
+				diagnostics.push(diagnostic(range(0, 0, 0), SEVERITY_ERROR, &format!("{}:{}: {}", e.filename, e.lino, e.message)));
+			}
+		};
+
+		// Includes are also looked for next to the document, since the server isn't necessarily run from its directory
+		let options = FirstPassOptions {
+			defines: Vec::new(),
+			include_paths: Path::new(path).parent().map(|v| v.to_string_lossy().into_owned()).into_iter().collect()
+		};
+		let mut lexer = Lexer::new(path, source);
+		match pass_1::first_pass_with_options(&mut lexer, &options, &Documents(&self.documents)) {
+			Ok(result) => {
+				// Imported symbols are only defined once files are linked, so files that import can't go through the second pass on their own
+				if !result.symbol_defs.values().any(|v| v.kind == SymbolKind::Import) {
+					if let Err(e) = pass_2::second_pass(&result) {
+						error(e);
+					}
+				}
+
+				for message in result.diagnostics.iter().filter(|v| v.filename == path) {
+					let line = message.lino.saturating_sub(1);
+					let severity = match message.kind {
+						DiagnosticKind::Warning => SEVERITY_WARNING,
+						DiagnosticKind::Print => SEVERITY_INFORMATION
+					};
+					diagnostics.push(diagnostic(range(line, 0, line_len(source, line)), severity, &message.message));
+				}
+				self.results.insert(String::from(path), result);
+			}
+
+			Err(e) => error(e)
+		}
+
+		publish_diagnostics(path, diagnostics)
+	}
+
+	// Returns the path and the name of the symbol at the position in a request
+	fn symbol_at(&self, params: &JsonValue) -> Option<(String, String)> {
+		let path = uri_to_path(params.get("textDocument")?.get("uri")?.as_str()?);
+		let position = params.get("position")?;
+		let line = position.get("line")?.as_i64()? as u32;
+		let col = position.get("character")?.as_i64()? as u32;
+		let (name, _) = word_at(self.documents.get(&path)?, line, col)?;
+		Some((path, name))
+	}
+
+	// Returns the source of a file, whether it is open, included by an open file, or only on disk
+	fn source_of(&self, path: &str) -> Option<String> {
+		if let Some(source) = self.documents.get(path) {
+			return Some(source.clone());
+		}
+
+		let mut sources = Vec::new();
+		for result in self.results.values() {
+			include_sources(result, &mut sources);
+		}
+		match sources.iter().find(|v| v.0 == path) {
+			Some(v) => Some(String::from(v.1)),
+			None => fs::read_to_string(path).ok()
+		}
+	}
+
+	// Returns every file that may use a symbol: open documents and the files they include
+	fn searched_sources(&self) -> Vec<(String, String)> {
+		let mut sources: Vec<(&str, &str)> = self.documents.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+		for result in self.results.values() {
+			include_sources(result, &mut sources);
+		}
+
+		let mut seen: Vec<(String, String)> = Vec::new();
+		for (path, source) in sources {
+			if !seen.iter().any(|v| v.0 == path) {
+				seen.push((String::from(path), String::from(source)));
+			}
+		}
+		seen.sort();
+		seen
+	}
+
+	// Finds where a symbol is defined, looking in other open documents for imported symbols
+	fn find_definition(&self, path: &str, name: &str) -> Option<(String, u32)> {
+		let results = self.results.get(path).into_iter().chain(self.results.values());
+		for result in results {
+			match result.symbol_defs.get(name) {
+				Some(def) if def.kind != SymbolKind::Import => return Some((def.filename.clone(), def.lino)),
+				_ => {}
+			}
+		}
+		None
+	}
+
+	// Handles textDocument/definition
+	fn definition(&self, params: &JsonValue) -> JsonValue {
+		let (path, name) = match self.symbol_at(params) {
+			Some(v) => v,
+			None => return JsonValue::Null
+		};
+		let (def_path, lino) = match self.find_definition(&path, &name) {
+			Some(v) => v,
+			None => return JsonValue::Null
+		};
+
+		// Point at the name on the line it is defined on
+		let line = lino.saturating_sub(1);
+		let col = self.source_of(&def_path)
+			.and_then(|source| symbol_uses(&def_path, &source, &name).into_iter().find(|v| v.0 == line))
+			.map(|v| v.1)
+			.unwrap_or(0);
+		location(&def_path, line, col, col + name.chars().count() as u32)
+	}
+
+	// Handles textDocument/references
+	fn references(&self, params: &JsonValue) -> JsonValue {
+		let (path, name) = match self.symbol_at(params) {
+			Some(v) => v,
+			None => return JsonValue::Null
+		};
+		if !self.results.values().any(|v| v.symbol_defs.contains_key(&name)) {
+			return JsonValue::Array(Vec::new());
+		}
+
+		let include_declaration = params.get("context")
+			.and_then(|v| v.get("includeDeclaration"))
+			.map(|v| *v == JsonValue::Bool(true))
+			.unwrap_or(true);
+		let definition = self.find_definition(&path, &name).map(|v| (v.0, v.1.saturating_sub(1)));
+
+		let mut locations = Vec::new();
+		let len = name.chars().count() as u32;
+		for (file, source) in self.searched_sources() {
+			for (line, col) in symbol_uses(&file, &source, &name) {
+				if !include_declaration && definition.as_ref().map(|v| v.0 == file && v.1 == line).unwrap_or(false) {
+					continue;
+				}
+				locations.push(location(&file, line, col, col + len));
+			}
+		}
+		JsonValue::Array(locations)
+	}
+
+	// Handles textDocument/hover
+	fn hover(&self, params: &JsonValue) -> JsonValue {
+		let (path, name) = match self.symbol_at(params) {
+			Some(v) => v,
+			None => return JsonValue::Null
+		};
+
+		let text = match self.results.get(&path).and_then(|v| describe_symbol(v, &name)) {
+			Some(text) => text,
+			None if pass_1::MNEMONICS.contains(&name.to_lowercase().as_str()) => describe_mnemonic(&name.to_lowercase()),
+			None => return JsonValue::Null
+		};
+		JsonValue::object(vec![
+			("contents", JsonValue::object(vec![
+				("kind", JsonValue::string("markdown")),
+				("value", JsonValue::String(text))
+			]))
+		])
+	}
+
+	// Handles textDocument/completion
+	fn completion(&self, params: &JsonValue) -> JsonValue {
+		let item = |label: &str, kind: i64, detail: Option<String>| {
+			let mut pairs = vec![
+				("label", JsonValue::string(label)),
+				("kind", JsonValue::Number(kind))
+			];
+			if let Some(detail) = detail {
+				pairs.push(("detail", JsonValue::String(detail)));
+			}
+			JsonValue::object(pairs)
+		};
+
+		// Find what comes before the word being typed
+		let path = params.get("textDocument").and_then(|v| v.get("uri")).and_then(|v| v.as_str()).map(uri_to_path).unwrap_or_default();
+		let position = params.get("position");
+		let line = position.and_then(|v| v.get("line")).and_then(|v| v.as_i64()).unwrap_or(0) as u32;
+		let col = position.and_then(|v| v.get("character")).and_then(|v| v.as_i64()).unwrap_or(0) as u32;
+		let col = self.documents.get(&path).map(|v| from_utf16(v, line, col)).unwrap_or(0);
+		let text: Vec<char> = self.documents.get(&path).map(|v| line_text(v, line).chars().collect()).unwrap_or_default();
+		let mut start = (col as usize).min(text.len());
+		while start > 0 && (text[start - 1].is_ascii_alphanumeric() || text[start - 1] == '_') {
+			start -= 1;
+		}
+
+		// Pragmas follow a dot
+		if start > 0 && text[start - 1] == '.' {
+			return JsonValue::Array(parser::PRAGMAS.iter().map(|v| item(v, COMPLETION_KEYWORD, None)).collect());
+		}
+
+		let mut items: Vec<JsonValue> = pass_1::MNEMONICS.iter().map(|v| item(v, COMPLETION_KEYWORD, None)).collect();
+		if let Some(result) = self.results.get(&path) {
+			let mut names: Vec<&String> = result.symbol_defs.keys().collect();
+			names.sort();
+			for name in names {
+				let kind = match result.symbol_defs[name].kind {
+					SymbolKind::Constant => COMPLETION_CONSTANT,
+					_ => COMPLETION_VARIABLE
+				};
+				items.push(item(name, kind, result.symbol_table.get(name).map(|v| format!("${:04X}", v))));
+			}
+		}
+		JsonValue::Array(items)
+	}
+}
+
+// Creates a diagnostic
+fn diagnostic(range: JsonValue, severity: i64, message: &str) -> JsonValue {
+	JsonValue::object(vec![
+		("range", range),
+		("severity", JsonValue::Number(severity)),
+		("source", JsonValue::string("asm6502")),
+		("message", JsonValue::string(message))
+	])
+}
+
+// Creates a textDocument/publishDiagnostics notification
+fn publish_diagnostics(path: &str, diagnostics: Vec<JsonValue>) -> JsonValue {
+	JsonValue::object(vec![
+		("jsonrpc", JsonValue::string("2.0")),
+		("method", JsonValue::string("textDocument/publishDiagnostics")),
+		("params", JsonValue::object(vec![
+			("uri", JsonValue::String(path_to_uri(path))),
+			("diagnostics", JsonValue::Array(diagnostics))
+		]))
+	])
+}
+
+// Reads a message, returning None once the input ends
+pub fn read_message<R: BufRead>(input: &mut R) -> Option<Result<JsonValue, String>> {
+	// Read headers until an empty line
+	let mut length = None;
+	loop {
+		let mut header = String::new();
+		if input.read_line(&mut header).ok()? == 0 {
+			return None;
+		}
+		let header = header.trim_end();
+		if header.is_empty() {
+			break;
+		}
+		if let Some((key, value)) = header.split_once(':') {
+			if key.eq_ignore_ascii_case("Content-Length") {
+				length = value.trim().parse::<usize>().ok();
+			}
+		}
+	}
+
+	let length = match length {
+		Some(n) => n,
+		None => return Some(Err(String::from("Message has no Content-Length header")))
+	};
+	let mut body = vec![0u8; length];
+	input.read_exact(&mut body).ok()?;
+	Some(match String::from_utf8(body) {
+		Ok(body) => json::parse(&body),
+		Err(_) => Err(String::from("Message is not valid UTF-8"))
+	})
+}
+
+// Writes a message with its header
+pub fn write_message<W: Write>(output: &mut W, message: &JsonValue) -> io::Result<()> {
+	let body = message.to_string();
+	write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+	output.flush()
+}
+
+// Serves requests on stdin and stdout until the client exits, returning the exit code
+pub fn run() -> i32 {
+	let stdin = io::stdin();
+	let mut input = stdin.lock();
+	let stdout = io::stdout();
+	let mut output = stdout.lock();
+	let mut server = Server::new();
+
+	while let Some(message) = read_message(&mut input) {
+		let message = match message {
+			Ok(v) => v,
+			Err(e) => {
+				eprintln!("Error: {}", e);
+				continue;
+			}
+		};
+
+		for reply in server.handle(&message) {
+			if write_message(&mut output, &reply).is_err() {
+				return 1;
+			}
+		}
+		if server.exited() {
+			break;
+		}
+	}
+	server.exit_code()
+}
@@ -1,48 +1,605 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::io::ErrorKind;
+use std::path::Path;
 use std::process;
 
+use asm6502::archive;
+use asm6502::assembler;
+use asm6502::assembler::AssemblerError;
+use asm6502::archive::ArchiveMember;
+use asm6502::debug_info;
+use asm6502::disasm;
+use asm6502::files::FileProvider;
+use asm6502::files::FileSystem;
 use asm6502::lexer::Lexer;
+use asm6502::linker;
+use asm6502::linker::LinkerConfig;
+use asm6502::formatter;
+use asm6502::formatter::FormatOptions;
+use asm6502::formatter::MnemonicCase;
+use asm6502::listing;
+use asm6502::lsp;
+use asm6502::object;
+use asm6502::o65;
+use asm6502::output;
+use asm6502::output::OutputFormat;
+use asm6502::output::OutputOptions;
 use asm6502::pass_1;
-use asm6502::pass_2;
+use asm6502::pass_1::Bank;
+use asm6502::pass_1::DiagnosticKind;
+use asm6502::pass_1::FirstPassOptions;
 use asm6502::pass_2::AssemblerResult;
+use asm6502::sim::Cpu;
+use asm6502::sim::StopReason;
+use asm6502::spec;
+use asm6502::symbols;
+use asm6502::symbols::SymbolFormat;
+
+// The number of cycles run simulates before stopping by default
+const DEFAULT_MAX_CYCLES: u64 = 10_000_000;
 
 struct Config {
 	files: Vec<String>,
-	out: String,
-	write_addr: bool,
+	compile: bool,
+	link: bool,
+	out: Option<String>,
+	listing: Option<String>,
+	symbols: Option<String>,
+	symbol_format: Option<SymbolFormat>,
+	debug_info: Option<String>,
+	linker_config: Option<String>,
+	library_paths: Vec<String>,
+	libraries: Vec<String>,
+	first_pass: FirstPassOptions,
+	format: OutputFormat,
+	record_length: u8,
+	entry: Option<u16>,
+	init: Option<u16>,
 	addr_start: Option<u16>,
-	addr_end: Option<u16>
+	addr_end: Option<u16>,
+	run: bool,
+	test_spec: Option<String>,
+	pc: Option<String>,
+	max_cycles: u64,
+	report_page_crossings: bool
+}
+
+// Reads a file, exiting on failure
+fn read_file(path: &str) -> Vec<u8> {
+	FileSystem.read(path).unwrap_or_else(|e| {
+		match e.kind() {
+			ErrorKind::NotFound => eprintln!("File {} not found", path),
+			ErrorKind::PermissionDenied => eprintln!("Insufficient permissions to read {}", path),
+			_ => eprintln!("Error occured whilst reading from {}: {}", path, e)
+		}
+		process::exit(1);
+	})
+}
+
+// Writes a file, exiting on failure
+fn write_file<C: AsRef<[u8]>>(path: &str, contents: C) {
+	fs::write(path, contents).unwrap_or_else(|e| {
+		match e.kind() {
+			ErrorKind::PermissionDenied => eprintln!("Insufficient permissions to write {}", path),
+			_ => eprintln!("Error occured whilst writing to {}: {}", path, e)
+		}
+		process::exit(1);
+	});
+}
+
+// Finds a library in the library search paths, exiting if it isn't found
+fn find_library(name: &str, paths: &[String]) -> String {
+	for dir in paths.iter().map(|v| v.as_str()).chain(std::iter::once(".")) {
+		for file in [format!("lib{}.a", name), format!("{}.a", name)].iter() {
+			let path = Path::new(dir).join(file);
+			if path.is_file() {
+				return path.to_string_lossy().into_owned();
+			}
+		}
+	}
+
+	eprintln!("Error: library {} not found", name);
+	process::exit(1);
+}
+
+// Creates, lists, or extracts the members of an archive
+fn run_lib(name: &str, args: &[String]) {
+	let usage = || {
+		eprintln!("usage: {} lib create archive [objects]", name);
+		eprintln!("       {} lib list archive", name);
+		eprintln!("       {} lib extract archive [members]", name);
+		process::exit(1);
+	};
+	if args.len() < 2 {
+		usage();
+	}
+	let path = &args[1];
+
+	match args[0].as_str() {
+		"create" => {
+			let mut members: Vec<ArchiveMember> = Vec::new();
+			for file in args[2..].iter() {
+				let bytes = read_file(file);
+				if !object::is_object(&bytes) {
+					eprintln!("Error: {} is not an object file", file);
+					process::exit(1);
+				}
+
+				// Members are named after the file without its directory
+				let name = Path::new(file).file_name().map(|v| v.to_string_lossy().into_owned()).unwrap_or_default();
+				if members.iter().any(|v| v.name == name) {
+					eprintln!("Error: {} is added to the archive twice", name);
+					process::exit(1);
+				}
+				members.push(ArchiveMember { name, bytes });
+			}
+			write_file(path, archive::write_archive(&members));
+		}
+
+		"list" | "extract" => {
+			let members = archive::read_archive(path, &read_file(path)).unwrap_or_else(|e| {
+				eprintln!("Error: {}", e);
+				process::exit(1);
+			});
+
+			// Check that the requested members exist
+			let wanted = &args[2..];
+			if let Some(v) = wanted.iter().find(|v| !members.iter().any(|m| m.name == **v)) {
+				eprintln!("Error: {} is not in {}", v, path);
+				process::exit(1);
+			}
+
+			for member in members.iter().filter(|v| wanted.is_empty() || wanted.contains(&v.name)) {
+				if args[0] == "extract" {
+					// Members are only ever written to the current directory
+					if member.name.is_empty() || member.name == "." || member.name == ".." || member.name.contains(['/', '\\']) {
+						eprintln!("Error: {} has a member with an unsafe name: {}", path, member.name);
+						process::exit(1);
+					}
+					write_file(&member.name, &member.bytes);
+					continue;
+				}
+
+				// List members with their exported symbols
+				match object::read_object(&member.name, &member.bytes) {
+					Ok(result) => println!("{}: {}", member.name, result.exports.join(" ")),
+					Err(e) => println!("{}: {}", member.name, e)
+				}
+			}
+		}
+
+		_ => usage()
+	}
+}
+
+// Disassembles a binary loaded at an address, printing source that assembles back to it
+fn run_disasm(name: &str, args: &[String]) {
+	let usage = || {
+		eprintln!("usage: {} disasm [--trace] [--entry addr] binary load_addr [symbols]", name);
+		process::exit(1);
+	};
+	let parse_hex = |v: &str, what: &str| match u16::from_str_radix(v.trim_start_matches('$'), 16) {
+		Ok(v) => v,
+		Err(_) => {
+			eprintln!("Error: {} must be a 16 bit hex number", what);
+			process::exit(1);
+		}
+	};
+
+	let mut trace = false;
+	let mut entries = Vec::new();
+	let mut files = Vec::new();
+	let mut iter = args.iter();
+	while let Some(arg) = iter.next() {
+		if arg == "--trace" {
+			trace = true;
+		} else if arg == "--entry" {
+			match iter.next() {
+				Some(entry) => entries.push(parse_hex(entry, "--entry")),
+				None => usage()
+			}
+			trace = true;
+		} else {
+			files.push(arg);
+		}
+	}
+	if files.len() < 2 || files.len() > 3 {
+		usage();
+	}
+
+	let bytes = read_file(files[0]);
+	let load = parse_hex(files[1], "the load address");
+	let symbols = match files.get(2) {
+		Some(path) => {
+			let src = String::from_utf8_lossy(&read_file(path)).into_owned();
+			symbols::read_symbols(path, &src).unwrap_or_else(|e| {
+				eprintln!("Error: {}", e);
+				process::exit(1);
+			})
+		}
+		None => Vec::new()
+	};
+
+	// Trace from the entry points and vectors, or the load address if there are none
+	if trace {
+		entries.extend(disasm::vectors(&bytes, load));
+		if entries.is_empty() {
+			entries.push(load);
+		}
+	}
+
+	let source = disasm::disassemble(&bytes, load, &symbols, &entries).unwrap_or_else(|e| {
+		eprintln!("Error: {}", e);
+		process::exit(1);
+	});
+	print!("{}", source);
+}
+
+fn run_fmt(name: &str, args: &[String]) {
+	let usage = || {
+		eprintln!("usage: {} fmt [--check] [--case lower|upper|preserve] [--label-column n] [--instruction-column n] [--operand-column n] [--comment-column n] [--spaces] files", name);
+		process::exit(1);
+	};
+
+	let mut options = FormatOptions::default();
+	let mut check = false;
+	let mut files = Vec::new();
+	let mut iter = args.iter();
+	while let Some(arg) = iter.next() {
+		let mut column = || match iter.next().map(|v| v.parse::<usize>()) {
+			Some(Ok(n)) => n,
+			_ => {
+				eprintln!("Error: {} must be followed by a number", arg);
+				process::exit(1);
+			}
+		};
+
+		if arg == "--check" {
+			check = true;
+		} else if arg == "--case" {
+			options.case = match iter.next().map(|v| v.as_str()) {
+				Some("lower") => MnemonicCase::Lower,
+				Some("upper") => MnemonicCase::Upper,
+				Some("preserve") => MnemonicCase::Preserve,
+				_ => {
+					eprintln!("Error: --case must be followed by lower, upper, or preserve");
+					process::exit(1);
+				}
+			};
+		} else if arg == "--label-column" {
+			options.label_column = column();
+		} else if arg == "--instruction-column" {
+			options.instruction_column = column();
+		} else if arg == "--operand-column" {
+			options.operand_column = column();
+		} else if arg == "--comment-column" {
+			options.comment_column = column();
+		} else if arg == "--spaces" {
+			options.tabs = false;
+		} else if arg.starts_with('-') {
+			usage();
+		} else {
+			files.push(arg);
+		}
+	}
+	if files.is_empty() {
+		usage();
+	}
+
+	// Rewrite every file, or only list the ones that would change
+	let mut unformatted = false;
+	for file in files {
+		let src = String::from_utf8(read_file(file)).unwrap_or_else(|_| {
+			eprintln!("File {} is not valid UTF-8", file);
+			process::exit(1);
+		});
+		let formatted = formatter::format_source(file, &src, &options).unwrap_or_else(|e| {
+			eprintln!("Error on {}:{}: {}", e.filename, e.lino, e.message);
+			process::exit(1);
+		});
+
+		if formatted == src {
+			continue;
+		}
+		if check {
+			println!("{} is not formatted", file);
+			unformatted = true;
+		} else {
+			write_file(file, formatted.into_bytes());
+		}
+	}
+
+	if unformatted {
+		process::exit(1);
+	}
 }
 
 fn main() {
 	let mut config = Config {
 		files: Vec::new(),
-		out: String::from("a.out"),
-		write_addr: true,
+		compile: false,
+		link: false,
+		out: None,
+		listing: None,
+		symbols: None,
+		symbol_format: None,
+		debug_info: None,
+		linker_config: None,
+		library_paths: Vec::new(),
+		libraries: Vec::new(),
+		first_pass: FirstPassOptions::default(),
+		format: OutputFormat::Prg,
+		record_length: output::DEFAULT_RECORD_LENGTH,
+		entry: None,
+		init: None,
 		addr_start: None,
-		addr_end: None
+		addr_end: None,
+		run: false,
+		test_spec: None,
+		pc: None,
+		max_cycles: DEFAULT_MAX_CYCLES,
+		report_page_crossings: false
 	};
 
 	// Set up config
 	let mut iter = env::args();
 	let name = iter.next().unwrap();
+	let mut iter = iter.peekable();
+
+	// Format sources
+	if iter.peek().map(|v| v == "fmt").unwrap_or(false) {
+		iter.next();
+		run_fmt(&name, &iter.collect::<Vec<String>>());
+		return;
+	}
+
+	// Serve editors over stdio
+	if iter.peek().map(|v| v == "lsp").unwrap_or(false) {
+		process::exit(lsp::run());
+	}
+
+	// Manage archives
+	if iter.peek().map(|v| v == "lib").unwrap_or(false) {
+		iter.next();
+		run_lib(&name, &iter.collect::<Vec<String>>());
+		return;
+	}
+
+	// Disassemble a binary
+	if iter.peek().map(|v| v == "disasm").unwrap_or(false) {
+		iter.next();
+		run_disasm(&name, &iter.collect::<Vec<String>>());
+		return;
+	}
+
+	// Link object files instead of assembling source files
+	if iter.peek().map(|v| v == "link").unwrap_or(false) {
+		iter.next();
+		config.link = true;
+
+	// Run the assembled code in the simulator instead of writing it
+	} else if iter.peek().map(|v| v == "run").unwrap_or(false) {
+		iter.next();
+		config.run = true;
+
+	// Run the tests in a test spec against the assembled code
+	} else if iter.peek().map(|v| v == "test").unwrap_or(false) {
+		iter.next();
+		match iter.next() {
+			Some(spec) => config.test_spec = Some(spec),
+			None => {
+				eprintln!("usage: {} test spec [options] [files]", name);
+				process::exit(1);
+			}
+		}
+		config.run = true;
+	}
+
 	while let Some(arg) = iter.next() {
 		// Output files
 		if arg == "-o" || arg == "--output" {
 			if let Some(out) = iter.next() {
-				config.out = out;
+				config.out = Some(out);
 			} else {
 				eprintln!("Error: -o must be followed by a file name");
 				process::exit(1);
 			}
 
+		// Library search path
+		} else if let Some(dir) = arg.strip_prefix("-L") {
+			if !dir.is_empty() {
+				config.library_paths.push(String::from(dir));
+			} else if let Some(dir) = iter.next() {
+				config.library_paths.push(dir);
+			} else {
+				eprintln!("Error: -L must be followed by a directory");
+				process::exit(1);
+			}
+
+		// Include search path
+		} else if let Some(dir) = arg.strip_prefix("-I") {
+			if !dir.is_empty() {
+				config.first_pass.include_paths.push(String::from(dir));
+			} else if let Some(dir) = iter.next() {
+				config.first_pass.include_paths.push(dir);
+			} else {
+				eprintln!("Error: -I must be followed by a directory");
+				process::exit(1);
+			}
+
+		// Predefined constant
+		} else if let Some(define) = arg.strip_prefix("-D") {
+			let define = if define.is_empty() { iter.next().unwrap_or_default() } else { String::from(define) };
+			let value = match define.split_once('=') {
+				Some((label, value)) if !label.is_empty() => u16::from_str_radix(value.trim_start_matches('$'), 16).ok().map(|v| (String::from(label), v)),
+				_ => None
+			};
+			match value {
+				Some(v) => config.first_pass.defines.push(v),
+				None => {
+					eprintln!("Error: -D must be followed by name=value, where value is a 16 bit hex number");
+					process::exit(1);
+				}
+			}
+
+		// Library (-l followed by a separate argument is the listing)
+		} else if arg.starts_with("-l") && arg.len() > 2 {
+			config.libraries.push(String::from(&arg[2..]));
+		} else if arg == "--library" {
+			if let Some(library) = iter.next() {
+				config.libraries.push(library);
+			} else {
+				eprintln!("Error: --library must be followed by a library name");
+				process::exit(1);
+			}
+
+		// Only write object files
+		} else if arg == "-c" || arg == "--compile" {
+			config.compile = true;
+
+		// Listing file
+		} else if arg == "-l" || arg == "--listing" {
+			if let Some(listing) = iter.next() {
+				config.listing = Some(listing);
+			} else {
+				eprintln!("Error: -l must be followed by a file name");
+				process::exit(1);
+			}
+
+		// Symbol file
+		} else if arg == "--symbols" {
+			if let Some(symbols) = iter.next() {
+				config.symbols = Some(symbols);
+			} else {
+				eprintln!("Error: --symbols must be followed by a file name");
+				process::exit(1);
+			}
+
+		// Symbol file format
+		} else if arg == "--symbol-format" {
+			if let Some(format) = iter.next() {
+				config.symbol_format = match SymbolFormat::from_name(&format) {
+					Some(f) => Some(f),
+					None => {
+						eprintln!("Error: --symbol-format must be followed by vice, mesen, fceux, plain, or json");
+						process::exit(1);
+					}
+				};
+			} else {
+				eprintln!("Error: --symbol-format must be followed by vice, mesen, fceux, plain, or json");
+				process::exit(1);
+			}
+
+		// Debug info file
+		} else if arg == "-g" || arg == "--debug-info" {
+			if let Some(debug_info) = iter.next() {
+				config.debug_info = Some(debug_info);
+			} else {
+				eprintln!("Error: -g must be followed by a file name");
+				process::exit(1);
+			}
+
+		// Linker config
+		} else if arg == "-C" || arg == "--config" {
+			if let Some(linker_config) = iter.next() {
+				config.linker_config = Some(linker_config);
+			} else {
+				eprintln!("Error: -C must be followed by a file name");
+				process::exit(1);
+			}
+
+		// Init address
+		} else if arg == "--init" {
+			if let Some(init) = iter.next() {
+				// Parse hex number
+				config.init = match u16::from_str_radix(&init, 16) {
+					Ok(a) => Some(a),
+					Err(_) => {
+						eprintln!("Error: --init must be followed by valid 16 bit hex number");
+						process::exit(1);
+					}
+				};
+			} else {
+				eprintln!("Error: --init must be followed by a 16 bit hex number");
+				process::exit(1);
+			}
+
 		// Full disc
 		} else if arg == "-d" || arg == "--disc" {
 			config.addr_start = Some(0);
 			config.addr_end = Some(u16::MAX);
-			config.write_addr = false;
+			config.format = OutputFormat::Bin;
+
+		// Output format
+		} else if arg == "-f" || arg == "--format" {
+			if let Some(format) = iter.next() {
+				config.format = match OutputFormat::from_name(&format) {
+					Some(f) => f,
+					None => {
+						eprintln!("Error: -f must be followed by bin, prg, ihex, srec, ines, d64, apple, prodos, xex, ssd, or o65");
+						process::exit(1);
+					}
+				};
+			} else {
+				eprintln!("Error: -f must be followed by bin, prg, ihex, srec, ines, d64, apple, prodos, xex, ssd, or o65");
+				process::exit(1);
+			}
+
+		// Hex record length
+		} else if arg == "--record-length" {
+			if let Some(length) = iter.next() {
+				config.record_length = match length.parse::<u8>() {
+					Ok(n) if n > 0 => n,
+					_ => {
+						eprintln!("Error: --record-length must be followed by a number from 1 to 255");
+						process::exit(1);
+					}
+				};
+			} else {
+				eprintln!("Error: --record-length must be followed by a number from 1 to 255");
+				process::exit(1);
+			}
+
+		// Where the simulator starts
+		} else if arg == "--pc" {
+			if let Some(pc) = iter.next() {
+				config.pc = Some(pc);
+			} else {
+				eprintln!("Error: --pc must be followed by a label or a 16 bit hex number");
+				process::exit(1);
+			}
+
+		// How long the simulator runs
+		} else if arg == "--max-cycles" {
+			config.max_cycles = match iter.next().map(|v| v.parse::<u64>()) {
+				Some(Ok(n)) => n,
+				_ => {
+					eprintln!("Error: --max-cycles must be followed by a number");
+					process::exit(1);
+				}
+			};
+
+		// List code that crosses pages
+		} else if arg == "--report-page-crossings" {
+			config.report_page_crossings = true;
+
+		// Entry point
+		} else if arg == "--entry" {
+			if let Some(entry) = iter.next() {
+				// Parse hex number
+				config.entry = match u16::from_str_radix(&entry, 16) {
+					Ok(a) => Some(a),
+					Err(_) => {
+						eprintln!("Error: --entry must be followed by valid 16 bit hex number");
+						process::exit(1);
+					}
+				};
+			} else {
+				eprintln!("Error: --entry must be followed by a 16 bit hex number");
+				process::exit(1);
+			}
 
 		// Start address
 		} else if arg == "-s" || arg == "--start" {
@@ -84,94 +641,269 @@ fn main() {
 
 	// Check for files
 	if config.files.len() == 0 {
-		eprintln!("usage: {} [-o out] [-d] [-s addr] [-e addr] [files]", name);
+		eprintln!("usage: {} [link] [-c] [-o out] [-l listing] [--symbols file] [--symbol-format format] [-g debug] [--report-page-crossings] [-C config] [-I dir] [-D name=value] [-L dir] [-lname] [-f format] [--record-length n] [--entry addr] [--init addr] [-d] [-s addr] [-e addr] [files]", name);
+		eprintln!("       {} run [--pc label] [--max-cycles n] [options] [files]", name);
+		eprintln!("       {} test spec [--max-cycles n] [options] [files]", name);
+		eprintln!("       {} fmt [--check] [options] files", name);
+		eprintln!("       {} lsp", name);
+		eprintln!("       {} lib create|list|extract archive [files]", name);
+		eprintln!("       {} disasm [--trace] [--entry addr] binary load_addr [symbols]", name);
 		process::exit(1);
 	}
 
-	// The final result to be turned into a binary file
-	let mut final_result = AssemblerResult {
-		filename: String::from("total"),
-		start: u16::MAX,
-		end: 0,
-		bytes: [0u8; u16::MAX as usize + 1]
+	// Read the linker config
+	let linker_config = match &config.linker_config {
+		Some(path) => {
+			let content = fs::read_to_string(path).unwrap_or_else(|e| {
+				eprintln!("Error occured whilst reading from {}: {}", path, e);
+				process::exit(1);
+			});
+			linker::parse_config(path, &content).unwrap_or_else(|e| {
+				eprintln!("Error on {}", e);
+				process::exit(1);
+			})
+		}
+		None => LinkerConfig::default()
 	};
 
-	// Iterate over every file
+	// The listing of every file
+	let mut listing = String::new();
+
+	// The symbols of every file
+	let mut exported = Vec::new();
+
+	// The value of every label, for the simulator
+	let mut labels: HashMap<String, u16> = HashMap::new();
+
+	// Parse every file and generate ir
+	let mut first_passes = Vec::new();
+	let mut libraries = Vec::new();
 	for file in config.files {
 		// Read file
-		let content = fs::read_to_string(&file).unwrap_or_else(|e| {
-			match e.kind() {
-				ErrorKind::NotFound => eprintln!("File {} not found", &file),
-				ErrorKind::PermissionDenied => eprintln!("Insufficient permissions to read {}", &file),
-				_ => eprintln!("Error occured whilst reading from {}: {}", &file, e)
+		let content = read_file(&file);
+
+		// Archives given as input are searched like libraries
+		if config.link && archive::is_archive(&content) {
+			libraries.push(archive::read_objects(&file, &content).unwrap_or_else(|e| {
+				eprintln!("Error: {}", e);
+				process::exit(1);
+			}));
+			continue;
+
+		// o65 files are converted into the result of the first pass
+		} else if config.link && o65::is_o65(&content) {
+			first_passes.push(o65::read_o65(&file, &content).unwrap_or_else(|e| {
+				eprintln!("Error: {}", e);
+				process::exit(1);
+			}));
+			continue;
+
+		// Object files have already been through the first pass
+		} else if config.link {
+			let mut result = object::read_object(&file, &content).unwrap_or_else(|e| {
+				eprintln!("Error: {}", e);
+				process::exit(1);
+			});
+
+			// Object files don't store their source, so listings read it again if it still exists
+			if config.listing.is_some() {
+				result.source = FileSystem.read_to_string(&result.filename).unwrap_or_default();
 			}
+			first_passes.push(result);
+			continue;
+		}
+
+		let content = String::from_utf8(content).unwrap_or_else(|_| {
+			eprintln!("File {} is not valid UTF-8", &file);
 			process::exit(1);
 		});
 
 		// Parse file and generate ir
 		let mut lexer = Lexer::new(&file, &content);
-		let result = pass_1::first_pass(&mut lexer)
+		let result = pass_1::first_pass_with_options(&mut lexer, &config.first_pass, &FileSystem)
 			.unwrap_or_else(|e| {
 				eprintln!("Error on {}:{}: {}", e.filename, e.lino, e.message);
 				process::exit(1);
 			}
 		);
 
-		// Generate code
-		let result = pass_2::second_pass(result)
-			.unwrap_or_else(|e| {
-				eprintln!("Error on {}:{}: {}", e.filename, e.lino, e.message);
-				process::exit(1);
+		// Report .warning and .print messages
+		for diagnostic in result.diagnostics.iter() {
+			match diagnostic.kind {
+				DiagnosticKind::Warning => eprintln!("{}", diagnostic),
+				DiagnosticKind::Print => println!("{}", diagnostic)
 			}
-		);
+		}
 
-		// Merge code
-		final_result.merge(&result).unwrap_or_else(|e| {
-				eprintln!("Error: {}", e);
-				process::exit(1);
-			}
-		);
+		first_passes.push(result);
 	}
 
-	let out = config.out;
-	let start = match config.addr_start {
-		Some(v) => v,
-		None => final_result.start
-	};
-	let end = match config.addr_end {
-		Some(v) => v,
-		None => final_result.end
-	};
+	// Write object files to be linked later
+	if config.compile {
+		if config.out.is_some() && first_passes.len() > 1 {
+			eprintln!("Error: -o cannot be used with -c and several files");
+			process::exit(1);
+		}
+
+		for first_pass in first_passes.iter() {
+			let path = match &config.out {
+				Some(out) => out.clone(),
+				None => Path::new(&first_pass.filename).with_extension("o").to_string_lossy().into_owned()
+			};
+			write_file(&path, object::write_object(first_pass));
+		}
+		return;
+	}
+
+	// Add the library members that are needed
+	for library in config.libraries.iter() {
+		let path = find_library(library, &config.library_paths);
+		libraries.push(archive::read_objects(&path, &read_file(&path)).unwrap_or_else(|e| {
+			eprintln!("Error: {}", e);
+			process::exit(1);
+		}));
+	}
 
-	if end > start {
-		// Create contents of file
-		let mut contents: Vec<u8> = Vec::with_capacity((end - start) as usize + 3);
+	// Addresses of relocatable binaries aren't known until they're loaded, so there is nothing to list or map
+	if config.format == OutputFormat::O65 && (config.listing.is_some() || config.symbols.is_some() || config.debug_info.is_some() || config.linker_config.is_some()) {
+		eprintln!("Error: -l, --symbols, -g and -C cannot be used with -f o65");
+		process::exit(1);
+	}
 
-		// Write address if enabled
-		if config.write_addr {
-			contents.push(start as u8);
-			contents.push((start >> 8) as u8);
+	// Place named segments and generate code
+	let mut program = assembler::link_program(first_passes, libraries, &linker_config, config.format).unwrap_or_else(|e| {
+		match e {
+			AssemblerError::Parse(e) => eprintln!("Error on {}:{}: {}", e.filename, e.lino, e.message),
+			e => eprintln!("Error: {}", e)
 		}
+		process::exit(1);
+	});
+	if config.linker_config.is_some() {
+		print!("{}", linker::format_usage(&program.usage));
+	}
 
-		// Write contents
-		contents.extend(&final_result.bytes[start as usize..end as usize + 1]);
+	// Iterate over every file
+	for (first_pass, code) in program.files.iter().zip(program.code.iter()) {
+		for report in code.iter().flat_map(|v| v.1.cycle_reports.iter()) {
+			println!("{}", report);
+		}
+		if config.report_page_crossings {
+			for crossing in code.iter().flat_map(|v| v.1.page_crossings.iter()) {
+				println!("{}", crossing);
+			}
+		}
 
-		// Write code to file
-		fs::write(&out, contents).unwrap_or_else(|e| {
-			match e.kind() {
-				ErrorKind::PermissionDenied => eprintln!("Insufficient permissions to write {}", &out),
-				_ => eprintln!("Error occured whilst writing to {}: {}", &out, e)
+		// Generate listing
+		if config.listing.is_some() {
+			if !listing.is_empty() {
+				listing.push('\n');
 			}
+			if config.format == OutputFormat::Ines {
+				let results: Vec<(Bank, &AssemblerResult)> = code.iter().map(|v| (v.0, &v.1)).collect();
+				listing.push_str(&listing::generate_banked_listing(first_pass, &results));
+			} else {
+				listing.push_str(&listing::generate_listing(first_pass, &code[0].1));
+			}
+		}
+
+		// Collect symbols
+		if config.symbols.is_some() {
+			exported.extend(symbols::collect_symbols(first_pass));
+		}
+		if config.run {
+			labels.extend(first_pass.symbol_table.iter().map(|(k, v)| (k.clone(), *v)));
+		}
+	}
+
+	// Write listing to file
+	if let Some(path) = &config.listing {
+		write_file(path, listing);
+	}
+
+	// Write debug info to file
+	if let Some(path) = &config.debug_info {
+		write_file(path, debug_info::generate_debug_info(&program.result));
+	}
+
+	// Write symbols to file(s)
+	if let Some(path) = &config.symbols {
+		let format = config.symbol_format.unwrap_or_else(|| SymbolFormat::from_path(path));
+		for (suffix, contents) in symbols::export_symbols(&exported, format) {
+			write_file(&format!("{}{}", path, suffix), contents);
+		}
+	}
+
+	// Run every test
+	if let Some(path) = &config.test_spec {
+		let src = String::from_utf8(read_file(path)).unwrap_or_else(|_| {
+			eprintln!("File {} is not valid UTF-8", path);
 			process::exit(1);
 		});
-	} else {
-		// Write empty file
-		fs::write(&out, [0u8, 0]).unwrap_or_else(|e| {
-			match e.kind() {
-				ErrorKind::PermissionDenied => eprintln!("Insufficient permissions to write {}", &out),
-				_ => eprintln!("Error occured whilst writing to {}: {}", &out, e)
-			}
+		let tests = spec::parse_spec(path, &src).unwrap_or_else(|e| {
+			eprintln!("Error on {}:{}: {}", e.filename, e.lino, e.message);
 			process::exit(1);
 		});
+
+		let image = program.result.image();
+		let mut failed = 0;
+		for test in tests.iter() {
+			let failures = spec::run_test(test, &image, &labels, config.max_cycles);
+			if failures.is_empty() {
+				println!("PASS {}", test.name);
+				continue;
+			}
+
+			failed += 1;
+			println!("FAIL {}", test.name);
+			for failure in failures {
+				println!("    {}:{}: {}", path, failure.lino, failure.message);
+			}
+		}
+
+		println!("{} tests, {} passed, {} failed", tests.len(), tests.len() - failed, failed);
+		if failed > 0 {
+			process::exit(1);
+		}
+		return;
+	}
+
+	// Run the code until it reaches brk
+	if config.run {
+		let mut cpu = Cpu::new(program.result.image());
+		if let Some(pc) = &config.pc {
+			cpu.pc = match labels.get(pc) {
+				Some(v) => *v,
+				None => u16::from_str_radix(pc.trim_start_matches('$'), 16).unwrap_or_else(|_| {
+					eprintln!("Error: --pc must be followed by a label or a 16 bit hex number");
+					process::exit(1);
+				})
+			};
+		}
+
+		let reason = cpu.run(config.max_cycles);
+		match reason {
+			StopReason::Brk => println!("Stopped at brk"),
+			StopReason::CycleLimit => println!("Stopped after {} cycles", config.max_cycles),
+			StopReason::InvalidOpcode(opcode) => println!("Stopped at invalid opcode ${:02X}", opcode)
+		}
+		println!("{}", cpu.format_registers());
+		if reason != StopReason::Brk {
+			process::exit(1);
+		}
+		return;
 	}
+
+	let out = config.out.unwrap_or_else(|| String::from("a.out"));
+	let contents = program.write(&OutputOptions {
+		path: out.clone(),
+		record_length: config.record_length,
+		entry: config.entry,
+		init: config.init,
+		start: config.addr_start,
+		end: config.addr_end
+	});
+	write_file(&out, contents.unwrap_or_else(|e| {
+		eprintln!("Error: {}", e);
+		process::exit(1);
+	}));
 }
@@ -0,0 +1,577 @@
+//
+// src
+// o65.rs: Reads and writes relocatable o65 binaries.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+// Named segments are grouped into the four o65 segments by name: DATA goes in data, BSS in bss,
+// ZEROPAGE (or ZP) in zp, and everything else in text. References to labels in named segments
+// become relocations, and imports that no file exports become undefined references.
+//
+// When reading, the o65 segments become the named segments CODE, DATA, BSS, and ZEROPAGE, and every
+// relocation becomes a reference to a generated label such as CODE+$0012.
+//
+
+use std::collections::HashMap;
+
+use crate::linker;
+use crate::linker::{
+	LinkerConfig,
+	MemoryArea,
+	SegmentRule
+};
+use crate::object::Reader;
+use crate::pass_1::{
+	AnnotatedLine,
+	Bank,
+	FirstPassResult,
+	InstructionArg,
+	LineKind,
+	NamedSegment,
+	SymbolDef,
+	SymbolKind
+};
+use crate::pass_2;
+use crate::pass_2::AssemblerResult;
+
+// The marker and magic number at the start of every o65 file
+pub const O65_MAGIC: &[u8; 5] = &[0x01, 0x00, b'o', b'6', b'5'];
+
+// The address the text segment is assembled at
+pub const TEXT_BASE: u16 = 0x1000;
+
+// Segment ids
+const SEG_UNDEFINED: u8 = 0;
+const SEG_ABSOLUTE: u8 = 1;
+const SEG_TEXT: u8 = 2;
+const SEG_DATA: u8 = 3;
+const SEG_BSS: u8 = 4;
+const SEG_ZP: u8 = 5;
+
+// The named segment each o65 segment is read into
+const SEGMENT_NAMES: [&str; 6] = ["", "", "CODE", "DATA", "BSS", "ZEROPAGE"];
+
+// Relocation types
+const RELOC_WORD: u8 = 0x80;
+const RELOC_HIGH: u8 = 0x40;
+const RELOC_LOW: u8 = 0x20;
+
+// Mode bits
+const MODE_65816: u16 = 0x8000;
+const MODE_PAGED: u16 = 0x4000;
+const MODE_LONG: u16 = 0x2000;
+const MODE_OBJECT: u16 = 0x1000;
+
+// A relocation entry
+#[derive(Debug, Clone, PartialEq)]
+struct Reloc {
+	addr: u16,
+	kind: u8,
+	target: u8,
+	undefined: u16,
+	low: u8
+}
+
+// Checks whether some bytes are an o65 file
+pub fn is_o65(bytes: &[u8]) -> bool {
+	bytes.starts_with(O65_MAGIC)
+}
+
+// Gets the o65 segment a named segment is placed in
+pub fn segment_id(name: &str) -> u8 {
+	match name.to_uppercase().as_str() {
+		"DATA" => SEG_DATA,
+		"BSS" => SEG_BSS,
+		"ZEROPAGE" | "ZP" => SEG_ZP,
+		_ => SEG_TEXT
+	}
+}
+
+fn put_u16(out: &mut Vec<u8>, n: u16) {
+	out.extend(&n.to_le_bytes());
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+	out.extend(name.as_bytes());
+	out.push(0);
+}
+
+// Writes a relocation table, where each entry is the distance from the previous one
+fn put_relocs(out: &mut Vec<u8>, base: u16, relocs: &mut [Reloc]) {
+	relocs.sort_by_key(|v| v.addr);
+	let mut prev = base as i32 - 1;
+	for reloc in relocs.iter() {
+		let mut diff = reloc.addr as i32 - prev;
+		while diff > 254 {
+			out.push(255);
+			diff -= 254;
+		}
+		out.push(diff as u8);
+		out.push(reloc.kind | reloc.target);
+		if reloc.target == SEG_UNDEFINED {
+			put_u16(out, reloc.undefined);
+		}
+		if reloc.kind == RELOC_HIGH {
+			out.push(reloc.low);
+		}
+		prev = reloc.addr as i32;
+	}
+	out.push(0);
+}
+
+// Links files into a single relocatable o65 binary
+pub fn write_o65(files: &mut [FirstPassResult]) -> Result<Vec<u8>, String> {
+	// Code at a fixed address can't be relocated
+	for file in files.iter() {
+		if let Some(line) = file.lines.iter().find(|v| v.segment.is_none()) {
+			return Err(format!("{}:{}: o65 output requires all code to be in a named segment", file.filename, line.lino));
+		}
+	}
+
+	// Segments are loaded at any address, so they can't be page aligned
+	if let Some(segment) = files.iter().flat_map(|v| v.segments.iter()).find(|v| v.align > 1) {
+		return Err(format!("Segment {} uses .page_align, which o65 output does not support", segment.name));
+	}
+
+	// Place every o65 segment after the previous one, with zero page at $0000
+	let mut sizes = [0u32; 6];
+	for segment in files.iter().flat_map(|v| v.segments.iter()) {
+		sizes[segment_id(&segment.name) as usize] += segment.size as u32;
+	}
+	let mut bases = [0u16; 6];
+	let mut addr = TEXT_BASE as u32;
+	for id in [SEG_TEXT, SEG_DATA, SEG_BSS].iter() {
+		bases[*id as usize] = addr as u16;
+		addr += sizes[*id as usize];
+	}
+	if addr > 0x10000 {
+		return Err(format!("text, data, and bss segments are {} bytes, which does not fit in memory", addr - TEXT_BASE as u32));
+	} else if sizes[SEG_ZP as usize] > 0x100 {
+		return Err(format!("zp segment is {} bytes, which does not fit in zero page", sizes[SEG_ZP as usize]));
+	}
+
+	let ids = [SEG_TEXT, SEG_DATA, SEG_BSS, SEG_ZP];
+	let mut config = LinkerConfig::default();
+	for id in ids.iter() {
+		config.areas.push(MemoryArea {
+			name: String::from(SEGMENT_NAMES[*id as usize]),
+			start: bases[*id as usize],
+			size: sizes[*id as usize],
+			fill: None
+		});
+	}
+	for segment in files.iter().flat_map(|v| v.segments.iter()) {
+		if !config.segments.iter().any(|v| v.name == segment.name) {
+			config.segments.push(SegmentRule {
+				name: segment.name.clone(),
+				area: ids.iter().position(|v| *v == segment_id(&segment.name)).unwrap(),
+				align: 1,
+				start: None
+			});
+		}
+	}
+	linker::place(&config, files)?;
+
+	// Imports that no file exports are undefined references, which are assembled as 0
+	let mut undefined: Vec<String> = Vec::new();
+	for (name, filename, _) in linker::resolve_imports(files)? {
+		if let Some(file) = files.iter_mut().find(|v| v.filename == filename) {
+			file.symbol_table.insert(name.clone(), 0);
+		}
+		if !undefined.contains(&name) {
+			undefined.push(name);
+		}
+	}
+
+	// The segment every exported symbol is in
+	let symbol_segment = |file: &FirstPassResult, name: &String| -> u8 {
+		match file.symbol_defs[name].segment {
+			Some(i) => segment_id(&file.segments[i].name),
+			None => SEG_ABSOLUTE
+		}
+	};
+	let mut exported: HashMap<&String, u8> = HashMap::new();
+	for file in files.iter() {
+		for name in file.exports.iter() {
+			exported.insert(name, symbol_segment(file, name));
+		}
+	}
+
+	// Assemble and collect relocations
+	let mut result = AssemblerResult::new("o65");
+	let mut text_relocs = Vec::new();
+	let mut data_relocs = Vec::new();
+	for file in files.iter() {
+		let assembled = pass_2::second_pass(file).map_err(|e| format!("{}:{}: {}", e.filename, e.lino, e.message))?;
+		result.merge(&assembled)?;
+
+		for line in file.lines.iter() {
+			let (label, kind, offset) = match &line.arg {
+				InstructionArg::WordLabelArg(label) => (label, RELOC_WORD, 1),
+				InstructionArg::DataWordLabelArg(label) => (label, RELOC_WORD, 0),
+				InstructionArg::ByteLabelArg(label) | InstructionArg::ByteLabelLowArg(label) => (label, RELOC_LOW, 1),
+				InstructionArg::ByteLabelHighArg(label) => (label, RELOC_HIGH, 1),
+				InstructionArg::RelativeLabelArg(label) => (label, 0, 1),
+				_ => continue
+			};
+
+			// Find the segment the label is in
+			let def = &file.symbol_defs[label];
+			let (target, index) = if def.kind == SymbolKind::Import {
+				match undefined.iter().position(|v| v == label) {
+					Some(i) => (SEG_UNDEFINED, i as u16),
+					None => (exported[label], 0)
+				}
+			} else {
+				(symbol_segment(file, label), 0)
+			};
+
+			// Branches must stay within their segment
+			let segment = segment_id(&file.segments[line.segment.unwrap()].name);
+			if kind == 0 {
+				if target != segment {
+					return Err(format!("{}:{}: Branch to {} cannot be relocated", file.filename, line.lino, label));
+				}
+				continue;
+			} else if target == SEG_ABSOLUTE {
+				continue;
+			}
+
+			let reloc = Reloc {
+				addr: line.addr.wrapping_add(offset),
+				kind,
+				target,
+				undefined: index,
+				low: file.symbol_table[label] as u8
+			};
+			match segment {
+				SEG_TEXT => text_relocs.push(reloc),
+				SEG_DATA => data_relocs.push(reloc),
+				_ => return Err(format!("{}:{}: The {} segment cannot contain code or data", file.filename, line.lino, SEGMENT_NAMES[segment as usize]))
+			}
+		}
+	}
+
+	// Header
+	let mut out = Vec::new();
+	out.extend(O65_MAGIC);
+	out.push(0);
+	put_u16(&mut out, if undefined.is_empty() { 0 } else { MODE_OBJECT });
+	for id in [SEG_TEXT, SEG_DATA, SEG_BSS, SEG_ZP].iter() {
+		put_u16(&mut out, bases[*id as usize]);
+		put_u16(&mut out, sizes[*id as usize] as u16);
+	}
+	put_u16(&mut out, 0);
+
+	// Filename option
+	if let Some(file) = files.first() {
+		out.push(file.filename.len() as u8 + 3);
+		out.push(0);
+		put_name(&mut out, &file.filename);
+	}
+	out.push(0);
+
+	// Text and data
+	let image = result.image();
+	for id in [SEG_TEXT, SEG_DATA].iter() {
+		let start = bases[*id as usize] as usize;
+		out.extend(&image[start..start + sizes[*id as usize] as usize]);
+	}
+
+	// Undefined references
+	put_u16(&mut out, undefined.len() as u16);
+	for name in undefined.iter() {
+		put_name(&mut out, name);
+	}
+
+	// Relocation tables
+	put_relocs(&mut out, bases[SEG_TEXT as usize], &mut text_relocs);
+	put_relocs(&mut out, bases[SEG_DATA as usize], &mut data_relocs);
+
+	// Exported globals
+	let exports: Vec<(&String, &FirstPassResult)> = files.iter().flat_map(|f| f.exports.iter().map(move |v| (v, f))).collect();
+	put_u16(&mut out, exports.len() as u16);
+	for (name, file) in exports {
+		put_name(&mut out, name);
+		out.push(exported[name]);
+		put_u16(&mut out, file.symbol_table[name]);
+	}
+
+	Ok(out)
+}
+
+// Reads a name terminated by a zero byte
+fn read_name(r: &mut Reader) -> Result<String, String> {
+	let mut name = Vec::new();
+	loop {
+		match r.u8()? {
+			0 => break,
+			c => name.push(c)
+		}
+	}
+	Ok(String::from_utf8_lossy(&name).into_owned())
+}
+
+// Reads a relocation table for a segment of size bytes starting at base
+fn read_relocs(r: &mut Reader, filename: &str, base: u16, size: u16, paged: bool) -> Result<Vec<Reloc>, String> {
+	let mut relocs = Vec::new();
+	let mut addr = base as i32 - 1;
+	loop {
+		match r.u8()? {
+			0 => break,
+			255 => addr += 254,
+			diff => {
+				addr += diff as i32;
+				let typebyte = r.u8()?;
+				let (kind, target) = (typebyte & 0xE0, typebyte & 0x1F);
+				let undefined = if target == SEG_UNDEFINED { r.u16()? } else { 0 };
+				let low = if kind == RELOC_HIGH && !paged { r.u8()? } else { 0 };
+				if kind != RELOC_WORD && kind != RELOC_HIGH && kind != RELOC_LOW {
+					return Err(format!("{} has an unsupported relocation type ${:02X}", filename, kind));
+				} else if addr - base as i32 + if kind == RELOC_WORD { 2 } else { 1 } > size as i32 {
+					return Err(format!("{} is corrupt (relocation at ${:04X} is outside its segment)", filename, addr));
+				}
+				relocs.push(Reloc { addr: addr as u16, kind, target, undefined, low });
+			}
+		}
+	}
+	Ok(relocs)
+}
+
+// The state of reading an o65 file into the result of the first pass
+struct Input<'a> {
+	filename: &'a str,
+	bases: [u16; 6],
+	indices: [Option<usize>; 6],
+	undefined: Vec<String>,
+	result: FirstPassResult
+}
+
+impl<'a> Input<'a> {
+	// Gets the named segment for an o65 segment, adding it if needed
+	fn segment(&mut self, target: u8, size: u16) -> usize {
+		match self.indices[target as usize] {
+			Some(i) => i,
+			None => {
+				self.indices[target as usize] = Some(self.result.segments.len());
+				self.result.segments.push(NamedSegment {
+					name: String::from(SEGMENT_NAMES[target as usize]),
+					size,
+					align: 1
+				});
+				self.result.segments.len() - 1
+			}
+		}
+	}
+
+	// Gets a label for an address in a segment, defining it if needed (or None for absolute addresses)
+	fn label(&mut self, target: u8, value: u16) -> Result<Option<String>, String> {
+		if target == SEG_ABSOLUTE {
+			return Ok(None);
+		} else if !(SEG_TEXT..=SEG_ZP).contains(&target) {
+			return Err(format!("{} refers to unknown segment {}", self.filename, target));
+		}
+
+		let offset = value.wrapping_sub(self.bases[target as usize]);
+		let name = format!("{}+${:04X}", SEGMENT_NAMES[target as usize], offset);
+		if !self.result.symbol_defs.contains_key(&name) {
+			let segment = self.segment(target, 0);
+			self.result.symbol_table.insert(name.clone(), offset);
+			self.result.symbol_defs.insert(name.clone(), SymbolDef {
+				filename: String::from(self.filename),
+				lino: 0,
+				kind: SymbolKind::Label,
+				segment: Some(segment),
+				bank: Bank::None
+			});
+		}
+		Ok(Some(name))
+	}
+
+	// Gets the label a relocated value refers to, which is either an undefined reference or a label
+	fn target(&mut self, reloc: &Reloc, value: u16) -> Result<Option<String>, String> {
+		if reloc.target != SEG_UNDEFINED {
+			return self.label(reloc.target, value);
+		}
+
+		let name = match self.undefined.get(reloc.undefined as usize) {
+			Some(name) => name.clone(),
+			None => return Err(format!("{} refers to missing undefined reference {}", self.filename, reloc.undefined))
+		};
+		if value != 0 {
+			return Err(format!("{} refers to {} with an offset, which is not supported", self.filename, name));
+		}
+
+		let filename = self.filename;
+		self.result.symbol_defs.entry(name.clone()).or_insert_with(|| SymbolDef {
+			filename: String::from(filename),
+			lino: 0,
+			kind: SymbolKind::Import,
+			segment: None,
+			bank: Bank::None
+		});
+		Ok(Some(name))
+	}
+
+	// Converts the bytes of a segment into lines, with relocated bytes referring to labels
+	fn lines(&mut self, id: u8, bytes: &[u8], relocs: &[Reloc]) -> Result<(), String> {
+		let base = self.bases[id as usize];
+		let segment = self.indices[id as usize];
+		let relocs: HashMap<u16, &Reloc> = relocs.iter().map(|v| (v.addr.wrapping_sub(base), v)).collect();
+		let at = |i: usize| relocs.get(&(i as u16)).copied();
+
+		let mut i = 0usize;
+		while i < bytes.len() {
+			let mut line = AnnotatedLine {
+				lino: 0,
+				col: 0,
+				kind: LineKind::Data,
+				bank: Bank::None,
+				segment,
+				addr: i as u16,
+				opcode: bytes[i],
+				arg: InstructionArg::NoArgs
+			};
+
+			match (at(i), at(i + 1)) {
+				// Relocated word
+				(Some(reloc), _) if reloc.kind == RELOC_WORD && i + 1 < bytes.len() => {
+					let value = u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+					if let Some(name) = self.target(reloc, value)? {
+						line.opcode = 0;
+						line.arg = InstructionArg::DataWordLabelArg(name);
+						self.result.lines.push(line);
+						i += 2;
+						continue;
+					}
+				}
+
+				// Relocated low or high byte, which is emitted with the byte before it
+				(None, Some(reloc)) if reloc.kind != RELOC_WORD && i + 1 < bytes.len() => {
+					let value = if reloc.kind == RELOC_LOW {
+						// Only the low byte is known, so assume the address is in the first page of the segment
+						let base = self.bases.get(reloc.target as usize).copied().unwrap_or(0);
+						let value = (base & 0xFF00) | bytes[i + 1] as u16;
+						if value < base { value.wrapping_add(0x100) } else { value }
+					} else {
+						u16::from_le_bytes([reloc.low, bytes[i + 1]])
+					};
+					if let Some(name) = self.target(reloc, value)? {
+						line.arg = if reloc.kind == RELOC_LOW {
+							InstructionArg::ByteLabelLowArg(name)
+						} else {
+							InstructionArg::ByteLabelHighArg(name)
+						};
+						self.result.lines.push(line);
+						i += 2;
+						continue;
+					}
+				}
+
+				(Some(reloc), _) if reloc.target != SEG_ABSOLUTE => {
+					return Err(format!("{} has a relocation at ${:04X} that is not supported", self.filename, base.wrapping_add(i as u16)));
+				}
+
+				_ => {}
+			}
+
+			self.result.lines.push(line);
+			i += 1;
+		}
+		Ok(())
+	}
+}
+
+// Reads an o65 file as a link input
+pub fn read_o65(filename: &str, bytes: &[u8]) -> Result<FirstPassResult, String> {
+	if !is_o65(bytes) {
+		return Err(format!("{} is not an o65 file", filename));
+	}
+	let mut r = Reader::new(filename, bytes, O65_MAGIC.len() + 1);
+	let mode = r.u16()?;
+	if mode & (MODE_65816 | MODE_LONG) != 0 {
+		return Err(format!("{} uses 65816 or 32 bit mode, which is not supported", filename));
+	}
+
+	let mut bases = [0u16; 6];
+	let mut sizes = [0u16; 6];
+	for id in [SEG_TEXT, SEG_DATA, SEG_BSS, SEG_ZP].iter() {
+		bases[*id as usize] = r.u16()?;
+		sizes[*id as usize] = r.u16()?;
+	}
+	r.u16()?;
+
+	// Skip header options
+	loop {
+		match r.u8()? {
+			0 => break,
+			len => {
+				r.take(len as usize - 1)?;
+			}
+		}
+	}
+
+	let text = r.take(sizes[SEG_TEXT as usize] as usize)?;
+	let data = r.take(sizes[SEG_DATA as usize] as usize)?;
+	let mut undefined = Vec::new();
+	for _ in 0..r.u16()? {
+		undefined.push(read_name(&mut r)?);
+	}
+	let paged = mode & MODE_PAGED != 0;
+	let text_relocs = read_relocs(&mut r, filename, bases[SEG_TEXT as usize], sizes[SEG_TEXT as usize], paged)?;
+	let data_relocs = read_relocs(&mut r, filename, bases[SEG_DATA as usize], sizes[SEG_DATA as usize], paged)?;
+
+	let mut input = Input {
+		filename,
+		bases,
+		indices: [None; 6],
+		undefined,
+		result: FirstPassResult {
+			filename: String::from(filename),
+			source: String::new(),
+			lines: Vec::new(),
+			symbol_table: HashMap::new(),
+			symbol_defs: HashMap::new(),
+			includes: Vec::new(),
+			segments: Vec::new(),
+			exports: Vec::new(),
+			cycle_regions: Vec::new(),
+			page_asserts: Vec::new(),
+			asserts: Vec::new(),
+			diagnostics: Vec::new(),
+			ines: None
+		}
+	};
+
+	// Named segments for the o65 segments that are used
+	for id in [SEG_TEXT, SEG_DATA, SEG_BSS, SEG_ZP].iter() {
+		if sizes[*id as usize] > 0 {
+			input.segment(*id, sizes[*id as usize]);
+		}
+	}
+	input.lines(SEG_TEXT, text, &text_relocs)?;
+	input.lines(SEG_DATA, data, &data_relocs)?;
+
+	// Exported globals
+	for _ in 0..r.u16()? {
+		let name = read_name(&mut r)?;
+		let target = r.u8()?;
+		let value = r.u16()?;
+		let (value, def) = match input.label(target, value)? {
+			Some(label) => (input.result.symbol_table[&label], input.result.symbol_defs[&label].clone()),
+			None => (value, SymbolDef {
+				filename: String::from(filename),
+				lino: 0,
+				kind: SymbolKind::Constant,
+				segment: None,
+				bank: Bank::None
+			})
+		};
+		input.result.symbol_table.insert(name.clone(), value);
+		input.result.symbol_defs.insert(name.clone(), def);
+		input.result.exports.push(name);
+	}
+
+	Ok(input.result)
+}
@@ -0,0 +1,512 @@
+//
+// src
+// object.rs: Reads and writes relocatable object files.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+// An object file stores the assembler's intermediate representation after the first pass (annotated lines, symbols, segments and
+// checks), so files can be assembled separately and linked later. It is not an interchange format: it mirrors the in-memory IR, and
+// the version is bumped whenever the IR changes. All numbers are little endian, and strings are a u32 length followed by UTF-8 bytes.
+//
+// "A65O"                         magic
+// u8 version
+// str filename
+// u32 count, (str name, u16 size, u16 align)*       named segments
+// u32 count, line*                                   annotated lines
+//     u32 lino, u32 col, u8 kind, u8 bank, u8 bank number, u16 segment (FFFF if none), u16 addr, u8 opcode, arg
+//     arg is a u8 tag followed by a u8, u16, or str depending on the tag (see ARG_*)
+// u32 count, symbol*                                 symbols
+//     str name, u8 has value, u16 value, str filename, u32 lino, u8 kind, u16 segment (FFFF if none), u8 bank, u8 bank number
+// u32 count, str*                                    exported symbol names
+// u32 count, (u32 lino, u32 end lino, u16 budget)*   cycle regions (budget FFFF if none)
+// u32 count, (u32 lino, u16 addr, u16 segment, str label)*   page asserts (segment FFFF if none)
+// u32 count, assert*                                 .assert checks
+//     str filename, u32 lino, value left, u8 comparison, value right, u8 has message, str message (if any)
+//     value is a u8 tag (0 for a literal, 1 for a label) followed by a u16 or str
+// u8 has iNES header, then u16 mapper, u8 submapper, u8 mirroring, u16 prg banks, u16 chr banks (FFFF if unset), u8 battery, u8 nes2
+//
+// The source text is not stored; listings of linked objects read it again from the original file if it still exists.
+// Included files are not stored, since their symbols are already part of the symbol table.
+// .warning and .print messages are not stored either, since they are reported when the object file is written.
+//
+
+use std::collections::HashMap;
+
+use crate::ines::InesHeader;
+use crate::parser::{
+	Address,
+	Comparison,
+	Mirroring
+};
+use crate::pass_1::{
+	AnnotatedLine,
+	Assert,
+	Bank,
+	CycleRegion,
+	FirstPassResult,
+	InstructionArg,
+	LineKind,
+	NamedSegment,
+	PageAssert,
+	SymbolDef,
+	SymbolKind
+};
+
+// The magic number at the start of every object file
+pub const OBJECT_MAGIC: &[u8; 4] = b"A65O";
+
+// The version of the object file format
+pub const OBJECT_VERSION: u8 = 7;
+
+// Argument tags
+const ARG_NONE: u8 = 0;
+const ARG_BYTE: u8 = 1;
+const ARG_BYTE_LABEL: u8 = 2;
+const ARG_RELATIVE_LABEL: u8 = 3;
+const ARG_BYTE_LABEL_LOW: u8 = 4;
+const ARG_BYTE_LABEL_HIGH: u8 = 5;
+const ARG_WORD: u8 = 6;
+const ARG_WORD_LABEL: u8 = 7;
+const ARG_DATA_WORD_LABEL: u8 = 8;
+
+// Marks a missing segment or bank count
+const NONE: u16 = 0xFFFF;
+
+// Checks whether some bytes are an object file
+pub fn is_object(bytes: &[u8]) -> bool {
+	bytes.starts_with(OBJECT_MAGIC)
+}
+
+fn put_u16(out: &mut Vec<u8>, n: u16) {
+	out.extend(&n.to_le_bytes());
+}
+
+pub(crate) fn put_u32(out: &mut Vec<u8>, n: u32) {
+	out.extend(&n.to_le_bytes());
+}
+
+pub(crate) fn put_str(out: &mut Vec<u8>, s: &str) {
+	put_u32(out, s.len() as u32);
+	out.extend(s.as_bytes());
+}
+
+fn put_segment(out: &mut Vec<u8>, segment: Option<usize>) {
+	put_u16(out, segment.map(|v| v as u16).unwrap_or(NONE));
+}
+
+fn put_bank(out: &mut Vec<u8>, bank: Bank) {
+	out.extend(&match bank {
+		Bank::None => [0, 0],
+		Bank::Prg(n) => [1, n],
+		Bank::Chr(n) => [2, n]
+	});
+}
+
+fn put_value(out: &mut Vec<u8>, value: &Address) {
+	match value {
+		Address::Literal(n) => {
+			out.push(0);
+			put_u16(out, *n);
+		}
+		Address::Label(label) => {
+			out.push(1);
+			put_str(out, label);
+		}
+	}
+}
+
+// Serialises the result of the first pass into an object file
+pub fn write_object(first_pass: &FirstPassResult) -> Vec<u8> {
+	let mut out = Vec::new();
+	out.extend(OBJECT_MAGIC);
+	out.push(OBJECT_VERSION);
+	put_str(&mut out, &first_pass.filename);
+
+	// Named segments
+	put_u32(&mut out, first_pass.segments.len() as u32);
+	for segment in first_pass.segments.iter() {
+		put_str(&mut out, &segment.name);
+		put_u16(&mut out, segment.size);
+		put_u16(&mut out, segment.align);
+	}
+
+	// Lines
+	put_u32(&mut out, first_pass.lines.len() as u32);
+	for line in first_pass.lines.iter() {
+		put_u32(&mut out, line.lino);
+		put_u32(&mut out, line.col);
+		out.push(match line.kind {
+			LineKind::Code => 0,
+			LineKind::Data => 1
+		});
+		put_bank(&mut out, line.bank);
+		put_segment(&mut out, line.segment);
+		put_u16(&mut out, line.addr);
+		out.push(line.opcode);
+
+		match &line.arg {
+			InstructionArg::NoArgs => out.push(ARG_NONE),
+			InstructionArg::ByteArg(n) => out.extend(&[ARG_BYTE, *n]),
+			InstructionArg::WordArg(n) => {
+				out.push(ARG_WORD);
+				put_u16(&mut out, *n);
+			}
+			InstructionArg::ByteLabelArg(label)
+			| InstructionArg::RelativeLabelArg(label)
+			| InstructionArg::ByteLabelLowArg(label)
+			| InstructionArg::ByteLabelHighArg(label)
+			| InstructionArg::WordLabelArg(label)
+			| InstructionArg::DataWordLabelArg(label) => {
+				out.push(match line.arg {
+					InstructionArg::ByteLabelArg(_) => ARG_BYTE_LABEL,
+					InstructionArg::RelativeLabelArg(_) => ARG_RELATIVE_LABEL,
+					InstructionArg::ByteLabelLowArg(_) => ARG_BYTE_LABEL_LOW,
+					InstructionArg::ByteLabelHighArg(_) => ARG_BYTE_LABEL_HIGH,
+					InstructionArg::WordLabelArg(_) => ARG_WORD_LABEL,
+					_ => ARG_DATA_WORD_LABEL
+				});
+				put_str(&mut out, label);
+			}
+		}
+	}
+
+	// Symbols, sorted so the output is deterministic
+	let mut symbols: Vec<(&String, &SymbolDef)> = first_pass.symbol_defs.iter().collect();
+	symbols.sort_by_key(|v| v.0);
+	put_u32(&mut out, symbols.len() as u32);
+	for (name, def) in symbols {
+		put_str(&mut out, name);
+		match first_pass.symbol_table.get(name) {
+			Some(v) => {
+				out.push(1);
+				put_u16(&mut out, *v);
+			}
+			None => {
+				out.push(0);
+				put_u16(&mut out, 0);
+			}
+		}
+		put_str(&mut out, &def.filename);
+		put_u32(&mut out, def.lino);
+		out.push(match def.kind {
+			SymbolKind::Label => 0,
+			SymbolKind::Constant => 1,
+			SymbolKind::Variable => 2,
+			SymbolKind::Import => 3
+		});
+		put_segment(&mut out, def.segment);
+		put_bank(&mut out, def.bank);
+	}
+
+	// Exports
+	put_u32(&mut out, first_pass.exports.len() as u32);
+	for name in first_pass.exports.iter() {
+		put_str(&mut out, name);
+	}
+
+	// Cycle regions
+	put_u32(&mut out, first_pass.cycle_regions.len() as u32);
+	for region in first_pass.cycle_regions.iter() {
+		put_u32(&mut out, region.lino);
+		put_u32(&mut out, region.end_lino);
+		put_u16(&mut out, region.budget.unwrap_or(NONE));
+	}
+
+	// Page asserts
+	put_u32(&mut out, first_pass.page_asserts.len() as u32);
+	for assert in first_pass.page_asserts.iter() {
+		put_u32(&mut out, assert.lino);
+		put_u16(&mut out, assert.addr);
+		put_segment(&mut out, assert.segment);
+		put_str(&mut out, &assert.label);
+	}
+
+	// Asserts
+	put_u32(&mut out, first_pass.asserts.len() as u32);
+	for assert in first_pass.asserts.iter() {
+		put_str(&mut out, &assert.filename);
+		put_u32(&mut out, assert.lino);
+		put_value(&mut out, &assert.left);
+		out.push(match assert.comparison {
+			Comparison::Equal => 0,
+			Comparison::Less => 1,
+			Comparison::LessEqual => 2,
+			Comparison::Greater => 3,
+			Comparison::GreaterEqual => 4,
+			Comparison::NotEqual => 5
+		});
+		put_value(&mut out, &assert.right);
+		match &assert.message {
+			Some(message) => {
+				out.push(1);
+				put_str(&mut out, message);
+			}
+			None => out.push(0)
+		}
+	}
+
+	// iNES header
+	match &first_pass.ines {
+		Some(header) => {
+			out.push(1);
+			put_u16(&mut out, header.mapper);
+			out.push(header.submapper);
+			out.push(match header.mirroring {
+				Mirroring::Horizontal => 0,
+				Mirroring::Vertical => 1,
+				Mirroring::FourScreen => 2
+			});
+			put_u16(&mut out, header.prg_banks.unwrap_or(NONE));
+			put_u16(&mut out, header.chr_banks.unwrap_or(NONE));
+			out.push(header.battery as u8);
+			out.push(header.nes2 as u8);
+		}
+		None => out.push(0)
+	}
+
+	out
+}
+
+// Reads values from an object file (also used for o65 files)
+pub(crate) struct Reader<'a> {
+	filename: &'a str,
+	bytes: &'a [u8],
+	pos: usize
+}
+
+impl<'a> Reader<'a> {
+	// Creates a reader starting at the given position
+	pub(crate) fn new(filename: &'a str, bytes: &'a [u8], pos: usize) -> Reader<'a> {
+		Reader { filename, bytes, pos }
+	}
+
+	pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+		if self.pos + n > self.bytes.len() {
+			return Err(format!("{} is truncated", self.filename));
+		}
+		let bytes = &self.bytes[self.pos..self.pos + n];
+		self.pos += n;
+		Ok(bytes)
+	}
+
+	pub(crate) fn u8(&mut self) -> Result<u8, String> {
+		Ok(self.take(1)?[0])
+	}
+
+	pub(crate) fn u16(&mut self) -> Result<u16, String> {
+		let b = self.take(2)?;
+		Ok(u16::from_le_bytes([b[0], b[1]]))
+	}
+
+	pub(crate) fn u32(&mut self) -> Result<u32, String> {
+		let b = self.take(4)?;
+		Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+	}
+
+	pub(crate) fn str(&mut self) -> Result<String, String> {
+		let len = self.u32()? as usize;
+		match String::from_utf8(self.take(len)?.to_vec()) {
+			Ok(s) => Ok(s),
+			Err(_) => Err(format!("{} contains an invalid string", self.filename))
+		}
+	}
+
+	fn segment(&mut self, count: usize) -> Result<Option<usize>, String> {
+		match self.u16()? {
+			NONE => Ok(None),
+			n if (n as usize) < count => Ok(Some(n as usize)),
+			n => Err(format!("{} refers to missing segment {}", self.filename, n))
+		}
+	}
+
+	fn bank(&mut self) -> Result<Bank, String> {
+		match (self.u8()?, self.u8()?) {
+			(0, _) => Ok(Bank::None),
+			(1, n) => Ok(Bank::Prg(n)),
+			(2, n) => Ok(Bank::Chr(n)),
+			_ => self.invalid("bank")
+		}
+	}
+
+	fn value(&mut self) -> Result<Address, String> {
+		match self.u8()? {
+			0 => Ok(Address::Literal(self.u16()?)),
+			1 => Ok(Address::Label(self.str()?)),
+			_ => self.invalid("value")
+		}
+	}
+
+	fn invalid<T>(&self, what: &str) -> Result<T, String> {
+		Err(format!("{} contains an invalid {}", self.filename, what))
+	}
+}
+
+// Reads an object file back into the result of the first pass
+pub fn read_object(filename: &str, bytes: &[u8]) -> Result<FirstPassResult, String> {
+	if !is_object(bytes) {
+		return Err(format!("{} is not an object file", filename));
+	}
+	let mut r = Reader::new(filename, bytes, OBJECT_MAGIC.len());
+	let version = r.u8()?;
+	if version != OBJECT_VERSION {
+		return Err(format!("{} has unsupported object file version {}", filename, version));
+	}
+	let name = r.str()?;
+
+	// Named segments
+	let mut segments = Vec::new();
+	for _ in 0..r.u32()? {
+		segments.push(NamedSegment {
+			name: r.str()?,
+			size: r.u16()?,
+			align: r.u16()?
+		});
+	}
+
+	// Lines
+	let mut lines = Vec::new();
+	for _ in 0..r.u32()? {
+		let lino = r.u32()?;
+		let col = r.u32()?;
+		let kind = match r.u8()? {
+			0 => LineKind::Code,
+			1 => LineKind::Data,
+			_ => return r.invalid("line kind")
+		};
+		let bank = r.bank()?;
+		let segment = r.segment(segments.len())?;
+		let addr = r.u16()?;
+		let opcode = r.u8()?;
+		let arg = match r.u8()? {
+			ARG_NONE => InstructionArg::NoArgs,
+			ARG_BYTE => InstructionArg::ByteArg(r.u8()?),
+			ARG_WORD => InstructionArg::WordArg(r.u16()?),
+			ARG_BYTE_LABEL => InstructionArg::ByteLabelArg(r.str()?),
+			ARG_RELATIVE_LABEL => InstructionArg::RelativeLabelArg(r.str()?),
+			ARG_BYTE_LABEL_LOW => InstructionArg::ByteLabelLowArg(r.str()?),
+			ARG_BYTE_LABEL_HIGH => InstructionArg::ByteLabelHighArg(r.str()?),
+			ARG_WORD_LABEL => InstructionArg::WordLabelArg(r.str()?),
+			ARG_DATA_WORD_LABEL => InstructionArg::DataWordLabelArg(r.str()?),
+			_ => return r.invalid("argument")
+		};
+
+		lines.push(AnnotatedLine { lino, col, kind, bank, segment, addr, opcode, arg });
+	}
+
+	// Symbols
+	let mut symbol_table = HashMap::new();
+	let mut symbol_defs = HashMap::new();
+	for _ in 0..r.u32()? {
+		let key = r.str()?;
+		let has_value = r.u8()? != 0;
+		let value = r.u16()?;
+		let def = SymbolDef {
+			filename: r.str()?,
+			lino: r.u32()?,
+			kind: match r.u8()? {
+				0 => SymbolKind::Label,
+				1 => SymbolKind::Constant,
+				2 => SymbolKind::Variable,
+				3 => SymbolKind::Import,
+				_ => return r.invalid("symbol kind")
+			},
+			segment: r.segment(segments.len())?,
+			bank: r.bank()?
+		};
+
+		if has_value {
+			symbol_table.insert(key.clone(), value);
+		}
+		symbol_defs.insert(key, def);
+	}
+
+	// Exports
+	let mut exports = Vec::new();
+	for _ in 0..r.u32()? {
+		exports.push(r.str()?);
+	}
+
+	// Cycle regions
+	let mut cycle_regions = Vec::new();
+	for _ in 0..r.u32()? {
+		let lino = r.u32()?;
+		let end_lino = r.u32()?;
+		let budget = r.u16()?;
+		cycle_regions.push(CycleRegion { lino, end_lino, budget: if budget == NONE { None } else { Some(budget) } });
+	}
+
+	// Page asserts
+	let mut page_asserts = Vec::new();
+	for _ in 0..r.u32()? {
+		let lino = r.u32()?;
+		let addr = r.u16()?;
+		let segment = r.segment(segments.len())?;
+		page_asserts.push(PageAssert { lino, addr, segment, label: r.str()? });
+	}
+
+	// Asserts
+	let mut asserts = Vec::new();
+	for _ in 0..r.u32()? {
+		let filename = r.str()?;
+		let lino = r.u32()?;
+		let left = r.value()?;
+		let comparison = match r.u8()? {
+			0 => Comparison::Equal,
+			1 => Comparison::Less,
+			2 => Comparison::LessEqual,
+			3 => Comparison::Greater,
+			4 => Comparison::GreaterEqual,
+			5 => Comparison::NotEqual,
+			_ => return r.invalid("comparison")
+		};
+		let right = r.value()?;
+		let message = match r.u8()? {
+			0 => None,
+			_ => Some(r.str()?)
+		};
+		asserts.push(Assert { filename, lino, left, comparison, right, message });
+	}
+
+	// iNES header
+	let ines = match r.u8()? {
+		0 => None,
+		_ => {
+			let mapper = r.u16()?;
+			let submapper = r.u8()?;
+			let mirroring = match r.u8()? {
+				0 => Mirroring::Horizontal,
+				1 => Mirroring::Vertical,
+				2 => Mirroring::FourScreen,
+				_ => return r.invalid("mirroring")
+			};
+			let prg_banks = r.u16()?;
+			let chr_banks = r.u16()?;
+			Some(InesHeader {
+				mapper,
+				submapper,
+				mirroring,
+				prg_banks: if prg_banks == NONE { None } else { Some(prg_banks) },
+				chr_banks: if chr_banks == NONE { None } else { Some(chr_banks) },
+				battery: r.u8()? != 0,
+				nes2: r.u8()? != 0
+			})
+		}
+	};
+
+	Ok(FirstPassResult {
+		filename: name,
+		source: String::new(),
+		lines,
+		symbol_table,
+		symbol_defs,
+		includes: Vec::new(),
+		segments,
+		exports,
+		cycle_regions,
+		page_asserts,
+		asserts,
+		diagnostics: Vec::new(),
+		ines
+	})
+}
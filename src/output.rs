@@ -0,0 +1,255 @@
+//
+// src
+// output.rs: Serialises assembled code into binary and hex file formats.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::acorn;
+use crate::apple;
+use crate::atari;
+use crate::cbm;
+use crate::pass_2::AssemblerResult;
+
+// Represents an output file format
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+	// Raw bytes
+	Bin,
+
+	// Raw bytes prefixed with the little endian load address
+	Prg,
+
+	// Intel HEX
+	Ihex,
+
+	// Motorola S-record (S19)
+	Srec,
+
+	// iNES/NES 2.0 ROM built from .bank and .chr_bank
+	Ines,
+
+	// Commodore 1541 disk image containing the program as a PRG file
+	D64,
+
+	// Apple DOS 3.3 binary file
+	Dos33,
+
+	// ProDOS binary file wrapped in AppleSingle
+	Prodos,
+
+	// Atari 8-bit executable
+	Xex,
+
+	// BBC Micro single sided DFS disc image
+	Ssd,
+
+	// Relocatable o65 binary built from named segments
+	O65,
+}
+
+impl OutputFormat {
+	// Gets an output format from its name
+	pub fn from_name(name: &str) -> Option<OutputFormat> {
+		match name {
+			"bin" => Some(OutputFormat::Bin),
+			"prg" => Some(OutputFormat::Prg),
+			"ihex" | "hex" => Some(OutputFormat::Ihex),
+			"srec" | "s19" => Some(OutputFormat::Srec),
+			"ines" | "nes" => Some(OutputFormat::Ines),
+			"d64" => Some(OutputFormat::D64),
+			"apple" | "dos33" => Some(OutputFormat::Dos33),
+			"prodos" => Some(OutputFormat::Prodos),
+			"xex" => Some(OutputFormat::Xex),
+			"ssd" => Some(OutputFormat::Ssd),
+			"o65" => Some(OutputFormat::O65),
+			_ => None
+		}
+	}
+}
+
+// The default number of data bytes per hex record
+pub const DEFAULT_RECORD_LENGTH: u8 = 16;
+
+// Clips segments to the inclusive range start..=end
+pub fn clip_segments(segments: Vec<(u16, &[u8])>, start: u16, end: u16) -> Vec<(u16, &[u8])> {
+	segments.into_iter().filter_map(|(addr, bytes)| {
+		let first = addr.max(start);
+		let last = (addr as u32 + bytes.len() as u32 - 1).min(end as u32);
+		if (first as u32) > last {
+			None
+		} else {
+			let offset = (first - addr) as usize;
+			Some((first, &bytes[offset..offset + (last - first as u32) as usize + 1]))
+		}
+	}).collect()
+}
+
+// Writes an Intel HEX record
+fn ihex_record(out: &mut String, addr: u16, kind: u8, data: &[u8]) {
+	let mut sum = data.len() as u8;
+	sum = sum.wrapping_add((addr >> 8) as u8).wrapping_add(addr as u8).wrapping_add(kind);
+
+	let _ = write!(out, ":{:02X}{:04X}{:02X}", data.len(), addr, kind);
+	for byte in data {
+		sum = sum.wrapping_add(*byte);
+		let _ = write!(out, "{:02X}", byte);
+	}
+	let _ = writeln!(out, "{:02X}", sum.wrapping_neg());
+}
+
+// Serialises segments into Intel HEX, with the entry point as a start segment address record
+pub fn write_ihex(segments: &[(u16, &[u8])], record_length: u8, entry: Option<u16>) -> String {
+	let mut out = String::new();
+
+	// Data records
+	for (addr, bytes) in segments {
+		for (i, chunk) in bytes.chunks(record_length as usize).enumerate() {
+			ihex_record(&mut out, addr.wrapping_add((i * record_length as usize) as u16), 0x00, chunk);
+		}
+	}
+
+	// Start segment address (CS:IP with CS = 0)
+	if let Some(entry) = entry {
+		ihex_record(&mut out, 0, 0x03, &[0, 0, (entry >> 8) as u8, entry as u8]);
+	}
+
+	// End of file
+	ihex_record(&mut out, 0, 0x01, &[]);
+	out
+}
+
+// Writes a Motorola S-record with a 16 bit address
+fn srec_record(out: &mut String, kind: u8, addr: u16, data: &[u8]) {
+	let count = data.len() as u8 + 3;
+	let mut sum = count.wrapping_add((addr >> 8) as u8).wrapping_add(addr as u8);
+
+	let _ = write!(out, "S{}{:02X}{:04X}", kind, count, addr);
+	for byte in data {
+		sum = sum.wrapping_add(*byte);
+		let _ = write!(out, "{:02X}", byte);
+	}
+	let _ = writeln!(out, "{:02X}", !sum);
+}
+
+// Serialises segments into Motorola S-records (S19), with the entry point in the S9 termination record
+pub fn write_srec(segments: &[(u16, &[u8])], record_length: u8, entry: Option<u16>, name: &str) -> String {
+	let mut out = String::new();
+	let record_length = record_length.min(252) as usize;
+
+	// Header
+	srec_record(&mut out, 0, 0, name.as_bytes());
+
+	// Data records
+	let mut count = 0u32;
+	for (addr, bytes) in segments {
+		for (i, chunk) in bytes.chunks(record_length).enumerate() {
+			srec_record(&mut out, 1, addr.wrapping_add((i * record_length) as u16), chunk);
+			count += 1;
+		}
+	}
+
+	// Record count
+	if count <= 0xFFFF {
+		srec_record(&mut out, 5, count as u16, &[]);
+	}
+
+	// Termination with start address
+	srec_record(&mut out, 9, entry.unwrap_or(0), &[]);
+	out
+}
+
+// Serialises the bytes from start to end (inclusive) into a raw binary, optionally prefixed with the load address
+pub fn write_bin(bytes: &[u8], start: u16, end: u16, write_addr: bool) -> Vec<u8> {
+	let mut contents: Vec<u8> = Vec::with_capacity((end - start) as usize + 3);
+
+	// Write address if enabled
+	if write_addr {
+		contents.push(start as u8);
+		contents.push((start >> 8) as u8);
+	}
+
+	// Write contents
+	contents.extend(&bytes[start as usize..end as usize + 1]);
+	contents
+}
+
+// Settings for writing a file from assembled code
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+	// The path being written to, whose name is used for disk and file names in images
+	pub path: String,
+	pub record_length: u8,
+	pub entry: Option<u16>,
+	pub init: Option<u16>,
+
+	// The inclusive range of addresses to write, defaulting to every byte written
+	pub start: Option<u16>,
+	pub end: Option<u16>
+}
+
+impl Default for OutputOptions {
+	fn default() -> Self {
+		OutputOptions {
+			path: String::from("a.out"),
+			record_length: DEFAULT_RECORD_LENGTH,
+			entry: None,
+			init: None,
+			start: None,
+			end: None
+		}
+	}
+}
+
+// Serialises assembled code into a format
+// iNES ROMs are built from banks and o65 binaries from the first pass, so neither can be written from a single result
+pub fn write_output(format: OutputFormat, result: &AssemblerResult, options: &OutputOptions) -> Result<Vec<u8>, String> {
+	let start = options.start.unwrap_or_else(|| result.start().unwrap_or(u16::MAX));
+	let end = options.end.unwrap_or_else(|| result.end().unwrap_or(0));
+	let name = Path::new(&options.path).file_stem().map(|v| v.to_string_lossy().into_owned()).unwrap_or_default();
+
+	match format {
+		// Flat binaries, which are empty if nothing was written
+		OutputFormat::Bin | OutputFormat::Prg if end > start => Ok(write_bin(&result.image(), start, end, format == OutputFormat::Prg)),
+		OutputFormat::Bin | OutputFormat::Prg => Ok(vec![0u8, 0]),
+
+		// Hex formats only contain the written bytes
+		OutputFormat::Ihex | OutputFormat::Srec => {
+			let segments = clip_segments(result.slices(), start, end);
+			let entry = options.entry.or_else(|| segments.first().map(|v| v.0));
+			Ok(if format == OutputFormat::Ihex {
+				write_ihex(&segments, options.record_length, entry)
+			} else {
+				write_srec(&segments, options.record_length, entry, &options.path)
+			}.into_bytes())
+		}
+
+		// Disk image with a single PRG file, named after the output file
+		OutputFormat::D64 => {
+			let prg = if end > start {
+				write_bin(&result.image(), start, end, true)
+			} else {
+				vec![0u8, 0]
+			};
+			cbm::write_d64(&name, &prg)
+		}
+
+		// Container formats built from the written segments
+		OutputFormat::Dos33 | OutputFormat::Prodos | OutputFormat::Xex | OutputFormat::Ssd => {
+			let segments = clip_segments(result.slices(), start, end);
+			let entry = options.entry.or_else(|| segments.first().map(|v| v.0));
+			match format {
+				OutputFormat::Dos33 => apple::write_dos33(&segments),
+				OutputFormat::Prodos => Ok(apple::write_prodos(&name, &segments)),
+				OutputFormat::Xex => Ok(atari::write_xex(&segments, entry, options.init)),
+				_ => acorn::write_ssd(&name, &acorn::segment_files(&name, &segments, entry))
+			}
+		}
+
+		OutputFormat::Ines | OutputFormat::O65 => Err(String::from("iNES and o65 output cannot be written from a single result"))
+	}
+}
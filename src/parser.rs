@@ -6,6 +6,8 @@
 // Created on October 19 2020.
 //
 
+use std::fmt;
+
 use crate::lexer::Lexer;
 use crate::lexer::TokenValue;
 
@@ -19,7 +21,7 @@ pub enum ImmediateValue {
 }
 
 // Represents an address
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Address {
 	Literal(u16),
 	Label(String),
@@ -47,6 +49,96 @@ pub struct Instruction {
 	pub addr_mode: AddressingMode,
 }
 
+impl fmt::Display for ImmediateValue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ImmediateValue::Literal(n) => write!(f, "${:02X}", n),
+			ImmediateValue::Label(label) => write!(f, "{}", label),
+			ImmediateValue::LowByte(label) => write!(f, "<{}", label),
+			ImmediateValue::HighByte(label) => write!(f, ">{}", label),
+		}
+	}
+}
+
+impl fmt::Display for Address {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Address::Literal(n @ 0..=255) => write!(f, "${:02X}", n),
+			Address::Literal(n) => write!(f, "${:04X}", n),
+			Address::Label(label) => write!(f, "{}", label),
+		}
+	}
+}
+
+impl AddressingMode {
+	// Gets the prefix that forces the operand size, for zero page labels and absolute addresses below $100
+	fn size_prefix(&self) -> &'static str {
+		match self {
+			AddressingMode::ZeroPage(Address::Label(_))
+			| AddressingMode::ZeroPageX(Address::Label(_))
+			| AddressingMode::ZeroPageY(Address::Label(_)) => "z:",
+			AddressingMode::Absolute(Address::Literal(0..=255))
+			| AddressingMode::AbsoluteX(Address::Literal(0..=255))
+			| AddressingMode::AbsoluteY(Address::Literal(0..=255)) => "a:",
+			_ => "",
+		}
+	}
+}
+
+// Formats the operand of an instruction the way the parser reads it
+impl fmt::Display for AddressingMode {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			AddressingMode::Implicit => Ok(()),
+			AddressingMode::Immediate(i) => write!(f, "#{}", i),
+			AddressingMode::ZeroPage(a) | AddressingMode::Absolute(a) => write!(f, "{}{}", self.size_prefix(), a),
+			AddressingMode::ZeroPageX(a) | AddressingMode::AbsoluteX(a) => write!(f, "{}{}, x", self.size_prefix(), a),
+			AddressingMode::ZeroPageY(a) | AddressingMode::AbsoluteY(a) => write!(f, "{}{}, y", self.size_prefix(), a),
+			AddressingMode::IndirectX(a) => write!(f, "({}, x)", a),
+			AddressingMode::IndirectY(a) => write!(f, "({}), y", a),
+			AddressingMode::Indirect(a) => write!(f, "({})", a),
+		}
+	}
+}
+
+impl fmt::Display for Instruction {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.addr_mode {
+			AddressingMode::Implicit => write!(f, "{}", self.opcode),
+			_ => write!(f, "{} {}", self.opcode, self.addr_mode),
+		}
+	}
+}
+
+// Represents the nametable mirroring of an iNES ROM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirroring {
+	Horizontal,
+	Vertical,
+	FourScreen,
+}
+
+// Represents a field of an iNES header
+#[derive(Debug)]
+pub enum InesField {
+	Mapper(u16),
+	Submapper(u8),
+	Mirroring(Mirroring),
+	PrgBanks(u16),
+	ChrBanks(u16),
+	Battery,
+	Nes2,
+}
+
+// Represents a Commodore machine that a BASIC stub is generated for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Machine {
+	C64,
+	Vic20,
+	Vic20Expanded3k,
+	Vic20Expanded8k,
+}
+
 #[derive(Debug)]
 pub enum Pragma {
 	Byte(u8),
@@ -54,7 +146,64 @@ pub enum Pragma {
 	Word(Address),
 	Origin(Address),
 	Define(String, Address),
+	Set(String, Address),
 	Include(String),
+	Incbin(String),
+	Ines(InesField),
+	Bank(u8, Option<u16>),
+	ChrBank(u8),
+	BasicStub(Machine, u16),
+	Segment(String),
+	Res(u16),
+	Export(Vec<String>),
+	Import(Vec<String>),
+	Global(Vec<String>),
+	CyclesBegin(Option<u16>),
+	CyclesEnd,
+	AssertSamePage(String),
+	PageAlign,
+	Assert(Address, Comparison, Address, Option<String>),
+	Error(String),
+	Warning(String),
+	Print(String),
+}
+
+// How two values are compared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+	Equal,
+	NotEqual,
+	Less,
+	LessEqual,
+	Greater,
+	GreaterEqual
+}
+
+impl Comparison {
+	// Returns whether the comparison holds between two values
+	pub fn test<T: PartialOrd>(&self, a: T, b: T) -> bool {
+		match self {
+			Comparison::Equal => a == b,
+			Comparison::NotEqual => a != b,
+			Comparison::Less => a < b,
+			Comparison::LessEqual => a <= b,
+			Comparison::Greater => a > b,
+			Comparison::GreaterEqual => a >= b
+		}
+	}
+}
+
+impl fmt::Display for Comparison {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", match self {
+			Comparison::Equal => "=",
+			Comparison::NotEqual => "!=",
+			Comparison::Less => "<",
+			Comparison::LessEqual => "<=",
+			Comparison::Greater => ">",
+			Comparison::GreaterEqual => ">="
+		})
+	}
 }
 
 #[derive(Debug)]
@@ -68,6 +217,7 @@ pub enum LineValue {
 #[derive(Debug)]
 pub struct Line {
 	pub lino: u32,
+	pub col: u32,
 	pub label: String,
 	pub value: LineValue,
 }
@@ -80,6 +230,14 @@ pub struct ParseError {
 	pub message: String,
 }
 
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}: {}", self.filename, self.lino, self.message)
+	}
+}
+
+impl std::error::Error for ParseError {}
+
 impl ParseError {
 	pub fn new<T>(filename: String, lino: u32, message: &str) -> Result<T, ParseError> {
 		Err(ParseError {
@@ -260,7 +418,23 @@ fn parse_operand(lexer: &mut Lexer, instr: &mut Instruction) -> Result<(), Parse
 		}
 
 	// Everything else
-	} else if let Some(token) = lexer.peek() {
+	} else if let Some(mut token) = lexer.peek() {
+		// Forced operand sizes (lda a:$12; lda z:label)
+		let mut zero_page = None;
+		if let TokenValue::Symbol(s) = &token.value {
+			let state = lexer.save();
+			lexer.next();
+			if let (Some(_), "a" | "A" | "z" | "Z") = (optional!(lexer, TokenValue::Colon), s.as_str()) {
+				zero_page = Some(s == "z" || s == "Z");
+				token = match lexer.peek() {
+					Some(token) => token,
+					None => return ParseError::new_from_lexer(lexer, "Missing address"),
+				};
+			} else {
+				lexer.recall(state);
+			}
+		}
+
 		// Get address
 		let addr = match token.value {
 			TokenValue::Bin(n) => Address::Literal(n),
@@ -268,10 +442,19 @@ fn parse_operand(lexer: &mut Lexer, instr: &mut Instruction) -> Result<(), Parse
 			TokenValue::Dec(n) => Address::Literal(n),
 			TokenValue::Hex(n) => Address::Literal(n),
 			TokenValue::Symbol(s) => Address::Label(s),
+			_ if zero_page.is_some() => return ParseError::new_from_lexer(lexer, "Expected address"),
 			_ => return Ok(()),
 		};
 		lexer.next();
 
+		// Small literals are zero page unless forced otherwise, and labels are absolute
+		let zero_page = zero_page.unwrap_or(matches!(addr, Address::Literal(0..=255)));
+		if zero_page {
+			if let Address::Literal(n) = addr {
+				check_overflow(lexer, n)?;
+			}
+		}
+
 		// X and Y index addressing
 		instr.addr_mode = if let Some(_) = optional!(lexer, TokenValue::Comma) {
 			let reg = unwrap_token!(
@@ -282,7 +465,7 @@ fn parse_operand(lexer: &mut Lexer, instr: &mut Instruction) -> Result<(), Parse
 			// X indexing addressing
 			if reg == "x" || reg == "X" {
 				// Zero page (lda $00, x)
-				if let Address::Literal(0..=255) = addr {
+				if zero_page {
 					AddressingMode::ZeroPageX(addr)
 
 				// Absolute (lda $1234, x; lda label, x)
@@ -293,7 +476,7 @@ fn parse_operand(lexer: &mut Lexer, instr: &mut Instruction) -> Result<(), Parse
 			// Y indexing addressing
 			} else if reg == "y" || reg == "Y" {
 				// Zero page (lda $00, y)
-				if let Address::Literal(0..=255) = addr {
+				if zero_page {
 					AddressingMode::ZeroPageY(addr)
 
 				// Absolute (lda $1234, y; lda label, y)
@@ -307,7 +490,7 @@ fn parse_operand(lexer: &mut Lexer, instr: &mut Instruction) -> Result<(), Parse
 			}
 		} else {
 			// Zero page addressing (lda $00)
-			if let Address::Literal(0..=255) = addr {
+			if zero_page {
 				AddressingMode::ZeroPage(addr)
 
 			// Absolute addressing (lda $1234; lda label)
@@ -320,6 +503,14 @@ fn parse_operand(lexer: &mut Lexer, instr: &mut Instruction) -> Result<(), Parse
 	Ok(())
 }
 
+// The names of every pragma, without the leading dot
+pub const PRAGMAS: &[&str] = &[
+	"byte", "bytes", "word", "origin", "define", "set", "include", "incbin",
+	"ines", "bank", "chr_bank", "basic_stub", "segment", "res", "export", "import",
+	"global", "cycles_begin", "cycles_end", "assert_same_page", "page_align", "assert", "error", "warning",
+	"print"
+];
+
 // Parse a pragma
 fn parse_pragma(lexer: &mut Lexer) -> Result<Pragma, ParseError> {
 	// Get the name of the pragma
@@ -440,6 +631,17 @@ fn parse_pragma(lexer: &mut Lexer) -> Result<Pragma, ParseError> {
 			}
 		}
 
+		"set" => {
+			// Consume variable name
+			let label = unwrap_token!(
+				consume!(lexer, TokenValue::Symbol(_), "Expected variable name after .set")?,
+				Symbol
+			);
+
+			// Consume value
+			Ok(Pragma::Set(label, parse_set_value(lexer)?))
+		}
+
 		"include" => {
 			// Consume include path as string
 			if let Some(token) = optional!(lexer, TokenValue::String(_)) {
@@ -449,11 +651,226 @@ fn parse_pragma(lexer: &mut Lexer) -> Result<Pragma, ParseError> {
 			}
 		}
 
+		"incbin" => {
+			// Consume binary file path as string
+			if let Some(token) = optional!(lexer, TokenValue::String(_)) {
+				Ok(Pragma::Incbin(unwrap_token!(token, String)))
+			} else {
+				ParseError::new_from_lexer(lexer, "Expected string with binary file path")
+			}
+		}
+
+		"ines" => Ok(Pragma::Ines(parse_ines_field(lexer)?)),
+
+		"bank" => {
+			// Consume bank number and optional address
+			match optional_number(lexer) {
+				Some(n) if n < 256 => {
+					optional!(lexer, TokenValue::Comma);
+					Ok(Pragma::Bank(n as u8, optional_number(lexer)))
+				}
+				_ => ParseError::new_from_lexer(lexer, "Expected PRG bank number after .bank"),
+			}
+		}
+
+		"chr_bank" => {
+			// Consume bank number
+			match optional_number(lexer) {
+				Some(n) if n < 256 => Ok(Pragma::ChrBank(n as u8)),
+				_ => ParseError::new_from_lexer(lexer, "Expected CHR bank number after .chr_bank"),
+			}
+		}
+
+		"basic_stub" => {
+			// Consume optional machine
+			let machine = match optional!(lexer, TokenValue::Symbol(_)) {
+				Some(token) => match unwrap_token!(token, Symbol).as_str() {
+					"c64" => Machine::C64,
+					"vic20" => Machine::Vic20,
+					"vic20_3k" => Machine::Vic20Expanded3k,
+					"vic20_8k" => Machine::Vic20Expanded8k,
+					_ => return ParseError::new_from_lexer(lexer, "Expected c64, vic20, vic20_3k, or vic20_8k"),
+				},
+				None => Machine::C64,
+			};
+
+			// Consume optional line number
+			optional!(lexer, TokenValue::Comma);
+			match optional_number(lexer) {
+				Some(n) if n > 63999 => ParseError::new_from_lexer(lexer, "BASIC line numbers must be at most 63999"),
+				Some(n) => Ok(Pragma::BasicStub(machine, n)),
+				None => Ok(Pragma::BasicStub(machine, 10)),
+			}
+		}
+
+		"segment" => {
+			// Consume segment name as string
+			if let Some(token) = optional!(lexer, TokenValue::String(_)) {
+				Ok(Pragma::Segment(unwrap_token!(token, String)))
+			} else {
+				ParseError::new_from_lexer(lexer, "Expected string with segment name")
+			}
+		}
+
+		"res" => {
+			// Consume number of bytes to reserve
+			match optional_number(lexer) {
+				Some(n) => Ok(Pragma::Res(n)),
+				None => ParseError::new_from_lexer(lexer, "Expected number of bytes after .res"),
+			}
+		}
+
+		"export" => Ok(Pragma::Export(parse_symbol_list(lexer, "Expected symbol names after .export")?)),
+		"import" => Ok(Pragma::Import(parse_symbol_list(lexer, "Expected symbol names after .import")?)),
+		"global" => Ok(Pragma::Global(parse_symbol_list(lexer, "Expected symbol names after .global")?)),
+
+		// Consume optional cycle budget
+		"cycles_begin" => Ok(Pragma::CyclesBegin(optional_number(lexer))),
+		"cycles_end" => Ok(Pragma::CyclesEnd),
+
+		"assert_same_page" => {
+			// Consume label
+			let label = consume!(lexer, TokenValue::Symbol(_), "Expected label after .assert_same_page")?;
+			Ok(Pragma::AssertSamePage(unwrap_token!(label, Symbol)))
+		}
+
+		"page_align" => Ok(Pragma::PageAlign),
+
+		"assert" => {
+			// Consume comparison and optional message
+			let left = parse_set_value(lexer)?;
+			let comparison = parse_comparison(lexer)?;
+			let right = parse_set_value(lexer)?;
+			let message = match optional!(lexer, TokenValue::Comma) {
+				Some(_) => Some(unwrap_token!(consume!(lexer, TokenValue::String(_), "Expected message after ','")?, String)),
+				None => None,
+			};
+			Ok(Pragma::Assert(left, comparison, right, message))
+		}
+
+		"error" => Ok(Pragma::Error(parse_message(lexer, "Expected message after .error")?)),
+		"warning" => Ok(Pragma::Warning(parse_message(lexer, "Expected message after .warning")?)),
+		"print" => Ok(Pragma::Print(parse_message(lexer, "Expected message after .print")?)),
+
 		// Invalid pragma
 		_ => ParseError::new_from_lexer(lexer, "Invalid pragma"),
 	}
 }
 
+// Consumes a string with a diagnostic message
+fn parse_message(lexer: &mut Lexer, err: &str) -> Result<String, ParseError> {
+	Ok(unwrap_token!(consume!(lexer, TokenValue::String(_), err)?, String))
+}
+
+// Parses a comparison (=, <, <=, >, or >=)
+pub fn parse_comparison(lexer: &mut Lexer) -> Result<Comparison, ParseError> {
+	let comparison = match lexer.next().map(|v| v.value) {
+		Some(TokenValue::Equals) => return Ok(Comparison::Equal),
+		Some(TokenValue::Bang) => {
+			consume!(lexer, TokenValue::Equals, "Expected '=' after '!'")?;
+			return Ok(Comparison::NotEqual);
+		}
+		Some(TokenValue::LT) => Comparison::Less,
+		Some(TokenValue::GT) => Comparison::Greater,
+		_ => return ParseError::new_from_lexer(lexer, "Expected '=', '!=', '<', '<=', '>', or '>='")
+	};
+
+	if let Some(TokenValue::Equals) = lexer.peek().map(|v| v.value) {
+		lexer.next();
+		return Ok(match comparison {
+			Comparison::Less => Comparison::LessEqual,
+			_ => Comparison::GreaterEqual
+		});
+	}
+	Ok(comparison)
+}
+
+// Consumes a comma separated list of symbols
+fn parse_symbol_list(lexer: &mut Lexer, err: &str) -> Result<Vec<String>, ParseError> {
+	let mut symbols = vec![unwrap_token!(consume!(lexer, TokenValue::Symbol(_), err)?, Symbol)];
+	while optional!(lexer, TokenValue::Comma).is_some() {
+		symbols.push(unwrap_token!(consume!(lexer, TokenValue::Symbol(_), err)?, Symbol));
+	}
+	Ok(symbols)
+}
+
+// Optionally consumes a number literal
+fn optional_number(lexer: &mut Lexer) -> Option<u16> {
+	let n = match lexer.peek()?.value {
+		TokenValue::Bin(n) => n,
+		TokenValue::Oct(n) => n,
+		TokenValue::Dec(n) => n,
+		TokenValue::Hex(n) => n,
+		_ => return None,
+	};
+	lexer.next();
+	Some(n)
+}
+
+// Parses a field of an iNES header (.ines key value)
+fn parse_ines_field(lexer: &mut Lexer) -> Result<InesField, ParseError> {
+	let key = unwrap_token!(
+		consume!(lexer, TokenValue::Symbol(_), "Expected iNES header field after .ines")?,
+		Symbol
+	);
+
+	match key.as_str() {
+		"mapper" => match optional_number(lexer) {
+			Some(n) if n < 4096 => Ok(InesField::Mapper(n)),
+			_ => ParseError::new_from_lexer(lexer, "Expected mapper number from 0 to 4095"),
+		},
+
+		"submapper" => match optional_number(lexer) {
+			Some(n) if n < 16 => Ok(InesField::Submapper(n as u8)),
+			_ => ParseError::new_from_lexer(lexer, "Expected submapper number from 0 to 15"),
+		},
+
+		"prg" => match optional_number(lexer) {
+			Some(n) if n < 4096 => Ok(InesField::PrgBanks(n)),
+			_ => ParseError::new_from_lexer(lexer, "Expected number of 16 KiB PRG banks"),
+		},
+
+		"chr" => match optional_number(lexer) {
+			Some(n) if n < 4096 => Ok(InesField::ChrBanks(n)),
+			_ => ParseError::new_from_lexer(lexer, "Expected number of 8 KiB CHR banks"),
+		},
+
+		"mirroring" => {
+			let mirroring = unwrap_token!(
+				consume!(lexer, TokenValue::Symbol(_), "Expected horizontal, vertical, or four_screen")?,
+				Symbol
+			);
+
+			match mirroring.as_str() {
+				"horizontal" => Ok(InesField::Mirroring(Mirroring::Horizontal)),
+				"vertical" => Ok(InesField::Mirroring(Mirroring::Vertical)),
+				"four_screen" => Ok(InesField::Mirroring(Mirroring::FourScreen)),
+				_ => ParseError::new_from_lexer(lexer, "Expected horizontal, vertical, or four_screen"),
+			}
+		}
+
+		"battery" => Ok(InesField::Battery),
+		"nes2" => Ok(InesField::Nes2),
+		_ => ParseError::new_from_lexer(lexer, "Invalid iNES header field"),
+	}
+}
+
+// Parses the value assigned to a variable by .set or =
+fn parse_set_value(lexer: &mut Lexer) -> Result<Address, ParseError> {
+	if let Some(token) = lexer.next() {
+		match token.value {
+			TokenValue::Bin(n) => Ok(Address::Literal(n)),
+			TokenValue::Oct(n) => Ok(Address::Literal(n)),
+			TokenValue::Dec(n) => Ok(Address::Literal(n)),
+			TokenValue::Hex(n) => Ok(Address::Literal(n)),
+			TokenValue::Symbol(s) => Ok(Address::Label(s)),
+			_ => ParseError::new_from_lexer(lexer, "Expected address or label as variable value"),
+		}
+	} else {
+		ParseError::new_from_lexer(lexer, "Expected address or label as variable value")
+	}
+}
+
 // Parses everything in the line after the label
 fn parse_post_label(lexer: &mut Lexer) -> Result<LineValue, ParseError> {
 	// Parse instruction
@@ -487,30 +904,41 @@ pub fn parse_line(lexer: &mut Lexer) -> Result<Option<Line>, ParseError> {
 	// The line to eventually return
 	let mut line = Line {
 		lino: lexer.get_lino(),
+		col: 0,
 		label: String::from(""),
 		value: LineValue::None,
 	};
 
 	// Stop parsing if there's nothing left
-	if let None = lexer.peek() {
-		return Ok(None);
+	match lexer.peek() {
+		Some(token) => line.col = token.charpos,
+		None => return Ok(None),
 	}
 
 	// First token
 	let state = lexer.save();
 	let label = optional!(lexer, TokenValue::Symbol(_));
 
-	// First token is a label
+	// First token is a label or a variable assignment (name = value)
+	let mut assigned = false;
 	if let Some(label) = label {
 		if let Some(_) = optional!(lexer, TokenValue::Colon) {
 			line.label = unwrap_token!(label, Symbol);
+			if let Some(token) = lexer.peek() {
+				line.col = token.charpos;
+			}
+		} else if let Some(_) = optional!(lexer, TokenValue::Equals) {
+			line.value = LineValue::Pragma(Pragma::Set(unwrap_token!(label, Symbol), parse_set_value(lexer)?));
+			assigned = true;
 		} else {
 			lexer.recall(state);
 		}
 	}
 
 	// Parse everything after the label
-	line.value = parse_post_label(lexer)?;
+	if !assigned {
+		line.value = parse_post_label(lexer)?;
+	}
 
 	// Parse newline if not at eof
 	if let Some(token) = lexer.peek() {
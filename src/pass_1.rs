@@ -7,14 +7,24 @@
 //
 
 use std::collections::HashMap;
-use std::fs;
-
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::path::Path;
+
+use crate::cbm;
+use crate::files::{
+	FileProvider,
+	FileSystem
+};
+use crate::ines::InesHeader;
 use crate::lexer::Lexer;
 use crate::parser;
 use crate::parser::{
 	Address,
 	AddressingMode,
+	Comparison,
 	ImmediateValue,
+	Instruction,
 	LineValue,
 	ParseError,
 	Pragma
@@ -30,33 +40,270 @@ pub enum InstructionArg {
 	ByteLabelLowArg(String),
 	ByteLabelHighArg(String),
 	WordArg(u16),
-	WordLabelArg(String)
+	WordLabelArg(String),
+
+	// A word of data (.word) with the value of a label, emitted without an opcode
+	DataWordLabelArg(String)
+}
+
+// Whether an annotated line is an instruction or data from a pragma
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+	Code,
+	Data
+}
+
+// The ROM bank a line is assembled into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bank {
+	// Not in any bank (the whole address space)
+	None,
+
+	// A 16 KiB PRG bank (.bank)
+	Prg(u8),
+
+	// An 8 KiB CHR bank (.chr_bank)
+	Chr(u8)
 }
 
 // An annotated line of assembly
 #[derive(Debug)]
 pub struct AnnotatedLine {
 	pub lino: u32,
+	pub col: u32,
+	pub kind: LineKind,
+	pub bank: Bank,
+	pub segment: Option<usize>,
 	pub addr: u16,
 	pub opcode: u8,
 	pub arg: InstructionArg
 }
 
+impl AnnotatedLine {
+	// Returns the number of bytes the second pass emits for this line
+	pub fn size(&self) -> u16 {
+		match self.arg {
+			// brk is followed by a signature byte
+			InstructionArg::NoArgs if self.kind == LineKind::Code && self.opcode == 0x00 => 2,
+			InstructionArg::NoArgs => 1,
+			InstructionArg::DataWordLabelArg(_) => 2,
+			InstructionArg::ByteArg(_)
+			| InstructionArg::ByteLabelArg(_)
+			| InstructionArg::RelativeLabelArg(_)
+			| InstructionArg::ByteLabelLowArg(_)
+			| InstructionArg::ByteLabelHighArg(_) => 2,
+			InstructionArg::WordArg(_) | InstructionArg::WordLabelArg(_) => 3
+		}
+	}
+}
+
+// The kind of a symbol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+	// Defined with `label:`
+	Label,
+
+	// Defined with .define
+	Constant,
+
+	// Defined with .set or `name = value`; may be reassigned
+	Variable,
+
+	// Declared with .import or .global and defined by another file; resolved by the linker
+	Import
+}
+
+// Where and how a symbol was defined
+#[derive(Debug, Clone)]
+pub struct SymbolDef {
+	pub filename: String,
+	pub lino: u32,
+	pub kind: SymbolKind,
+
+	// The named segment the value is relative to, if any
+	pub segment: Option<usize>,
+
+	// The ROM bank a label is in, or the bank of the label a constant was set to
+	pub bank: Bank
+}
+
+// A segment switched to with .segment, whose address is assigned by the linker
+#[derive(Debug, Clone)]
+pub struct NamedSegment {
+	pub name: String,
+	pub size: u16,
+
+	// The alignment the linker must place the segment at (256 if it uses .page_align, otherwise 1)
+	pub align: u16
+}
+
+// A file included with .include
+#[derive(Debug)]
+pub struct IncludedFile {
+	pub lino: u32,
+	pub result: FirstPassResult
+}
+
+// A region of straight-line code between .cycles_begin and .cycles_end
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleRegion {
+	pub lino: u32,
+	pub end_lino: u32,
+	pub budget: Option<u16>
+}
+
+// A check that a label is on the same page as the address of a .assert_same_page line
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageAssert {
+	pub lino: u32,
+	pub addr: u16,
+	pub segment: Option<usize>,
+	pub label: String
+}
+
+// A .assert check, evaluated once every symbol is resolved
+#[derive(Debug, Clone)]
+pub struct Assert {
+	pub filename: String,
+	pub lino: u32,
+	pub left: Address,
+	pub comparison: Comparison,
+	pub right: Address,
+	pub message: Option<String>
+}
+
+// The kind of a diagnostic emitted by the source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticKind {
+	Warning,
+	Print
+}
+
+// A message emitted with .warning or .print
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+	pub filename: String,
+	pub lino: u32,
+	pub kind: DiagnosticKind,
+	pub message: String
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.kind {
+			DiagnosticKind::Warning => write!(f, "Warning on {}:{}: {}", self.filename, self.lino, self.message),
+			DiagnosticKind::Print => write!(f, "{}:{}: {}", self.filename, self.lino, self.message)
+		}
+	}
+}
+
 // The result of the first pass
 #[derive(Debug)]
 pub struct FirstPassResult {
 	pub filename: String,
+	pub source: String,
 	pub lines: Vec<AnnotatedLine>,
-	pub symbol_table: HashMap<String, u16>
+	pub symbol_table: HashMap<String, u16>,
+	pub symbol_defs: HashMap<String, SymbolDef>,
+	pub includes: Vec<IncludedFile>,
+	pub segments: Vec<NamedSegment>,
+	pub exports: Vec<String>,
+	pub cycle_regions: Vec<CycleRegion>,
+	pub page_asserts: Vec<PageAssert>,
+	pub asserts: Vec<Assert>,
+	pub diagnostics: Vec<Diagnostic>,
+	pub ines: Option<InesHeader>
 }
 
-// Adds a symbol to the symbol table
-fn add_symbol(symbol_table: &mut HashMap<String, u16>, key: String, value: u16) {
-	if symbol_table.contains_key(&key) {
-		panic!("Repeated symbol");
-	} else {
-		symbol_table.insert(key, value);
+impl FirstPassResult {
+	// Moves every named segment to the given base address, one per entry in segments
+	pub fn relocate(&mut self, bases: &[u16]) {
+		for line in self.lines.iter_mut() {
+			if let Some(i) = line.segment {
+				line.addr = line.addr.wrapping_add(bases[i]);
+			}
+		}
+
+		for assert in self.page_asserts.iter_mut() {
+			if let Some(i) = assert.segment {
+				assert.addr = assert.addr.wrapping_add(bases[i]);
+			}
+		}
+
+		for (key, def) in self.symbol_defs.iter() {
+			if let Some(i) = def.segment {
+				if let Some(v) = self.symbol_table.get_mut(key) {
+					*v = v.wrapping_add(bases[i]);
+				}
+			}
+		}
+	}
+}
+
+// Adds a symbol definition, erroring if it was already defined (unless both definitions are variables)
+fn add_symbol_def(symbol_defs: &mut HashMap<String, SymbolDef>, key: String, def: SymbolDef) -> Result<(), ParseError> {
+	if let Some(prev) = symbol_defs.get(&key) {
+		if prev.kind != SymbolKind::Variable || def.kind != SymbolKind::Variable {
+			return ParseError::new(def.filename, def.lino, &format!("Symbol '{}' redefined (first defined at {}:{})", key, prev.filename, prev.lino));
+		}
 	}
+
+	symbol_defs.insert(key, def);
+	Ok(())
+}
+
+// Adds a symbol to the symbol table, erroring if it was already defined (unless both definitions are variables)
+fn add_symbol(symbol_table: &mut HashMap<String, u16>, symbol_defs: &mut HashMap<String, SymbolDef>, key: String, value: u16, def: SymbolDef) -> Result<(), ParseError> {
+	add_symbol_def(symbol_defs, key.clone(), def)?;
+	symbol_table.insert(key, value);
+	Ok(())
+}
+
+// Defines a constant or variable with the value of a literal or previously defined label
+fn define_symbol(lexer: &Lexer, symbol_table: &mut HashMap<String, u16>, symbol_defs: &mut HashMap<String, SymbolDef>, lino: u32, label: String, value: Address, kind: SymbolKind) -> Result<(), ParseError> {
+	let (value, segment, bank) = match value {
+		// Set label to the value of another label, which is relative to the same segment
+		Address::Label(s) => {
+			match symbol_table.get(&s) {
+				Some(v) => (*v, symbol_defs[&s].segment, symbol_defs[&s].bank),
+				None => return ParseError::new_from_lexer(lexer, &format!("Setting label {} to value of undefined label {}", label, s))
+			}
+		}
+
+		// Set label to a value
+		Address::Literal(n) => (n, None, Bank::None)
+	};
+
+	let def = SymbolDef {
+		filename: lexer.get_filename().clone(),
+		lino,
+		kind,
+		segment,
+		bank
+	};
+	add_symbol(symbol_table, symbol_defs, label, value, def)
+}
+
+// Switches back to absolute addressing, saving the address counter of the current named segment
+fn leave_segment(segments: &mut [NamedSegment], segment: &mut Option<usize>, addr: u16) {
+	if let Some(i) = segment.take() {
+		segments[i].size = addr;
+	}
+}
+
+// Replaces references to variables with their current value, since variables may be reassigned later on
+fn resolve_variables(lexer: &Lexer, arg: InstructionArg, symbol_table: &HashMap<String, u16>, symbol_defs: &HashMap<String, SymbolDef>) -> Result<InstructionArg, ParseError> {
+	let is_variable = |label: &String| {
+		matches!(symbol_defs.get(label), Some(SymbolDef { kind: SymbolKind::Variable, .. }))
+	};
+
+	Ok(match arg {
+		InstructionArg::ByteLabelArg(label) if is_variable(&label) => InstructionArg::ByteArg(parser::check_overflow(lexer, symbol_table[&label])?),
+		InstructionArg::ByteLabelLowArg(label) if is_variable(&label) => InstructionArg::ByteArg(symbol_table[&label] as u8),
+		InstructionArg::ByteLabelHighArg(label) if is_variable(&label) => InstructionArg::ByteArg((symbol_table[&label] >> 8) as u8),
+		InstructionArg::WordLabelArg(label) if is_variable(&label) => InstructionArg::WordArg(symbol_table[&label]),
+		arg => arg
+	})
 }
 
 // Opcodes that end with c=01
@@ -388,200 +635,292 @@ macro_rules! opcode_implicit {
 	}};
 }
 
-// Performs the first pass on the code
-pub fn first_pass(lexer: &mut Lexer) -> Result<FirstPassResult, ParseError> {
-	let mut symbol_table = HashMap::new();
-	let mut lines = Vec::new();
-	let mut addr = 0u16;
+// Every opcode mnemonic that encode_instruction accepts
+pub const MNEMONICS: &[&str] = &[
+	"ora", "and", "eor", "adc", "sta", "lda", "cmp", "sbc",
+	"asl", "rol", "lsr", "ror", "stx", "ldx", "dec", "inc",
+	"sty", "ldy", "cpy", "cpx", "bit", "jmp", "brk", "jsr",
+	"bpl", "bmi", "bvc", "bvs", "bcc", "bcs", "bne", "beq",
+	"rti", "rts", "php", "plp", "pha", "pla",
+	"clc", "sec", "cli", "sei", "clv", "cld", "sed",
+	"inx", "dex", "iny", "dey", "tax", "txa", "tay", "tya", "tsx", "txs", "nop"
+];
+
+// Encodes an instruction into the opcode and argument of a line, returning the number of bytes it takes up
+pub fn encode_instruction(lexer: &Lexer, instr: Instruction, line: &mut AnnotatedLine) -> Result<u16, ParseError> {
+	let mut size = 0u16;
+
+	// Match the opcode (aaa_bbb_cc)
+	match instr.opcode.to_lowercase().as_str() {
+		// c=01
+		"ora" => opcode_c_01!(0b000_000_01, line, size, instr, lexer, true ),
+		"and" => opcode_c_01!(0b001_000_01, line, size, instr, lexer, true ),
+		"eor" => opcode_c_01!(0b010_000_01, line, size, instr, lexer, true ),
+		"adc" => opcode_c_01!(0b011_000_01, line, size, instr, lexer, true ),
+		"sta" => opcode_c_01!(0b100_000_01, line, size, instr, lexer, false),
+		"lda" => opcode_c_01!(0b101_000_01, line, size, instr, lexer, true ),
+		"cmp" => opcode_c_01!(0b110_000_01, line, size, instr, lexer, true ),
+		"sbc" => opcode_c_01!(0b111_000_01, line, size, instr, lexer, true ),
+
+		// c=10
+		"asl" => opcode_c_10!(0b000_000_10, line, size, instr, lexer, ZeroPageX, AbsoluteX, false, true , true ),
+		"rol" => opcode_c_10!(0b001_000_10, line, size, instr, lexer, ZeroPageX, AbsoluteX, false, true , true ),
+		"lsr" => opcode_c_10!(0b010_000_10, line, size, instr, lexer, ZeroPageX, AbsoluteX, false, true , true ),
+		"ror" => opcode_c_10!(0b011_000_10, line, size, instr, lexer, ZeroPageX, AbsoluteX, false, true , true ),
+		"stx" => opcode_c_10!(0b100_000_10, line, size, instr, lexer, ZeroPageY, AbsoluteY, false, false, false),
+		"ldx" => opcode_c_10!(0b101_000_10, line, size, instr, lexer, ZeroPageY, AbsoluteY, true , false, true ),
+		"dec" => opcode_c_10!(0b110_000_10, line, size, instr, lexer, ZeroPageX, AbsoluteX, false, false, true ),
+		"inc" => opcode_c_10!(0b111_000_10, line, size, instr, lexer, ZeroPageX, AbsoluteX, false, false, true ),
+
+		// c=00
+		"sty" => opcode_c_00!(0b100_000_00, line, size, instr, lexer, false, true , false),
+		"ldy" => opcode_c_00!(0b101_000_00, line, size, instr, lexer, true , true , true ),
+		"cpy" => opcode_c_00!(0b110_000_00, line, size, instr, lexer, true , false, false),
+		"cpx" => opcode_c_00!(0b111_000_00, line, size, instr, lexer, true , false, false),
+
+		// Bit
+		"bit" => {
+			line.opcode = 0b001_000_00;
+
+			match instr.addr_mode {
+				// bit $zp
+				AddressingMode::ZeroPage(a) => {
+					line.opcode |= 0b000_001_00;
+
+					line.arg = match a {
+						Address::Literal(a) => InstructionArg::ByteArg(parser::check_overflow(lexer, a)?),
+						Address::Label(label) => InstructionArg::ByteLabelArg(label)
+					};
 
-	// Iterate over every line
-	while let Some(line) = parser::parse_line(lexer)? {
-		// Set labels to the current address
-		if line.label != "" {
-			add_symbol(&mut symbol_table, line.label, addr);
-		} else {
-			match line.value {
-				// Deal with instructions
-				LineValue::Instruction(instr) => {
-					let mut line = AnnotatedLine {
-						lino: lexer.get_lino(),
-						addr: addr,
-						opcode: 0b000_000_00,
-						arg: InstructionArg::NoArgs
+					size += 2;
+				}
+
+				// bit $addr
+				AddressingMode::Absolute(a) => {
+					line.opcode |= 0b000_011_00;
+
+					line.arg = match a {
+						Address::Literal(a) => InstructionArg::WordArg(a),
+						Address::Label(label) => InstructionArg::WordLabelArg(label)
 					};
 
-					// Match the opcode (aaa_bbb_cc)
-					match instr.opcode.to_lowercase().as_str() {
-						// c=01
-						"ora" => opcode_c_01!(0b000_000_01, line, addr, instr, lexer, true ),
-						"and" => opcode_c_01!(0b001_000_01, line, addr, instr, lexer, true ),
-						"eor" => opcode_c_01!(0b010_000_01, line, addr, instr, lexer, true ),
-						"adc" => opcode_c_01!(0b011_000_01, line, addr, instr, lexer, true ),
-						"sta" => opcode_c_01!(0b100_000_01, line, addr, instr, lexer, false),
-						"lda" => opcode_c_01!(0b101_000_01, line, addr, instr, lexer, true ),
-						"cmp" => opcode_c_01!(0b110_000_01, line, addr, instr, lexer, true ),
-						"sbc" => opcode_c_01!(0b111_000_01, line, addr, instr, lexer, true ),
-
-						// c=10
-						"asl" => opcode_c_10!(0b000_000_10, line, addr, instr, lexer, ZeroPageX, AbsoluteX, false, true , true ),
-						"rol" => opcode_c_10!(0b001_000_10, line, addr, instr, lexer, ZeroPageX, AbsoluteX, false, true , true ),
-						"lsr" => opcode_c_10!(0b010_000_10, line, addr, instr, lexer, ZeroPageX, AbsoluteX, false, true , true ),
-						"ror" => opcode_c_10!(0b011_000_10, line, addr, instr, lexer, ZeroPageX, AbsoluteX, false, true , true ),
-						"stx" => opcode_c_10!(0b100_000_10, line, addr, instr, lexer, ZeroPageY, AbsoluteY, false, false, false),
-						"ldx" => opcode_c_10!(0b101_000_10, line, addr, instr, lexer, ZeroPageY, AbsoluteY, true , false, true ),
-						"dec" => opcode_c_10!(0b110_000_10, line, addr, instr, lexer, ZeroPageX, AbsoluteX, false, false, true ),
-						"inc" => opcode_c_10!(0b111_000_10, line, addr, instr, lexer, ZeroPageX, AbsoluteX, false, false, true ),
-
-						// c=00
-						"sty" => opcode_c_00!(0b100_000_00, line, addr, instr, lexer, false, true , false),
-						"ldy" => opcode_c_00!(0b101_000_00, line, addr, instr, lexer, true , true , true ),
-						"cpy" => opcode_c_00!(0b110_000_00, line, addr, instr, lexer, true , false, false),
-						"cpx" => opcode_c_00!(0b111_000_00, line, addr, instr, lexer, true , false, false),
-
-						// Bit
-						"bit" => {
-							line.opcode = 0b001_000_00;
-
-							match instr.addr_mode {
-								// bit $zp
-								AddressingMode::ZeroPage(a) => {
-									line.opcode |= 0b000_001_00;
-
-									line.arg = match a {
-										Address::Literal(a) => InstructionArg::ByteArg(parser::check_overflow(lexer, a)?),
-										Address::Label(label) => InstructionArg::ByteLabelArg(label)
-									};
-
-									addr += 2;
-								}
+					size += 3;
+				}
 
-								// bit $addr
-								AddressingMode::Absolute(a) => {
-									line.opcode |= 0b000_011_00;
+				_ => return ParseError::new_from_lexer(lexer, &format!("Invalid argument for opcode '{}'", instr.opcode))
+			}
+		}
 
-									line.arg = match a {
-										Address::Literal(a) => InstructionArg::WordArg(a),
-										Address::Label(label) => InstructionArg::WordLabelArg(label)
-									};
+		// Jump
+		"jmp" => {
+			match instr.addr_mode {
+				// jmp $addr
+				AddressingMode::Absolute(a) => {
+					line.opcode = 0x4C;
 
-									addr += 3;
-								}
+					line.arg = match a {
+						Address::Literal(a) => InstructionArg::WordArg(a),
+						Address::Label(label) => InstructionArg::WordLabelArg(label)
+					};
 
-								_ => return ParseError::new_from_lexer(&lexer, &format!("Invalid argument for opcode '{}'", instr.opcode))
-							}
-						}
+					size += 3;
+				}
 
-						// Jump
-						"jmp" => {
-							match instr.addr_mode {
-								// jmp $addr
-								AddressingMode::Absolute(a) => {
-									line.opcode = 0x4C;
+				// jmp ($addr)
+				AddressingMode::Indirect(a) => {
+					line.opcode = 0x6C;
 
-									line.arg = match a {
-										Address::Literal(a) => InstructionArg::WordArg(a),
-										Address::Label(label) => InstructionArg::WordLabelArg(label)
-									};
+					line.arg = match a {
+						Address::Literal(a) => InstructionArg::WordArg(a),
+						Address::Label(label) => InstructionArg::WordLabelArg(label)
+					};
 
-									addr += 3;
-								}
+					size += 3;
+				}
 
-								// jmp ($addr)
-								AddressingMode::Indirect(a) => {
-									line.opcode = 0x6C;
+				_ => return ParseError::new_from_lexer(lexer, &format!("Invalid argument for opcode '{}'", instr.opcode))
+			}
+		}
 
-									line.arg = match a {
-										Address::Literal(a) => InstructionArg::WordArg(a),
-										Address::Label(label) => InstructionArg::WordLabelArg(label)
-									};
+		// Software interrupt
+		"brk" => {
+			line.opcode = 0x00;
+			size += 2;
 
-									addr += 3;
-								}
+			match instr.addr_mode {
+				AddressingMode::Implicit => {}
+				_ => return ParseError::new_from_lexer(lexer, &format!("Invalid argument for opcode '{}'", instr.opcode))
+			}
+		}
 
-								_ => return ParseError::new_from_lexer(&lexer, &format!("Invalid argument for opcode '{}'", instr.opcode))
-							}
-						}
+		// Jump to subroutine
+		"jsr" => {
+			line.opcode = 0x20;
 
-						// Software interrupt
-						"brk" => {
-							line.opcode = 0x00;
-							addr += 2;
+			match instr.addr_mode {
+				AddressingMode::Absolute(a) => {
+					line.arg = match a {
+						Address::Literal(a) => InstructionArg::WordArg(a),
+						Address::Label(label) => InstructionArg::WordLabelArg(label)
+					}
+				}
 
-							match instr.addr_mode {
-								AddressingMode::Implicit => {}
-								_ => return ParseError::new_from_lexer(&lexer, &format!("Invalid argument for opcode '{}'", instr.opcode))
-							}
-						}
+				_ => return ParseError::new_from_lexer(lexer, &format!("Invalid argument for opcode '{}'", instr.opcode))
+			}
 
-						// Jump to subroutine
-						"jsr" => {
-							line.opcode = 0x20;
+			size += 3;
+		}
 
-							match instr.addr_mode {
-								AddressingMode::Absolute(a) => {
-									line.arg = match a {
-										Address::Literal(a) => InstructionArg::WordArg(a),
-										Address::Label(label) => InstructionArg::WordLabelArg(label)
-									}
-								}
+		// Branching instructions
+		"bpl" => opcode_branch!(0x10, line, size, instr, lexer),
+		"bmi" => opcode_branch!(0x30, line, size, instr, lexer),
+		"bvc" => opcode_branch!(0x50, line, size, instr, lexer),
+		"bvs" => opcode_branch!(0x70, line, size, instr, lexer),
+		"bcc" => opcode_branch!(0x90, line, size, instr, lexer),
+		"bcs" => opcode_branch!(0xB0, line, size, instr, lexer),
+		"bne" => opcode_branch!(0xD0, line, size, instr, lexer),
+		"beq" => opcode_branch!(0xF0, line, size, instr, lexer),
+
+		// Opcodes that are a single byte
+		// Returns
+		"rti" => opcode_implicit!(0x40, line, size, instr, lexer),
+		"rts" => opcode_implicit!(0x60, line, size, instr, lexer),
+
+		// Stack operations
+		"php" => opcode_implicit!(0x08, line, size, instr, lexer),
+		"plp" => opcode_implicit!(0x28, line, size, instr, lexer),
+		"pha" => opcode_implicit!(0x48, line, size, instr, lexer),
+		"pla" => opcode_implicit!(0x68, line, size, instr, lexer),
+
+		// Flag setting/clearing
+		"clc" => opcode_implicit!(0x18, line, size, instr, lexer),
+		"sec" => opcode_implicit!(0x38, line, size, instr, lexer),
+		"cli" => opcode_implicit!(0x58, line, size, instr, lexer),
+		"sei" => opcode_implicit!(0x78, line, size, instr, lexer),
+		"clv" => opcode_implicit!(0xB8, line, size, instr, lexer),
+		"cld" => opcode_implicit!(0xD8, line, size, instr, lexer),
+		"sed" => opcode_implicit!(0xF8, line, size, instr, lexer),
+
+		// Incrementing/decrementing
+		"inx" => opcode_implicit!(0xE8, line, size, instr, lexer),
+		"dex" => opcode_implicit!(0xCA, line, size, instr, lexer),
+		"iny" => opcode_implicit!(0xC8, line, size, instr, lexer),
+		"dey" => opcode_implicit!(0x88, line, size, instr, lexer),
+
+		// Transfering registers
+		"tax" => opcode_implicit!(0xAA, line, size, instr, lexer),
+		"txa" => opcode_implicit!(0x8A, line, size, instr, lexer),
+		"tay" => opcode_implicit!(0xA8, line, size, instr, lexer),
+		"tya" => opcode_implicit!(0x98, line, size, instr, lexer),
+		"tsx" => opcode_implicit!(0xBA, line, size, instr, lexer),
+		"txs" => opcode_implicit!(0x9A, line, size, instr, lexer),
+
+		// No operation
+		"nop" => opcode_implicit!(0xEA, line, size, instr, lexer),
+
+		// Invalid opcode
+		_ => return ParseError::new_from_lexer(lexer, &format!("Invalid opcode '{}'", instr.opcode))
+	}
 
-								_ => return ParseError::new_from_lexer(&lexer, &format!("Invalid argument for opcode '{}'", instr.opcode))
-							}
+	Ok(size)
+}
 
-							addr += 3;
-						}
+// Moves the address counter past bytes that were emitted or reserved, erroring if they run past $FFFF
+// wrapped is set once the counter reaches the end of memory, so nothing more can be emitted until it is moved
+fn advance(lexer: &Lexer, addr: &mut u16, wrapped: &mut bool, size: u16) -> Result<(), ParseError> {
+	if size == 0 {
+		return Ok(());
+	} else if *wrapped || *addr as u32 + size as u32 > 0x10000 {
+		return ParseError::new_from_lexer(lexer, "Code or data runs past $FFFF");
+	}
 
-						// Branching instructions
-						"bpl" => opcode_branch!(0x10, line, addr, instr, lexer),
-						"bmi" => opcode_branch!(0x30, line, addr, instr, lexer),
-						"bvc" => opcode_branch!(0x50, line, addr, instr, lexer),
-						"bvs" => opcode_branch!(0x70, line, addr, instr, lexer),
-						"bcc" => opcode_branch!(0x90, line, addr, instr, lexer),
-						"bcs" => opcode_branch!(0xB0, line, addr, instr, lexer),
-						"bne" => opcode_branch!(0xD0, line, addr, instr, lexer),
-						"beq" => opcode_branch!(0xF0, line, addr, instr, lexer),
-
-						// Opcodes that are a single byte
-						// Returns
-						"rti" => opcode_implicit!(0x40, line, addr, instr, lexer),
-						"rts" => opcode_implicit!(0x60, line, addr, instr, lexer),
-
-						// Stack operations
-						"php" => opcode_implicit!(0x08, line, addr, instr, lexer),
-						"plp" => opcode_implicit!(0x28, line, addr, instr, lexer),
-						"pha" => opcode_implicit!(0x48, line, addr, instr, lexer),
-						"pla" => opcode_implicit!(0x68, line, addr, instr, lexer),
-
-						// Flag setting/clearing
-						"clc" => opcode_implicit!(0x18, line, addr, instr, lexer),
-						"sec" => opcode_implicit!(0x38, line, addr, instr, lexer),
-						"cli" => opcode_implicit!(0x58, line, addr, instr, lexer),
-						"sei" => opcode_implicit!(0x78, line, addr, instr, lexer),
-						"clv" => opcode_implicit!(0xB8, line, addr, instr, lexer),
-						"cld" => opcode_implicit!(0xD8, line, addr, instr, lexer),
-						"sed" => opcode_implicit!(0xF8, line, addr, instr, lexer),
-
-						// Incrementing/decrementing
-						"inx" => opcode_implicit!(0xE8, line, addr, instr, lexer),
-						"dex" => opcode_implicit!(0xCA, line, addr, instr, lexer),
-						"iny" => opcode_implicit!(0xC8, line, addr, instr, lexer),
-						"dey" => opcode_implicit!(0x88, line, addr, instr, lexer),
-
-						// Transfering registers
-						"tax" => opcode_implicit!(0xAA, line, addr, instr, lexer),
-						"txa" => opcode_implicit!(0x8A, line, addr, instr, lexer),
-						"tay" => opcode_implicit!(0xA8, line, addr, instr, lexer),
-						"tya" => opcode_implicit!(0x98, line, addr, instr, lexer),
-						"tsx" => opcode_implicit!(0xBA, line, addr, instr, lexer),
-						"txs" => opcode_implicit!(0x9A, line, addr, instr, lexer),
-
-						// No operation
-						"nop" => opcode_implicit!(0xEA, line, addr, instr, lexer),
-
-						// Invalid opcode
-						_ => return ParseError::new_from_lexer(lexer, &format!("Invalid opcode '{}'", instr.opcode))
-					}
+	*addr = addr.wrapping_add(size);
+	*wrapped = *addr == 0;
+	Ok(())
+}
+
+// Settings for the first pass that don't come from the source
+#[derive(Debug, Clone, Default)]
+pub struct FirstPassOptions {
+	// Constants defined before the first line
+	pub defines: Vec<(String, u16)>,
+
+	// Directories searched for included files that aren't found as given
+	pub include_paths: Vec<String>
+}
+
+// Finds an included file, searching the include paths if it doesn't exist as given
+fn find_include(file: &str, options: &FirstPassOptions, files: &dyn FileProvider) -> String {
+	if files.exists(file) {
+		return String::from(file);
+	}
+	options.include_paths.iter()
+		.map(|v| Path::new(v).join(file).to_string_lossy().into_owned())
+		.find(|v| files.exists(v))
+		.unwrap_or_else(|| String::from(file))
+}
+
+// Performs the first pass on the code, reading included files from disk
+pub fn first_pass(lexer: &mut Lexer) -> Result<FirstPassResult, ParseError> {
+	first_pass_with_options(lexer, &FirstPassOptions::default(), &FileSystem)
+}
 
+// Performs the first pass on the code with constants and include paths, reading included files from a file provider
+pub fn first_pass_with_options(lexer: &mut Lexer, options: &FirstPassOptions, files: &dyn FileProvider) -> Result<FirstPassResult, ParseError> {
+	let mut symbol_table = HashMap::new();
+	let mut symbol_defs = HashMap::new();
+	let mut includes = Vec::new();
+	let mut ines: Option<InesHeader> = None;
+	let mut lines = Vec::new();
+	let mut addr = 0u16;
+	let mut wrapped = false;
+	let mut bank = Bank::None;
+	let mut segments: Vec<NamedSegment> = Vec::new();
+	let mut segment: Option<usize> = None;
+	let mut exports: Vec<String> = Vec::new();
+	let mut globals: Vec<(u32, String)> = Vec::new();
+	let mut cycle_regions: Vec<CycleRegion> = Vec::new();
+	let mut open_regions: Vec<(u32, Option<u16>)> = Vec::new();
+	let mut page_asserts: Vec<PageAssert> = Vec::new();
+	let mut asserts: Vec<Assert> = Vec::new();
+	let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+	// Add the predefined constants
+	for (label, value) in options.defines.iter() {
+		define_symbol(lexer, &mut symbol_table, &mut symbol_defs, 0, label.clone(), Address::Literal(*value), SymbolKind::Constant)?;
+	}
+
+	// Iterate over every line
+	while let Some(line) = parser::parse_line(lexer)? {
+		// Set labels to the current address
+		if !line.label.is_empty() {
+			let def = SymbolDef {
+				filename: lexer.get_filename().clone(),
+				lino: line.lino,
+				kind: SymbolKind::Label,
+				segment,
+				bank
+			};
+			add_symbol(&mut symbol_table, &mut symbol_defs, line.label, addr, def)?;
+		} else {
+			match line.value {
+				// Deal with instructions
+				LineValue::Instruction(instr) => {
+					let mut line = AnnotatedLine {
+						lino: lexer.get_lino(),
+						col: line.col,
+						kind: LineKind::Code,
+						bank,
+						segment,
+						addr,
+						opcode: 0b000_000_00,
+						arg: InstructionArg::NoArgs
+					};
+
+					let size = encode_instruction(lexer, instr, &mut line)?;
+					advance(lexer, &mut addr, &mut wrapped, size)?;
+
+					line.arg = resolve_variables(lexer, line.arg, &symbol_table, &symbol_defs)?;
 					lines.push(line);
 				}
 
@@ -592,11 +931,15 @@ pub fn first_pass(lexer: &mut Lexer) -> Result<FirstPassResult, ParseError> {
 						Pragma::Byte(byte) => {
 							lines.push(AnnotatedLine {
 								lino: lexer.get_lino(),
-								addr: addr,
+								col: line.col,
+								kind: LineKind::Data,
+								bank,
+								segment,
+								addr,
 								opcode: byte,
 								arg: InstructionArg::NoArgs
 							});
-							addr += 1;
+							advance(lexer, &mut addr, &mut wrapped, 1)?;
 						}
 
 						// Push a collection of bytes
@@ -604,23 +947,38 @@ pub fn first_pass(lexer: &mut Lexer) -> Result<FirstPassResult, ParseError> {
 							for byte in bytes {
 								lines.push(AnnotatedLine {
 									lino: lexer.get_lino(),
-									addr: addr,
+									col: line.col,
+									kind: LineKind::Data,
+									bank,
+									segment,
+									addr,
 									opcode: byte,
 									arg: InstructionArg::NoArgs
 								});
-								addr += 1;
+								advance(lexer, &mut addr, &mut wrapped, 1)?;
 							}
 						}
 
 						// Push a word
 						Pragma::Word(word) => {
 							let word = match word {
+								// Variables use their current value
+								Address::Label(label) if matches!(symbol_defs.get(&label), Some(SymbolDef { kind: SymbolKind::Variable, .. })) => symbol_table[&label],
+
+								// Other labels are resolved in the second pass, after segments are placed
 								Address::Label(label) => {
-									// Labels must be already set to set the origin
-									match symbol_table.get(&label) {
-										Some(w) => *w,
-										None => return ParseError::new_from_lexer(lexer, &format!("Setting word to value of undefined label {}", label))
-									}
+									lines.push(AnnotatedLine {
+										lino: lexer.get_lino(),
+										col: line.col,
+										kind: LineKind::Data,
+										bank,
+										segment,
+										addr,
+										opcode: 0,
+										arg: InstructionArg::DataWordLabelArg(label)
+									});
+									advance(lexer, &mut addr, &mut wrapped, 2)?;
+									continue;
 								}
 
 								// Literal address
@@ -630,67 +988,271 @@ pub fn first_pass(lexer: &mut Lexer) -> Result<FirstPassResult, ParseError> {
 							// Push low byte
 							lines.push(AnnotatedLine {
 								lino: lexer.get_lino(),
-								addr: addr,
+								col: line.col,
+								kind: LineKind::Data,
+								bank,
+								segment,
+								addr,
 								opcode: word as u8,
 								arg: InstructionArg::NoArgs
 							});
-							addr += 1;
+							advance(lexer, &mut addr, &mut wrapped, 1)?;
 
 							// Push high byte
 							lines.push(AnnotatedLine {
 								lino: lexer.get_lino(),
-								addr: addr,
+								col: line.col,
+								kind: LineKind::Data,
+								bank,
+								segment,
+								addr,
 								opcode: (word >> 8) as u8,
 								arg: InstructionArg::NoArgs
 							});
-							addr += 1;
+							advance(lexer, &mut addr, &mut wrapped, 1)?;
 						}
 
 						// Set the origin
 						Pragma::Origin(a) => {
+							leave_segment(&mut segments, &mut segment, addr);
 							match a {
 								Address::Label(label) => {
-									// Labels must be already set to set the origin
-									addr = match symbol_table.get(&label) {
-										Some(a) => *a,
-										None => return ParseError::new_from_lexer(lexer, &format!("Setting origin to value of undefined label {}", label))
+									// Labels must be already set to set the origin, and cannot move with a segment
+									addr = match (symbol_table.get(&label), symbol_defs.get(&label)) {
+										(Some(_), Some(SymbolDef { segment: Some(i), .. })) => return ParseError::new_from_lexer(lexer, &format!("Setting origin to label {} in segment {}", label, segments[*i].name)),
+										(Some(a), _) => *a,
+										_ => return ParseError::new_from_lexer(lexer, &format!("Setting origin to value of undefined label {}", label))
 									}
 								}
 
 								// Literal address
 								Address::Literal(a) => addr = a
 							}
+							wrapped = false;
 						}
 
-						// Define a label with a given address
-						Pragma::Define(label, addr) => {
-							match addr {
-								// Set label to the value of another label
-								Address::Label(s) => {
-									let v = match symbol_table.get(&s) {
-										Some(v) => *v,
-										None => return ParseError::new_from_lexer(lexer, &format!("Setting label {} to value of undefined label {}", label, s))
-									};
-									add_symbol(&mut symbol_table, label, v);
-								}
+						// Define a constant with a given value
+						Pragma::Define(label, value) => {
+							define_symbol(lexer, &mut symbol_table, &mut symbol_defs, line.lino, label, value, SymbolKind::Constant)?;
+						}
 
-								// Set label to a value
-								Address::Literal(n) => add_symbol(&mut symbol_table, label, n)
+						// (Re)assign a variable
+						Pragma::Set(label, value) => {
+							// Variables are substituted before linking, so they cannot move with a segment
+							if let Address::Label(s) = &value {
+								if let Some(SymbolDef { segment: Some(i), .. }) = symbol_defs.get(s) {
+									return ParseError::new_from_lexer(lexer, &format!("Setting variable {} to label {} in segment {}", label, s, segments[*i].name));
+								}
 							}
+							define_symbol(lexer, &mut symbol_table, &mut symbol_defs, line.lino, label, value, SymbolKind::Variable)?;
 						}
 
 						// Include a file
 						Pragma::Include(file) => {
-							match fs::read_to_string(&file) {
+							let file = find_include(&file, options, files);
+							match files.read_to_string(&file) {
 								Ok(s) => {
 									let mut lexer = Lexer::new(&file, &s);
-									let res = first_pass(&mut lexer)?;
-									symbol_table.extend(res.symbol_table);
+									let res = first_pass_with_options(&mut lexer, options, files)?;
+									for (key, def) in res.symbol_defs.iter() {
+										// The predefined constants are already in this file's symbol table
+										if def.lino == 0 && options.defines.iter().any(|v| v.0 == *key) {
+											continue;
+										}
+										let mut def = def.clone();
+
+										// Segments are matched by name
+										if let Some(i) = def.segment {
+											let name = &res.segments[i].name;
+											def.segment = Some(match segments.iter().position(|v| v.name == *name) {
+												Some(i) => i,
+												None => {
+													segments.push(NamedSegment { name: name.clone(), size: 0, align: 1 });
+													segments.len() - 1
+												}
+											});
+										}
+										match res.symbol_table.get(key) {
+											Some(value) => add_symbol(&mut symbol_table, &mut symbol_defs, key.clone(), *value, def)?,
+											None => add_symbol_def(&mut symbol_defs, key.clone(), def)?
+										}
+									}
+									exports.extend(res.exports.iter().cloned());
+									asserts.extend(res.asserts.iter().cloned());
+									diagnostics.extend(res.diagnostics.iter().cloned());
+									includes.push(IncludedFile {
+										lino: line.lino,
+										result: res
+									});
 								}
 
 								Err(e) => return ParseError::new_from_lexer(lexer, &format!("Could not read included file {}: {}", &file, e))
 							}
 						}
+
+						// Include the bytes of a binary file
+						Pragma::Incbin(file) => {
+							let file = find_include(&file, options, files);
+							match files.read(&file) {
+								Ok(bytes) => {
+									for byte in bytes {
+										lines.push(AnnotatedLine {
+											lino: lexer.get_lino(),
+											col: line.col,
+											kind: LineKind::Data,
+											bank,
+											segment,
+											addr,
+											opcode: byte,
+											arg: InstructionArg::NoArgs
+										});
+										advance(lexer, &mut addr, &mut wrapped, 1)?;
+									}
+								}
+
+								Err(e) => return ParseError::new_from_lexer(lexer, &format!("Could not read binary file {}: {}", &file, e))
+							}
+						}
+
+						// Set a field of the iNES header
+						Pragma::Ines(field) => {
+							ines.get_or_insert_with(InesHeader::default).set(field);
+						}
+
+						// Switch to a PRG bank, which starts at $8000 by default
+						Pragma::Bank(n, origin) => {
+							leave_segment(&mut segments, &mut segment, addr);
+							bank = Bank::Prg(n);
+							addr = origin.unwrap_or(0x8000);
+							wrapped = false;
+						}
+
+						// Generate a BASIC stub at the start of BASIC memory that jumps to the following code
+						Pragma::BasicStub(machine, stub_lino) => {
+							leave_segment(&mut segments, &mut segment, addr);
+							addr = machine.basic_start();
+							wrapped = false;
+							for byte in cbm::basic_stub(addr, stub_lino) {
+								lines.push(AnnotatedLine {
+									lino: lexer.get_lino(),
+									col: line.col,
+									kind: LineKind::Data,
+									bank,
+									segment,
+									addr,
+									opcode: byte,
+									arg: InstructionArg::NoArgs
+								});
+								advance(lexer, &mut addr, &mut wrapped, 1)?;
+							}
+						}
+
+						// Switch to a CHR bank, which starts at $0000
+						Pragma::ChrBank(n) => {
+							leave_segment(&mut segments, &mut segment, addr);
+							bank = Bank::Chr(n);
+							addr = 0;
+							wrapped = false;
+						}
+
+						// Switch to a named segment, which continues from where it was last left
+						Pragma::Segment(name) => {
+							leave_segment(&mut segments, &mut segment, addr);
+
+							let i = match segments.iter().position(|v| v.name == name) {
+								Some(i) => i,
+								None => {
+									segments.push(NamedSegment { name, size: 0, align: 1 });
+									segments.len() - 1
+								}
+							};
+							segment = Some(i);
+							addr = segments[i].size;
+							wrapped = false;
+						}
+
+						// Export symbols to other files, which may be defined later on
+						Pragma::Export(names) => {
+							exports.extend(names);
+						}
+
+						// Declare symbols defined by other files
+						Pragma::Import(names) => {
+							for name in names {
+								let def = SymbolDef {
+									filename: lexer.get_filename().clone(),
+									lino: line.lino,
+									kind: SymbolKind::Import,
+									segment: None,
+									bank: Bank::None
+								};
+								add_symbol_def(&mut symbol_defs, name, def)?;
+							}
+						}
+
+						// Export symbols if they are defined in this file and import them otherwise
+						Pragma::Global(names) => {
+							let lino = line.lino;
+							globals.extend(names.into_iter().map(|v| (lino, v)));
+						}
+
+						// Reserve space without emitting any bytes
+						Pragma::Res(n) => {
+							advance(lexer, &mut addr, &mut wrapped, n)?;
+						}
+
+						// Start counting cycles
+						Pragma::CyclesBegin(budget) => {
+							open_regions.push((line.lino, budget));
+						}
+
+						// Stop counting cycles at the innermost region
+						Pragma::CyclesEnd => {
+							match open_regions.pop() {
+								Some((lino, budget)) => cycle_regions.push(CycleRegion { lino, end_lino: line.lino, budget }),
+								None => return ParseError::new(lexer.get_filename().clone(), line.lino, ".cycles_end without .cycles_begin")
+							}
+						}
+
+						// Check the label's page once every symbol is resolved
+						Pragma::AssertSamePage(label) => {
+							page_asserts.push(PageAssert { lino: line.lino, addr, segment, label });
+						}
+
+						// Skip to the start of the next page without emitting any bytes
+						Pragma::PageAlign => {
+							// Segments are aligned relative to their start, so the linker must place them on a page
+							if let Some(i) = segment {
+								segments[i].align = 256;
+							}
+							let padding = addr.wrapping_neg() & 0xFF;
+							advance(lexer, &mut addr, &mut wrapped, padding)?;
+						}
+
+						// Check the comparison once every symbol is resolved
+						Pragma::Assert(left, comparison, right, message) => {
+							asserts.push(Assert {
+								filename: lexer.get_filename().clone(),
+								lino: line.lino,
+								left,
+								comparison,
+								right,
+								message
+							});
+						}
+
+						// Stop assembling
+						Pragma::Error(message) => {
+							return ParseError::new(lexer.get_filename().clone(), line.lino, &message);
+						}
+
+						// Report a message
+						Pragma::Warning(message) => {
+							diagnostics.push(Diagnostic { filename: lexer.get_filename().clone(), lino: line.lino, kind: DiagnosticKind::Warning, message });
+						}
+						Pragma::Print(message) => {
+							diagnostics.push(Diagnostic { filename: lexer.get_filename().clone(), lino: line.lino, kind: DiagnosticKind::Print, message });
+						}
 					}
 				}
 
@@ -701,8 +1263,45 @@ pub fn first_pass(lexer: &mut Lexer) -> Result<FirstPassResult, ParseError> {
 
 	}
 
+	leave_segment(&mut segments, &mut segment, addr);
+
+	if let Some((lino, _)) = open_regions.pop() {
+		return ParseError::new(lexer.get_filename().clone(), lino, ".cycles_begin without .cycles_end");
+	}
+
+	// Resolve globals now that every symbol is defined
+	for (lino, name) in globals {
+		match symbol_defs.entry(name) {
+			Entry::Occupied(v) => exports.push(v.key().clone()),
+			Entry::Vacant(v) => {
+				v.insert(SymbolDef {
+					filename: lexer.get_filename().clone(),
+					lino,
+					kind: SymbolKind::Import,
+					segment: None,
+					bank: Bank::None
+				});
+			}
+		}
+	}
+
+	// Only symbols defined in this file can be exported
+	exports.sort();
+	exports.dedup();
+	for name in exports.iter() {
+		match symbol_defs.get(name) {
+			Some(SymbolDef { kind: SymbolKind::Import, .. }) | None => return ParseError::new(lexer.get_filename().clone(), lexer.get_lino(), &format!("Exported symbol '{}' is not defined", name)),
+
+			// Variables have no single value to export
+			Some(SymbolDef { kind: SymbolKind::Variable, .. }) => return ParseError::new(lexer.get_filename().clone(), lexer.get_lino(), &format!("Exported symbol '{}' is a variable", name)),
+			_ => {}
+		}
+	}
+
 	// Success!
 	Ok(FirstPassResult {
-		filename: lexer.get_filename().clone(), lines, symbol_table
+		filename: lexer.get_filename().clone(),
+		source: String::from(lexer.get_source()),
+		lines, symbol_table, symbol_defs, includes, segments, exports, cycle_regions, page_asserts, asserts, diagnostics, ines
 	})
 }
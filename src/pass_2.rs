@@ -6,174 +6,551 @@
 // Created on October 22 2020.
 // 
 
-use crate::parser::ParseError;
+use std::fmt;
+
+use crate::disasm::{self, OpcodeInfo, OperandKind};
+use crate::parser::{
+	Address,
+	ParseError
+};
 use crate::pass_1::{
+	AnnotatedLine,
+	Bank,
 	FirstPassResult,
-	InstructionArg
+	InstructionArg,
+	LineKind,
+	SymbolKind
 };
 
+// Maps a range of emitted bytes back to the source that generated it
+#[derive(Debug, Clone)]
+pub struct DebugRange {
+	pub start: u16,
+	pub end: u16,
+	pub filename: String,
+	pub lino: u32,
+	pub col: u32,
+	pub label: Option<String>,
+	pub kind: LineKind
+}
+
+// The number of cycles an instruction takes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cycles {
+	pub base: u8,
+
+	// Whether indexing may cross a page, which takes one more cycle
+	pub page_penalty: bool,
+
+	// Extra cycles if a branch is taken: 1, or 2 if the target is on another page
+	pub branch_penalty: u8
+}
+
+impl Cycles {
+	// Returns the fewest cycles the instruction can take
+	pub fn min(&self) -> u32 {
+		self.base as u32
+	}
+
+	// Returns the most cycles the instruction can take
+	pub fn max(&self) -> u32 {
+		self.base as u32 + self.page_penalty as u32 + self.branch_penalty as u32
+	}
+}
+
+impl fmt::Display for Cycles {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.min() == self.max() {
+			write!(f, "{}", self.min())
+		} else {
+			write!(f, "{}-{}", self.min(), self.max())
+		}
+	}
+}
+
+// The cycle count of one instruction
+#[derive(Debug, Clone)]
+pub struct LineCycles {
+	pub addr: u16,
+	pub filename: String,
+	pub lino: u32,
+	pub cycles: Cycles
+}
+
+// The total cycle count of a .cycles_begin/.cycles_end region
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleReport {
+	pub filename: String,
+	pub lino: u32,
+	pub end_lino: u32,
+	pub min: u32,
+	pub max: u32,
+	pub budget: Option<u16>
+}
+
+impl fmt::Display for CycleReport {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}-{}: ", self.filename, self.lino, self.end_lino)?;
+		if self.min == self.max {
+			write!(f, "{} cycles", self.min)?;
+		} else {
+			write!(f, "{}-{} cycles", self.min, self.max)?;
+		}
+		if let Some(budget) = self.budget {
+			write!(f, " (budget {})", budget)?;
+		}
+		Ok(())
+	}
+}
+
+// Where code crosses a 256 byte page
+#[derive(Debug, Clone, PartialEq)]
+pub enum PageCrossingKind {
+	// A branch whose target is on another page than the following instruction, taking one more cycle
+	Branch(u16),
+
+	// An instruction whose bytes span two pages
+	Instruction
+}
+
+// Code that crosses a page
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageCrossing {
+	pub filename: String,
+	pub lino: u32,
+	pub addr: u16,
+	pub kind: PageCrossingKind
+}
+
+impl fmt::Display for PageCrossing {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.kind {
+			PageCrossingKind::Branch(target) => write!(f, "{}:{}: ${:04X}: branch to ${:04X} crosses a page", self.filename, self.lino, self.addr, target),
+			PageCrossingKind::Instruction => write!(f, "{}:{}: ${:04X}: instruction crosses a page", self.filename, self.lino, self.addr)
+		}
+	}
+}
+
+// A contiguous range of written bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+	pub addr: u16,
+	pub bytes: Vec<u8>,
+	pub filename: String
+}
+
+impl Segment {
+	// Returns the last address of the segment
+	pub fn end(&self) -> u16 {
+		self.addr.wrapping_add(self.bytes.len() as u16 - 1)
+	}
+}
+
 // Represents the result from the second pass
 #[derive(Debug)]
 pub struct AssemblerResult {
 	pub filename: String,
-	pub start: u16,
-	pub end: u16,
-	pub bytes: [u8; u16::MAX as usize + 1]
+	pub segments: Vec<Segment>,
+	pub debug: Vec<DebugRange>,
+	pub cycles: Vec<LineCycles>,
+	pub cycle_reports: Vec<CycleReport>,
+	pub page_crossings: Vec<PageCrossing>
 }
 
 impl AssemblerResult {
+	// Creates an empty assembler result
+	pub fn new(filename: &str) -> AssemblerResult {
+		AssemblerResult {
+			filename: String::from(filename),
+			segments: Vec::new(),
+			debug: Vec::new(),
+			cycles: Vec::new(),
+			cycle_reports: Vec::new(),
+			page_crossings: Vec::new()
+		}
+	}
+
+	// Returns the first written address
+	pub fn start(&self) -> Option<u16> {
+		self.segments.first().map(|v| v.addr)
+	}
+
+	// Returns the last written address
+	pub fn end(&self) -> Option<u16> {
+		self.segments.iter().map(|v| v.end()).max()
+	}
+
 	// Merges two assembler results
-	pub fn merge(&mut self, other: &AssemblerResult) -> Result<(), String>{
-		if ((other.start <= self.start && self.start <= other.end)
-		 || (other.start <= self.end && self.end <= other.end))
-		&& !(self.start == u16::MAX && self.end == 0) {
-			Err(format!("Could not merge {} with {} (possible overwriting)", self.filename, other.filename))
-		} else {
-			self.start = self.start.min(other.start);
-			self.end = self.end.max(other.end);
+	pub fn merge(&mut self, other: &AssemblerResult) -> Result<(), String> {
+		// Check for overlapping segments
+		for segment in other.segments.iter() {
+			if let Some(v) = self.segments.iter().find(|v| v.addr <= segment.end() && segment.addr <= v.end()) {
+				return Err(format!("Could not merge {} with {} (${:04X}-${:04X} overlaps ${:04X}-${:04X})",
+					v.filename, segment.filename, v.addr, v.end(), segment.addr, segment.end()));
+			}
+		}
+
+		self.segments.extend(other.segments.iter().cloned());
+		self.segments.sort_by_key(|v| v.addr);
+		self.debug.extend(other.debug.iter().cloned());
+		self.cycles.extend(other.cycles.iter().cloned());
+		self.cycle_reports.extend(other.cycle_reports.iter().cloned());
+		self.page_crossings.extend(other.page_crossings.iter().cloned());
+		Ok(())
+	}
 
-			for i in other.start..other.end + 1{
-				self.bytes[i as usize] = other.bytes[i as usize];
+	// Fills the unwritten bytes from start to end (inclusive) with a value
+	pub fn fill(&mut self, start: u16, end: u16, value: u8, filename: &str) {
+		let mut gaps = Vec::new();
+		let mut addr = start as u32;
+		for segment in self.segments.iter().filter(|v| v.end() >= start && v.addr <= end) {
+			if segment.addr as u32 > addr {
+				gaps.push((addr, segment.addr as u32 - 1));
 			}
+			addr = addr.max(segment.end() as u32 + 1);
+		}
+		if addr <= end as u32 {
+			gaps.push((addr, end as u32));
+		}
+
+		for (first, last) in gaps {
+			self.segments.push(Segment {
+				addr: first as u16,
+				bytes: vec![value; (last - first + 1) as usize],
+				filename: String::from(filename)
+			});
+		}
+		self.segments.sort_by_key(|v| v.addr);
+	}
+
+	// Returns the written bytes of every segment as (address, bytes)
+	pub fn slices(&self) -> Vec<(u16, &[u8])> {
+		self.segments.iter().map(|v| (v.addr, &v.bytes[..])).collect()
+	}
 
-			Ok(())
+	// Returns a flat 64 KiB image of memory with unwritten bytes set to zero
+	pub fn image(&self) -> Vec<u8> {
+		let mut image = vec![0u8; u16::MAX as usize + 1];
+		for segment in self.segments.iter() {
+			let start = segment.addr as usize;
+			let len = segment.bytes.len().min(image.len() - start);
+			image[start..start + len].copy_from_slice(&segment.bytes[..len]);
 		}
+		image
 	}
 }
 
-// Check if code overwrites previously written code
-macro_rules! check_overwrite {
-	($first_pass: ident, $bytes: ident, $line: ident, $index: expr) => {
-		if $bytes[$index as usize] != 0 {
-			return ParseError::new($first_pass.filename, $line.lino, "This line overrides previously written code");
+// Builds the debug ranges of the code, one per source line
+fn debug_ranges(first_pass: &FirstPassResult, lines: &[&AnnotatedLine]) -> Vec<DebugRange> {
+	// Labels defined in this file, sorted by line number
+	let mut labels: Vec<(u32, &String)> = first_pass.symbol_defs.iter()
+		.filter(|(_, def)| def.kind == SymbolKind::Label && def.filename == first_pass.filename)
+		.map(|(name, def)| (def.lino, name))
+		.collect();
+	labels.sort();
+
+	let mut ranges: Vec<DebugRange> = Vec::new();
+	for line in lines.iter() {
+		let end = line.addr.wrapping_add(line.size() - 1);
+
+		// Lines that span several annotated lines (ie, .bytes) get a single range
+		if let Some(last) = ranges.last_mut() {
+			if last.lino == line.lino && last.end.wrapping_add(1) == line.addr {
+				last.end = end;
+				continue;
+			}
 		}
-	};
+
+		// Find the last label before this line
+		let label = match labels.binary_search_by(|v| v.0.cmp(&line.lino)) {
+			Ok(i) => Some(labels[i].1.clone()),
+			Err(0) => None,
+			Err(i) => Some(labels[i - 1].1.clone())
+		};
+
+		ranges.push(DebugRange {
+			start: line.addr,
+			end,
+			filename: first_pass.filename.clone(),
+			lino: line.lino,
+			col: line.col,
+			label,
+			kind: line.kind
+		});
+	}
+
+	ranges
 }
 
-// Performs the second pass on the code
-pub fn second_pass(first_pass: FirstPassResult) -> Result<AssemblerResult, ParseError> {
-	let mut start = u16::MAX;
-	let mut end = 0u16;
-	let mut bytes = [0u8; u16::MAX as usize + 1];
-
-	// Iterate over the lines of code
-	for line in first_pass.lines {
-		// Set the opcode
-		check_overwrite!(first_pass, bytes, line, line.addr);
-		bytes[line.addr as usize] = line.opcode;
-
-		// Set the start index if necessary
-		if line.addr < start {
-			start = line.addr;
-		}
-
-		// Match the argument
-		let last;
-		match line.arg {
-			// No arguments
-			InstructionArg::NoArgs => {
-				last = line.addr
+// Counts the cycles of every instruction in the given lines
+fn line_cycles(first_pass: &FirstPassResult, lines: &[&AnnotatedLine]) -> Vec<LineCycles> {
+	let table = disasm::opcodes();
+	let mut cycles = Vec::new();
+	for line in lines.iter().filter(|v| v.kind == LineKind::Code) {
+		let info: &OpcodeInfo = match &table[line.opcode as usize] {
+			Some(v) => v,
+			None => continue
+		};
+
+		// Taken branches take one more cycle, or two if the target is on another page
+		let next = line.addr.wrapping_add(2);
+		let target = match &line.arg {
+			InstructionArg::ByteArg(n) if info.kind == OperandKind::Relative => Some(next.wrapping_add(*n as i8 as u16)),
+			InstructionArg::RelativeLabelArg(label) => first_pass.symbol_table.get(label).copied(),
+			_ => None
+		};
+		let branch_penalty = match target {
+			Some(target) if target & 0xFF00 != next & 0xFF00 => 2,
+			Some(_) => 1,
+			None => 0
+		};
+
+		cycles.push(LineCycles {
+			addr: line.addr,
+			filename: first_pass.filename.clone(),
+			lino: line.lino,
+			cycles: Cycles {
+				base: info.cycles,
+				page_penalty: info.page_penalty,
+				branch_penalty
 			}
+		});
+	}
+	cycles
+}
 
-			// 1 byte argument
-			InstructionArg::ByteArg(n) => {
-				last = line.addr + 1;
-				check_overwrite!(first_pass, bytes, line, last);
-				bytes[last as usize] = n;
+// Totals the cycles of every .cycles_begin/.cycles_end region, erroring if a budget is exceeded
+fn cycle_reports(first_pass: &FirstPassResult, cycles: &[&LineCycles]) -> Result<Vec<CycleReport>, ParseError> {
+	let mut reports = Vec::new();
+	for region in first_pass.cycle_regions.iter() {
+		let inside = cycles.iter().filter(|v| v.filename == first_pass.filename && v.lino > region.lino && v.lino < region.end_lino);
+		let (min, max) = inside.fold((0, 0), |acc, v| (acc.0 + v.cycles.min(), acc.1 + v.cycles.max()));
+
+		if let Some(budget) = region.budget {
+			if max > budget as u32 {
+				return ParseError::new(first_pass.filename.clone(), region.lino, &format!("Cycle budget of {} exceeded (takes {}-{} cycles)", budget, min, max));
 			}
+		}
 
-			// 1 word argument
-			InstructionArg::WordArg(n) => {
-				last = line.addr + 2;
-				check_overwrite!(first_pass, bytes, line, line.addr + 1);
-				bytes[line.addr as usize + 1] = n as u8;
-				check_overwrite!(first_pass, bytes, line, last);
-				bytes[last as usize] = (n >> 8) as u8;
+		reports.push(CycleReport {
+			filename: first_pass.filename.clone(),
+			lino: region.lino,
+			end_lino: region.end_lino,
+			min,
+			max,
+			budget: region.budget
+		});
+	}
+	Ok(reports)
+}
+
+// Checks that every .assert_same_page label is on the same page as the line
+fn check_page_asserts(first_pass: &FirstPassResult) -> Result<(), ParseError> {
+	for assert in first_pass.page_asserts.iter() {
+		match first_pass.symbol_table.get(&assert.label) {
+			Some(v) if *v & 0xFF00 != assert.addr & 0xFF00 => {
+				return ParseError::new(first_pass.filename.clone(), assert.lino, &format!("Label '{}' (${:04X}) is not on the same page as ${:04X}", assert.label, v, assert.addr));
 			}
+			Some(_) => {}
+			None => return ParseError::new(first_pass.filename.clone(), assert.lino, &format!("Undeclared label '{}' used as value", assert.label))
+		}
+	}
+	Ok(())
+}
 
-			// Decode byte label argument
-			InstructionArg::ByteLabelArg(label) => {
-				last = line.addr + 1;
-
-				if let Some(v) = first_pass.symbol_table.get(&label) {
-					if *v < 256 {
-						check_overwrite!(first_pass, bytes, line, last);
-						bytes[last as usize] = *v as u8;
-					} else {
-						return ParseError::new(first_pass.filename, line.lino, &format!("Expected byte, found word {}", label));
-					}
-				} else {
-					return ParseError::new(first_pass.filename, line.lino, &format!("Undeclared label '{}' used as value", label));
-				}
+// Checks every .assert now that forward references are resolved
+fn check_asserts(first_pass: &FirstPassResult) -> Result<(), ParseError> {
+	for assert in first_pass.asserts.iter() {
+		let resolve = |value: &Address| match value {
+			Address::Literal(n) => Ok(*n),
+			Address::Label(label) => match first_pass.symbol_table.get(label) {
+				Some(v) => Ok(*v),
+				None => ParseError::new(assert.filename.clone(), assert.lino, &format!("Undeclared label '{}' used as value", label))
 			}
+		};
 
-			// Decode low byte of label argument
-			InstructionArg::ByteLabelLowArg(label) => {
-				last = line.addr + 1;
+		let left = resolve(&assert.left)?;
+		let right = resolve(&assert.right)?;
+		if !assert.comparison.test(left, right) {
+			let message = match &assert.message {
+				Some(message) => message.clone(),
+				None => format!("Assertion failed: {} {} {}", assert.left, assert.comparison, assert.right)
+			};
+			return ParseError::new(assert.filename.clone(), assert.lino, &format!("{} (${:04X} {} ${:04X})", message, left, assert.comparison, right));
+		}
+	}
+	Ok(())
+}
 
-				if let Some(v) = first_pass.symbol_table.get(&label) {
-					check_overwrite!(first_pass, bytes, line, last);
-					bytes[last as usize] = *v as u8;
-				} else {
-					return ParseError::new(first_pass.filename, line.lino, &format!("Undeclared label '{}' used as value", label));
-				}
-			}
+// Performs the second pass on the code
+pub fn second_pass(first_pass: &FirstPassResult) -> Result<AssemblerResult, ParseError> {
+	check_page_asserts(first_pass)?;
+	check_asserts(first_pass)?;
+	let lines: Vec<&AnnotatedLine> = first_pass.lines.iter().collect();
+	let mut result = assemble_lines(first_pass, &lines)?;
+	result.cycle_reports = cycle_reports(first_pass, &result.cycles.iter().collect::<Vec<_>>())?;
+	Ok(result)
+}
 
-			// Decode high byte of label argument
-			InstructionArg::ByteLabelHighArg(label) => {
-				last = line.addr + 1;
+// Performs the second pass on the code separately for every bank, sorted by bank
+pub fn second_pass_banked(first_pass: &FirstPassResult) -> Result<Vec<(Bank, AssemblerResult)>, ParseError> {
+	check_page_asserts(first_pass)?;
+	check_asserts(first_pass)?;
 
-				if let Some(v) = first_pass.symbol_table.get(&label) {
-					check_overwrite!(first_pass, bytes, line, last);
-					bytes[last as usize] = (*v >> 8) as u8;
-				} else {
-					return ParseError::new(first_pass.filename, line.lino, &format!("Undeclared label '{}' used as value", label));
-				}
-			}
+	// Group lines by bank
+	let mut banks: Vec<(Bank, Vec<&AnnotatedLine>)> = Vec::new();
+	for line in first_pass.lines.iter() {
+		match banks.iter_mut().find(|v| v.0 == line.bank) {
+			Some(bank) => bank.1.push(line),
+			None => banks.push((line.bank, vec![line]))
+		}
+	}
 
-			// Decode word label argument
-			InstructionArg::WordLabelArg(label) => {
-				last = line.addr + 2;
-
-				if let Some(v) = first_pass.symbol_table.get(&label) {
-					check_overwrite!(first_pass, bytes, line, line.addr + 1);
-					bytes[line.addr as usize + 1] = *v as u8;
-					check_overwrite!(first_pass, bytes, line, last);
-					bytes[last as usize] = (*v >> 8) as u8;
-				} else {
-					return ParseError::new(first_pass.filename, line.lino, &format!("Undeclared label '{}' used as value", label));
-				}
+	let mut results = Vec::new();
+	for (bank, lines) in banks {
+		results.push((bank, assemble_lines(first_pass, &lines)?));
+	}
+
+	// Regions may span banks, so their reports go with the bank the region starts in
+	let cycles: Vec<&LineCycles> = results.iter().flat_map(|v| v.1.cycles.iter()).collect();
+	for report in cycle_reports(first_pass, &cycles)? {
+		let index = results.iter().position(|v| v.1.cycles.iter().any(|c| c.filename == report.filename && c.lino > report.lino && c.lino < report.end_lino));
+		if let Some(result) = results.get_mut(index.unwrap_or(0)) {
+			result.1.cycle_reports.push(report);
+		}
+	}
+	results.sort_by_key(|v| match v.0 {
+		Bank::None => (0, 0),
+		Bank::Prg(n) => (1, n),
+		Bank::Chr(n) => (2, n)
+	});
+	Ok(results)
+}
+
+// Looks up the value of a label used by a line
+fn label_value(first_pass: &FirstPassResult, line: &AnnotatedLine, label: &str) -> Result<u16, ParseError> {
+	match first_pass.symbol_table.get(label) {
+		Some(v) => Ok(*v),
+		None => ParseError::new(first_pass.filename.clone(), line.lino, &format!("Undeclared label '{}' used as value", label))
+	}
+}
+
+// Encodes the bytes of a line, recording taken branches to another page
+fn line_bytes(first_pass: &FirstPassResult, line: &AnnotatedLine, page_crossings: &mut Vec<PageCrossing>) -> Result<Vec<u8>, ParseError> {
+	// Set the opcode (data words have none)
+	let mut bytes = Vec::with_capacity(3);
+	if !matches!(line.arg, InstructionArg::DataWordLabelArg(_)) {
+		bytes.push(line.opcode);
+	}
+
+	// Match the argument
+	match &line.arg {
+		// No arguments, apart from the signature byte after brk
+		InstructionArg::NoArgs if line.size() == 2 => bytes.push(0),
+		InstructionArg::NoArgs => {}
+
+		// 1 byte argument
+		InstructionArg::ByteArg(n) => bytes.push(*n),
+
+		// 1 word argument
+		InstructionArg::WordArg(n) => bytes.extend([*n as u8, (*n >> 8) as u8]),
+
+		// Decode byte label argument
+		InstructionArg::ByteLabelArg(label) => {
+			let v = label_value(first_pass, line, label)?;
+			if v < 256 {
+				bytes.push(v as u8);
+			} else {
+				return ParseError::new(first_pass.filename.clone(), line.lino, &format!("Expected byte, found word {}", label));
 			}
+		}
+
+		// Decode low and high bytes of label argument
+		InstructionArg::ByteLabelLowArg(label) => bytes.push(label_value(first_pass, line, label)? as u8),
+		InstructionArg::ByteLabelHighArg(label) => bytes.push((label_value(first_pass, line, label)? >> 8) as u8),
+
+		// Decode word label argument or data
+		InstructionArg::WordLabelArg(label) | InstructionArg::DataWordLabelArg(label) => {
+			let v = label_value(first_pass, line, label)?;
+			bytes.extend([v as u8, (v >> 8) as u8]);
+		}
 
-			// Decode relative label argument
-			InstructionArg::RelativeLabelArg(label) => {
-				last = line.addr + 1;
-
-				if let Some(v) = first_pass.symbol_table.get(&label) {
-					let diff = *v as i32 - line.addr as i32 - 2;
-					if -128 <= diff && diff <= 127 {
-						check_overwrite!(first_pass, bytes, line, last);
-						bytes[last as usize] = diff as u8;
-					} else {
-						return ParseError::new(first_pass.filename, line.lino, &format!("Label '{}' is too far away", label));
-					}
-				} else {
-					return ParseError::new(first_pass.filename, line.lino, &format!("Undeclared label '{}' used as value", label));
+		// Decode relative label argument
+		InstructionArg::RelativeLabelArg(label) => {
+			let v = label_value(first_pass, line, label)?;
+			let diff = v as i32 - line.addr as i32 - 2;
+			if -128 <= diff && diff <= 127 {
+				bytes.push(diff as u8);
+
+				// Taken branches to another page take one more cycle
+				if v & 0xFF00 != line.addr.wrapping_add(2) & 0xFF00 {
+					page_crossings.push(PageCrossing {
+						filename: first_pass.filename.clone(),
+						lino: line.lino,
+						addr: line.addr,
+						kind: PageCrossingKind::Branch(v)
+					});
 				}
+			} else {
+				return ParseError::new(first_pass.filename.clone(), line.lino, &format!("Label '{}' is too far away", label));
 			}
 		}
+	}
+
+	Ok(bytes)
+}
+
+// Generates code for the given lines of a file
+fn assemble_lines(first_pass: &FirstPassResult, lines: &[&AnnotatedLine]) -> Result<AssemblerResult, ParseError> {
+	let mut page_crossings = Vec::new();
+
+	// Encode every line, keeping its position in the source
+	let mut encoded = Vec::with_capacity(lines.len());
+	for (i, line) in lines.iter().enumerate() {
+		// Check if the instruction spans two pages
+		if line.kind == LineKind::Code && line.addr & 0xFF00 != line.addr.wrapping_add(line.size() - 1) & 0xFF00 {
+			page_crossings.push(PageCrossing {
+				filename: first_pass.filename.clone(),
+				lino: line.lino,
+				addr: line.addr,
+				kind: PageCrossingKind::Instruction
+			});
+		}
+
+		encoded.push((i, *line, line_bytes(first_pass, line, &mut page_crossings)?));
+	}
+
+	// Join the lines into segments in address order
+	encoded.sort_by_key(|v| (v.1.addr, v.0));
+	let mut segments: Vec<Segment> = Vec::new();
+	let mut prev: Option<(usize, &AnnotatedLine)> = None;
+	for (i, line, bytes) in encoded {
+		match segments.last_mut() {
+			// Overlapping lines are reported at whichever comes later in the source
+			Some(segment) if (line.addr as u32) < segment.addr as u32 + segment.bytes.len() as u32 => {
+				let (j, other) = prev.unwrap();
+				let lino = if i > j { line.lino } else { other.lino };
+				return ParseError::new(first_pass.filename.clone(), lino, "This line overrides previously written code");
+			}
+
+			Some(segment) if line.addr as u32 == segment.addr as u32 + segment.bytes.len() as u32 => segment.bytes.extend(bytes),
 
-		// Set the end index
-		if end < last {
-			end = last;
+			_ => segments.push(Segment {
+				addr: line.addr,
+				bytes,
+				filename: first_pass.filename.clone()
+			})
 		}
+		prev = Some((i, line));
 	}
 
 	// Success!
 	Ok(AssemblerResult {
-		filename: first_pass.filename,
-		start,
-		end,
-		bytes
+		filename: first_pass.filename.clone(),
+		segments,
+		debug: debug_ranges(first_pass, lines),
+		cycles: line_cycles(first_pass, lines),
+		cycle_reports: Vec::new(),
+		page_crossings
 	})
 }
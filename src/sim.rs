@@ -0,0 +1,471 @@
+//
+// src
+// sim.rs: Simulates an NMOS 6502 running assembled code.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use crate::disasm;
+use crate::disasm::{
+	Decoded,
+	OpcodeInfo,
+	OperandKind
+};
+
+// Status flags
+pub const FLAG_C: u8 = 0x01;
+pub const FLAG_Z: u8 = 0x02;
+pub const FLAG_I: u8 = 0x04;
+pub const FLAG_D: u8 = 0x08;
+pub const FLAG_B: u8 = 0x10;
+pub const FLAG_U: u8 = 0x20;
+pub const FLAG_V: u8 = 0x40;
+pub const FLAG_N: u8 = 0x80;
+
+// The address that call returns to
+const CALL_RETURN: u16 = 0xFFFF;
+
+// Why the simulation stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+	// A brk instruction was reached (it isn't executed)
+	Brk,
+
+	// The cycle limit was reached
+	CycleLimit,
+
+	// An opcode that the assembler can't emit was reached
+	InvalidOpcode(u8)
+}
+
+// The state of the CPU and the memory it runs in
+pub struct Cpu {
+	pub a: u8,
+	pub x: u8,
+	pub y: u8,
+	pub sp: u8,
+	pub p: u8,
+	pub pc: u16,
+	pub cycles: u64,
+	pub memory: Vec<u8>,
+	table: &'static [Option<OpcodeInfo>]
+}
+
+impl Cpu {
+	// Creates a CPU in the state it has after a reset, running in a 64 KiB image of memory
+	pub fn new(memory: Vec<u8>) -> Cpu {
+		let mut cpu = Cpu {
+			a: 0,
+			x: 0,
+			y: 0,
+			sp: 0xFD,
+			p: FLAG_U | FLAG_I,
+			pc: 0,
+			cycles: 0,
+			memory,
+			table: disasm::opcodes()
+		};
+		cpu.memory.resize(0x10000, 0);
+		cpu.pc = cpu.read_word(0xFFFC);
+		cpu
+	}
+
+	// Reads a byte from memory
+	pub fn read(&self, addr: u16) -> u8 {
+		self.memory[addr as usize]
+	}
+
+	// Writes a byte to memory
+	pub fn write(&mut self, addr: u16, value: u8) {
+		self.memory[addr as usize] = value;
+	}
+
+	// Reads a little endian word from memory
+	pub fn read_word(&self, addr: u16) -> u16 {
+		u16::from_le_bytes([self.read(addr), self.read(addr.wrapping_add(1))])
+	}
+
+	// Reads a word from the zero page, wrapping around within it
+	fn read_zp_word(&self, addr: u8) -> u16 {
+		u16::from_le_bytes([self.read(addr as u16), self.read(addr.wrapping_add(1) as u16)])
+	}
+
+	// Sets or clears a flag
+	pub fn set_flag(&mut self, flag: u8, value: bool) {
+		if value {
+			self.p |= flag;
+		} else {
+			self.p &= !flag;
+		}
+	}
+
+	// Gets a flag
+	pub fn flag(&self, flag: u8) -> bool {
+		self.p & flag != 0
+	}
+
+	// Sets the zero and negative flags from a value
+	fn set_nz(&mut self, value: u8) {
+		self.set_flag(FLAG_Z, value == 0);
+		self.set_flag(FLAG_N, value & 0x80 != 0);
+	}
+
+	// Pushes a byte onto the stack
+	pub fn push(&mut self, value: u8) {
+		self.write(0x0100 | self.sp as u16, value);
+		self.sp = self.sp.wrapping_sub(1);
+	}
+
+	// Pulls a byte from the stack
+	pub fn pull(&mut self) -> u8 {
+		self.sp = self.sp.wrapping_add(1);
+		self.read(0x0100 | self.sp as u16)
+	}
+
+	// Gets the effective address of an instruction's operand and whether indexing crossed a page
+	fn address(&self, d: &Decoded) -> (u16, bool) {
+		let crossed = |base: u16, addr: u16| base & 0xFF00 != addr & 0xFF00;
+		match d.info.kind {
+			OperandKind::ZeroPage => (d.operand, false),
+			OperandKind::ZeroPageX => ((d.operand as u8).wrapping_add(self.x) as u16, false),
+			OperandKind::ZeroPageY => ((d.operand as u8).wrapping_add(self.y) as u16, false),
+			OperandKind::Absolute | OperandKind::Relative => (d.operand, false),
+			OperandKind::AbsoluteX => {
+				let addr = d.operand.wrapping_add(self.x as u16);
+				(addr, crossed(d.operand, addr))
+			}
+			OperandKind::AbsoluteY => {
+				let addr = d.operand.wrapping_add(self.y as u16);
+				(addr, crossed(d.operand, addr))
+			}
+			OperandKind::IndirectX => (self.read_zp_word((d.operand as u8).wrapping_add(self.x)), false),
+			OperandKind::IndirectY => {
+				let base = self.read_zp_word(d.operand as u8);
+				let addr = base.wrapping_add(self.y as u16);
+				(addr, crossed(base, addr))
+			}
+
+			// jmp ($xxFF) reads the high byte from the start of the same page
+			OperandKind::Indirect => {
+				let hi = (d.operand & 0xFF00) | (d.operand as u8).wrapping_add(1) as u16;
+				(u16::from_le_bytes([self.read(d.operand), self.read(hi)]), false)
+			}
+			OperandKind::Implicit | OperandKind::Immediate => (0, false)
+		}
+	}
+
+	// Adds a value and the carry to the accumulator
+	fn adc(&mut self, m: u8) {
+		let a = self.a;
+		let c = self.p & FLAG_C;
+		let binary = a as u16 + m as u16 + c as u16;
+
+		if self.flag(FLAG_D) {
+			// The NMOS 6502 sets N and V from the result before the high digit is adjusted, and Z from the binary sum
+			let mut lo = (a & 0x0F) + (m & 0x0F) + c;
+			if lo >= 0x0A {
+				lo = ((lo + 0x06) & 0x0F) + 0x10;
+			}
+			let mut sum = (a & 0xF0) as u16 + (m & 0xF0) as u16 + lo as u16;
+			let signed = (a & 0xF0) as i8 as i16 + (m & 0xF0) as i8 as i16 + lo as i16;
+			self.set_flag(FLAG_N, sum & 0x80 != 0);
+			self.set_flag(FLAG_V, !(-128..=127).contains(&signed));
+			if sum >= 0xA0 {
+				sum += 0x60;
+			}
+			self.set_flag(FLAG_C, sum >= 0x100);
+			self.set_flag(FLAG_Z, binary as u8 == 0);
+			self.a = sum as u8;
+		} else {
+			let result = binary as u8;
+			self.set_flag(FLAG_C, binary > 0xFF);
+			self.set_flag(FLAG_V, (!(a ^ m) & (a ^ result) & 0x80) != 0);
+			self.set_nz(result);
+			self.a = result;
+		}
+	}
+
+	// Subtracts a value and the borrow from the accumulator
+	fn sbc(&mut self, m: u8) {
+		let a = self.a;
+		let borrow = 1 - (self.p & FLAG_C) as i16;
+		let binary = a as i16 - m as i16 - borrow;
+		let result = binary as u8;
+
+		// The NMOS 6502 sets every flag from the binary difference, even in decimal mode
+		self.set_flag(FLAG_C, binary >= 0);
+		self.set_flag(FLAG_V, ((a ^ m) & (a ^ result) & 0x80) != 0);
+		self.set_nz(result);
+
+		if self.flag(FLAG_D) {
+			let mut lo = (a & 0x0F) as i16 - (m & 0x0F) as i16 - borrow;
+			if lo < 0 {
+				lo = ((lo - 0x06) & 0x0F) - 0x10;
+			}
+			let mut diff = (a & 0xF0) as i16 - (m & 0xF0) as i16 + lo;
+			if diff < 0 {
+				diff -= 0x60;
+			}
+			self.a = diff as u8;
+		} else {
+			self.a = result;
+		}
+	}
+
+	// Compares a register with a value
+	fn compare(&mut self, reg: u8, m: u8) {
+		self.set_flag(FLAG_C, reg >= m);
+		self.set_nz(reg.wrapping_sub(m));
+	}
+
+	// Executes one instruction, returning why the simulation has to stop if it can't
+	pub fn step(&mut self) -> Result<(), StopReason> {
+		let opcode = self.read(self.pc);
+		let bytes = [opcode, self.read(self.pc.wrapping_add(1)), self.read(self.pc.wrapping_add(2))];
+		let d = match disasm::decode(self.table, &bytes, self.pc) {
+			Some(d) if d.info.mnemonic == "brk" => return Err(StopReason::Brk),
+			Some(d) => d,
+			None => return Err(StopReason::InvalidOpcode(opcode))
+		};
+
+		let (addr, crossed) = self.address(&d);
+		let next = self.pc.wrapping_add(d.info.size);
+		self.pc = next;
+		self.cycles += d.info.cycles as u64;
+		if crossed && d.info.page_penalty {
+			self.cycles += 1;
+		}
+
+		// Reads the operand
+		let operand = |cpu: &Cpu| match d.info.kind {
+			OperandKind::Immediate => d.operand as u8,
+			OperandKind::Implicit => cpu.a,
+			_ => cpu.read(addr)
+		};
+
+		// Writes the result of a read-modify-write instruction
+		let store = |cpu: &mut Cpu, value: u8| {
+			match d.info.kind {
+				OperandKind::Implicit => cpu.a = value,
+				_ => cpu.write(addr, value)
+			}
+			cpu.set_nz(value);
+		};
+
+		// Takes a branch
+		let branch = |cpu: &mut Cpu, taken: bool| {
+			if taken {
+				cpu.cycles += if next & 0xFF00 != addr & 0xFF00 { 2 } else { 1 };
+				cpu.pc = addr;
+			}
+		};
+
+		match d.info.mnemonic {
+			// Loads and stores
+			"lda" => {
+				self.a = operand(self);
+				self.set_nz(self.a);
+			}
+			"ldx" => {
+				self.x = operand(self);
+				self.set_nz(self.x);
+			}
+			"ldy" => {
+				self.y = operand(self);
+				self.set_nz(self.y);
+			}
+			"sta" => self.write(addr, self.a),
+			"stx" => self.write(addr, self.x),
+			"sty" => self.write(addr, self.y),
+
+			// Arithmetic and logic
+			"adc" => self.adc(operand(self)),
+			"sbc" => self.sbc(operand(self)),
+			"and" => {
+				self.a &= operand(self);
+				self.set_nz(self.a);
+			}
+			"ora" => {
+				self.a |= operand(self);
+				self.set_nz(self.a);
+			}
+			"eor" => {
+				self.a ^= operand(self);
+				self.set_nz(self.a);
+			}
+			"cmp" => self.compare(self.a, operand(self)),
+			"cpx" => self.compare(self.x, operand(self)),
+			"cpy" => self.compare(self.y, operand(self)),
+			"bit" => {
+				let m = operand(self);
+				self.set_flag(FLAG_Z, self.a & m == 0);
+				self.set_flag(FLAG_N, m & 0x80 != 0);
+				self.set_flag(FLAG_V, m & 0x40 != 0);
+			}
+
+			// Shifts and rotates
+			"asl" => {
+				let m = operand(self);
+				self.set_flag(FLAG_C, m & 0x80 != 0);
+				store(self, m << 1);
+			}
+			"lsr" => {
+				let m = operand(self);
+				self.set_flag(FLAG_C, m & 0x01 != 0);
+				store(self, m >> 1);
+			}
+			"rol" => {
+				let m = operand(self);
+				let c = self.p & FLAG_C;
+				self.set_flag(FLAG_C, m & 0x80 != 0);
+				store(self, (m << 1) | c);
+			}
+			"ror" => {
+				let m = operand(self);
+				let c = self.p & FLAG_C;
+				self.set_flag(FLAG_C, m & 0x01 != 0);
+				store(self, (m >> 1) | (c << 7));
+			}
+
+			// Incrementing/decrementing
+			"inc" => {
+				let m = operand(self).wrapping_add(1);
+				store(self, m);
+			}
+			"dec" => {
+				let m = operand(self).wrapping_sub(1);
+				store(self, m);
+			}
+			"inx" => {
+				self.x = self.x.wrapping_add(1);
+				self.set_nz(self.x);
+			}
+			"dex" => {
+				self.x = self.x.wrapping_sub(1);
+				self.set_nz(self.x);
+			}
+			"iny" => {
+				self.y = self.y.wrapping_add(1);
+				self.set_nz(self.y);
+			}
+			"dey" => {
+				self.y = self.y.wrapping_sub(1);
+				self.set_nz(self.y);
+			}
+
+			// Transfering registers
+			"tax" => {
+				self.x = self.a;
+				self.set_nz(self.x);
+			}
+			"txa" => {
+				self.a = self.x;
+				self.set_nz(self.a);
+			}
+			"tay" => {
+				self.y = self.a;
+				self.set_nz(self.y);
+			}
+			"tya" => {
+				self.a = self.y;
+				self.set_nz(self.a);
+			}
+			"tsx" => {
+				self.x = self.sp;
+				self.set_nz(self.x);
+			}
+			"txs" => self.sp = self.x,
+
+			// Stack operations
+			"pha" => self.push(self.a),
+			"php" => self.push(self.p | FLAG_B | FLAG_U),
+			"pla" => {
+				self.a = self.pull();
+				self.set_nz(self.a);
+			}
+			"plp" => self.p = (self.pull() & !FLAG_B) | FLAG_U,
+
+			// Flag setting/clearing
+			"clc" => self.set_flag(FLAG_C, false),
+			"sec" => self.set_flag(FLAG_C, true),
+			"cli" => self.set_flag(FLAG_I, false),
+			"sei" => self.set_flag(FLAG_I, true),
+			"clv" => self.set_flag(FLAG_V, false),
+			"cld" => self.set_flag(FLAG_D, false),
+			"sed" => self.set_flag(FLAG_D, true),
+
+			// Branching instructions
+			"bpl" => branch(self, !self.flag(FLAG_N)),
+			"bmi" => branch(self, self.flag(FLAG_N)),
+			"bvc" => branch(self, !self.flag(FLAG_V)),
+			"bvs" => branch(self, self.flag(FLAG_V)),
+			"bcc" => branch(self, !self.flag(FLAG_C)),
+			"bcs" => branch(self, self.flag(FLAG_C)),
+			"bne" => branch(self, !self.flag(FLAG_Z)),
+			"beq" => branch(self, self.flag(FLAG_Z)),
+
+			// Jumps, subroutines, and returns
+			"jmp" => self.pc = addr,
+			"jsr" => {
+				let ret = next.wrapping_sub(1);
+				self.push((ret >> 8) as u8);
+				self.push(ret as u8);
+				self.pc = addr;
+			}
+			"rts" => {
+				let lo = self.pull();
+				let hi = self.pull();
+				self.pc = u16::from_le_bytes([lo, hi]).wrapping_add(1);
+			}
+			"rti" => {
+				self.p = (self.pull() & !FLAG_B) | FLAG_U;
+				let lo = self.pull();
+				let hi = self.pull();
+				self.pc = u16::from_le_bytes([lo, hi]);
+			}
+
+			// No operation
+			_ => ()
+		}
+
+		Ok(())
+	}
+
+	// Runs until a brk or invalid opcode is reached or the cycle count reaches the limit
+	pub fn run(&mut self, max_cycles: u64) -> StopReason {
+		while self.cycles < max_cycles {
+			if let Err(reason) = self.step() {
+				return reason;
+			}
+		}
+		StopReason::CycleLimit
+	}
+
+	// Calls a subroutine as if with jsr, running until it returns; returns the number of cycles it took (including the rts)
+	pub fn call(&mut self, addr: u16, max_cycles: u64) -> Result<u64, StopReason> {
+		// Return to an address that can't be reached any other way with the stack where it was
+		let ret = CALL_RETURN.wrapping_sub(1);
+		self.push((ret >> 8) as u8);
+		self.push(ret as u8);
+		let sp = self.sp.wrapping_add(2);
+		let start = self.cycles;
+		self.pc = addr;
+
+		while self.pc != CALL_RETURN || self.sp != sp {
+			if self.cycles - start >= max_cycles {
+				return Err(StopReason::CycleLimit);
+			}
+			self.step()?;
+		}
+		Ok(self.cycles - start)
+	}
+
+	// Formats the registers and flags
+	pub fn format_registers(&self) -> String {
+		let flags: String = "NV-BDIZC".chars().enumerate()
+			.map(|(i, c)| if self.p & (0x80 >> i) != 0 { c } else { '.' })
+			.collect();
+		format!("PC=${:04X} A=${:02X} X=${:02X} Y=${:02X} SP=${:02X} P={} cycles={}", self.pc, self.a, self.x, self.y, self.sp, flags, self.cycles)
+	}
+}
@@ -0,0 +1,347 @@
+//
+// src
+// spec.rs: Runs test specs for assembly routines in the simulator.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+// A test spec is a list of tests, each of which sets up registers and memory, calls routines, and checks the results:
+//
+// test add
+// 	a = $05
+// 	c = 0
+// 	mem buffer = 1, 2, 3
+// 	jsr add_three
+// 	expect a = $08
+// 	expect c = 0
+// 	expect mem buffer = 1, 2, 3
+// 	expect cycles <= 20
+// end
+//
+// Registers are a, x, y and sp, and flags are c, z, i, d, v and n. Values may be numbers or labels.
+//
+
+use std::collections::HashMap;
+
+use crate::lexer::{
+	Lexer,
+	TokenValue
+};
+use crate::parser::{
+	self,
+	Comparison,
+	ParseError
+};
+use crate::sim;
+use crate::sim::{
+	Cpu,
+	StopReason
+};
+
+// A value in a test spec
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Literal(u16),
+	Label(String)
+}
+
+// What a statement sets or checks
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+	A,
+	X,
+	Y,
+	Sp,
+	Flag(u8),
+
+	// The number of cycles taken by the last call
+	Cycles,
+
+	// Memory starting at an address
+	Memory(Value)
+}
+
+// A statement in a test
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+	Set(Target, Vec<Value>),
+	Call(Value),
+	Expect(Target, Comparison, Vec<Value>)
+}
+
+// A test and its statements with their line numbers
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+	pub name: String,
+	pub lino: u32,
+	pub statements: Vec<(u32, Statement)>
+}
+
+// A failed expectation or a call that didn't return
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestFailure {
+	pub lino: u32,
+	pub message: String
+}
+
+// Gets the next token, or an error if there isn't one
+fn next_token(lexer: &mut Lexer, err: &str) -> Result<TokenValue, ParseError> {
+	match lexer.next() {
+		Some(token) => Ok(token.value),
+		None => ParseError::new_from_lexer(lexer, err)
+	}
+}
+
+// Parses a value
+fn parse_value(lexer: &mut Lexer) -> Result<Value, ParseError> {
+	match next_token(lexer, "Expected value")? {
+		TokenValue::Bin(n) | TokenValue::Oct(n) | TokenValue::Dec(n) | TokenValue::Hex(n) => Ok(Value::Literal(n)),
+		TokenValue::Symbol(s) => Ok(Value::Label(s)),
+		_ => ParseError::new_from_lexer(lexer, "Expected number or label")
+	}
+}
+
+// Parses comma separated values up to the end of the line
+fn parse_values(lexer: &mut Lexer) -> Result<Vec<Value>, ParseError> {
+	let mut values = vec![parse_value(lexer)?];
+	while let Some(TokenValue::Comma) = lexer.peek().map(|v| v.value) {
+		lexer.next();
+		values.push(parse_value(lexer)?);
+	}
+	Ok(values)
+}
+
+// Parses what a statement sets or checks, given the name it starts with
+fn parse_target(lexer: &mut Lexer, name: &str) -> Result<Target, ParseError> {
+	Ok(match name {
+		"a" => Target::A,
+		"x" => Target::X,
+		"y" => Target::Y,
+		"sp" => Target::Sp,
+		"c" => Target::Flag(sim::FLAG_C),
+		"z" => Target::Flag(sim::FLAG_Z),
+		"i" => Target::Flag(sim::FLAG_I),
+		"d" => Target::Flag(sim::FLAG_D),
+		"v" => Target::Flag(sim::FLAG_V),
+		"n" => Target::Flag(sim::FLAG_N),
+		"cycles" => Target::Cycles,
+		"mem" => Target::Memory(parse_value(lexer)?),
+		_ => return ParseError::new_from_lexer(lexer, &format!("Unknown register or flag '{}'", name))
+	})
+}
+
+// Checks that only memory is given several values
+fn check_values(lexer: &Lexer, target: &Target, values: &[Value]) -> Result<(), ParseError> {
+	if values.len() > 1 && !matches!(target, Target::Memory(_)) {
+		return ParseError::new_from_lexer(lexer, "Only memory can have several values");
+	}
+	Ok(())
+}
+
+// Parses a test spec
+pub fn parse_spec(filename: &str, src: &str) -> Result<Vec<TestCase>, ParseError> {
+	let mut lexer = Lexer::new(filename, src);
+	let mut tests: Vec<TestCase> = Vec::new();
+	let mut current: Option<TestCase> = None;
+
+	while let Some(token) = lexer.next() {
+		let lino = token.lino;
+		let word = match token.value {
+			TokenValue::Newline => continue,
+			TokenValue::Symbol(s) => s.to_lowercase(),
+			_ => return ParseError::new_from_lexer(&lexer, "Expected statement")
+		};
+
+		// Start and end tests
+		if word == "test" {
+			if current.is_some() {
+				return ParseError::new_from_lexer(&lexer, "Tests cannot be nested (missing 'end')");
+			}
+			let name = match next_token(&mut lexer, "Expected test name")? {
+				TokenValue::Symbol(s) | TokenValue::String(s) => s,
+				_ => return ParseError::new_from_lexer(&lexer, "Expected test name")
+			};
+			current = Some(TestCase { name, lino, statements: Vec::new() });
+		} else if word == "end" {
+			match current.take() {
+				Some(test) => tests.push(test),
+				None => return ParseError::new_from_lexer(&lexer, "'end' without 'test'")
+			}
+
+		// Statements
+		} else {
+			let test = match current.as_mut() {
+				Some(test) => test,
+				None => return ParseError::new_from_lexer(&lexer, "Statements must be inside a test")
+			};
+
+			let statement = match word.as_str() {
+				"jsr" => Statement::Call(parse_value(&mut lexer)?),
+				"expect" => {
+					let name = match next_token(&mut lexer, "Expected register, flag, cycles or mem")? {
+						TokenValue::Symbol(s) => s.to_lowercase(),
+						_ => return ParseError::new_from_lexer(&lexer, "Expected register, flag, cycles or mem")
+					};
+					let target = parse_target(&mut lexer, &name)?;
+					let comparison = parser::parse_comparison(&mut lexer)?;
+					let values = parse_values(&mut lexer)?;
+					if comparison != Comparison::Equal && matches!(target, Target::Memory(_)) {
+						return ParseError::new_from_lexer(&lexer, "Memory can only be compared with '='");
+					}
+					check_values(&lexer, &target, &values)?;
+					Statement::Expect(target, comparison, values)
+				}
+				_ => {
+					let target = parse_target(&mut lexer, &word)?;
+					if target == Target::Cycles {
+						return ParseError::new_from_lexer(&lexer, "Cycles cannot be set");
+					}
+					match next_token(&mut lexer, "Expected '='")? {
+						TokenValue::Equals => (),
+						_ => return ParseError::new_from_lexer(&lexer, "Expected '='")
+					}
+					let values = parse_values(&mut lexer)?;
+					check_values(&lexer, &target, &values)?;
+					Statement::Set(target, values)
+				}
+			};
+			test.statements.push((lino, statement));
+		}
+
+		// Every statement ends with a newline
+		match lexer.next().map(|v| v.value) {
+			None | Some(TokenValue::Newline) => (),
+			_ => return ParseError::new_from_lexer(&lexer, "Expected end of line")
+		}
+	}
+
+	if current.is_some() {
+		return ParseError::new_from_lexer(&lexer, "Missing 'end'");
+	}
+	Ok(tests)
+}
+
+// Gets the value of a label or literal
+fn resolve(value: &Value, labels: &HashMap<String, u16>) -> Result<u16, String> {
+	match value {
+		Value::Literal(n) => Ok(*n),
+		Value::Label(label) => labels.get(label).copied().ok_or_else(|| format!("Undefined label '{}'", label))
+	}
+}
+
+// Gets the value of a label or literal that has to fit in a byte
+fn resolve_byte(value: &Value, labels: &HashMap<String, u16>) -> Result<u8, String> {
+	let n = resolve(value, labels)?;
+	if n > 255 {
+		return Err(format!("Value ${:04X} doesn't fit in a byte", n));
+	}
+	Ok(n as u8)
+}
+
+// Formats a target for failure messages
+fn target_name(target: &Target) -> String {
+	match target {
+		Target::A => String::from("a"),
+		Target::X => String::from("x"),
+		Target::Y => String::from("y"),
+		Target::Sp => String::from("sp"),
+		Target::Flag(flag) => String::from(match *flag {
+			sim::FLAG_C => "c",
+			sim::FLAG_Z => "z",
+			sim::FLAG_I => "i",
+			sim::FLAG_D => "d",
+			sim::FLAG_V => "v",
+			_ => "n"
+		}),
+		Target::Cycles => String::from("cycles"),
+		Target::Memory(Value::Literal(n)) => format!("mem ${:04X}", n),
+		Target::Memory(Value::Label(label)) => format!("mem {}", label)
+	}
+}
+
+// Runs a test on a 64 KiB image of memory, returning every expectation that failed
+// A call that doesn't return within max_cycles or reaches brk fails the test and stops it
+pub fn run_test(test: &TestCase, image: &[u8], labels: &HashMap<String, u16>, max_cycles: u64) -> Vec<TestFailure> {
+	let mut cpu = Cpu::new(image.to_vec());
+	let mut cycles = 0u64;
+	let mut failures = Vec::new();
+
+	for (lino, statement) in test.statements.iter() {
+		let fail = |message: String| TestFailure { lino: *lino, message };
+		let result = (|| -> Result<Option<String>, String> {
+			match statement {
+				Statement::Set(target, values) => {
+					let bytes = values.iter().map(|v| resolve_byte(v, labels)).collect::<Result<Vec<u8>, String>>()?;
+					match target {
+						Target::Memory(addr) => {
+							let addr = resolve(addr, labels)?;
+							for (i, byte) in bytes.into_iter().enumerate() {
+								cpu.write(addr.wrapping_add(i as u16), byte);
+							}
+						}
+						Target::A => cpu.a = bytes[0],
+						Target::X => cpu.x = bytes[0],
+						Target::Y => cpu.y = bytes[0],
+						Target::Sp => cpu.sp = bytes[0],
+						Target::Flag(flag) => cpu.set_flag(*flag, bytes[0] != 0),
+						Target::Cycles => ()
+					}
+					Ok(None)
+				}
+
+				Statement::Call(addr) => {
+					let addr = resolve(addr, labels)?;
+					cycles = match cpu.call(addr, max_cycles) {
+						Ok(cycles) => cycles,
+						Err(StopReason::Brk) => return Err(format!("Reached brk at ${:04X}", cpu.pc)),
+						Err(StopReason::CycleLimit) => return Err(format!("Did not return within {} cycles", max_cycles)),
+						Err(StopReason::InvalidOpcode(opcode)) => return Err(format!("Reached invalid opcode ${:02X} at ${:04X}", opcode, cpu.pc))
+					};
+					Ok(None)
+				}
+
+				Statement::Expect(Target::Memory(addr), _, values) => {
+					let addr = resolve(addr, labels)?;
+					let expected = values.iter().map(|v| resolve_byte(v, labels)).collect::<Result<Vec<u8>, String>>()?;
+					let found: Vec<u8> = (0..expected.len()).map(|i| cpu.read(addr.wrapping_add(i as u16))).collect();
+					if found == expected {
+						return Ok(None);
+					}
+					let format = |bytes: &[u8]| bytes.iter().map(|v| format!("${:02X}", v)).collect::<Vec<String>>().join(", ");
+					Ok(Some(format!("Expected mem ${:04X} = {}, found {}", addr, format(&expected), format(&found))))
+				}
+
+				Statement::Expect(target, comparison, values) => {
+					let expected = resolve(&values[0], labels)? as u64;
+					let found = match target {
+						Target::A => cpu.a as u64,
+						Target::X => cpu.x as u64,
+						Target::Y => cpu.y as u64,
+						Target::Sp => cpu.sp as u64,
+						Target::Flag(flag) => cpu.flag(*flag) as u64,
+						Target::Cycles => cycles,
+						Target::Memory(_) => unreachable!()
+					};
+					if comparison.test(found, expected) {
+						return Ok(None);
+					}
+					Ok(Some(match target {
+						Target::Cycles | Target::Flag(_) => format!("Expected {} {} {}, found {}", target_name(target), comparison, expected, found),
+						_ => format!("Expected {} {} ${:02X}, found ${:02X}", target_name(target), comparison, expected, found)
+					}))
+				}
+			}
+		})();
+
+		match result {
+			Ok(None) => (),
+			Ok(Some(message)) => failures.push(fail(message)),
+			Err(message) => {
+				failures.push(fail(message));
+				break;
+			}
+		}
+	}
+
+	failures
+}
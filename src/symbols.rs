@@ -0,0 +1,203 @@
+//
+// src
+// symbols.rs: Exports symbol tables for emulators and debuggers.
+//
+// Created by jenra.
+// Created on October 18 2026.
+//
+
+use std::fmt::Write;
+
+use crate::ines::PRG_BANK_SIZE;
+use crate::json::JsonValue;
+use crate::pass_1::{
+	Bank,
+	FirstPassResult,
+	SymbolKind
+};
+
+// Represents a symbol file format
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolFormat {
+	// VICE monitor labels (al C:2000 .start)
+	Vice,
+
+	// Mesen label file (.mlb)
+	Mesen,
+
+	// FCEUX name lists (.nl), one file per bank
+	Fceux,
+
+	// name = $addr
+	Plain,
+
+	// JSON array of symbols
+	Json,
+}
+
+impl SymbolFormat {
+	// Gets a symbol format from its name
+	pub fn from_name(name: &str) -> Option<SymbolFormat> {
+		match name {
+			"vice" => Some(SymbolFormat::Vice),
+			"mesen" | "mlb" => Some(SymbolFormat::Mesen),
+			"fceux" | "nl" => Some(SymbolFormat::Fceux),
+			"plain" => Some(SymbolFormat::Plain),
+			"json" => Some(SymbolFormat::Json),
+			_ => None
+		}
+	}
+
+	// Guesses a symbol format from the extension of a path, defaulting to plain
+	pub fn from_path(path: &str) -> SymbolFormat {
+		match path.rsplit('.').next() {
+			Some("vs") | Some("lbl") => SymbolFormat::Vice,
+			Some("mlb") => SymbolFormat::Mesen,
+			Some("nl") => SymbolFormat::Fceux,
+			Some("json") => SymbolFormat::Json,
+			_ => SymbolFormat::Plain
+		}
+	}
+}
+
+// A symbol with its resolved value
+#[derive(Debug, Clone)]
+pub struct ExportedSymbol {
+	pub name: String,
+	pub value: u16,
+	pub kind: SymbolKind,
+	pub bank: Bank,
+}
+
+// Collects the symbols of a file sorted by value then name, leaving out variables since their final value is meaningless and imports since they belong to another file
+pub fn collect_symbols(first_pass: &FirstPassResult) -> Vec<ExportedSymbol> {
+	let mut symbols: Vec<ExportedSymbol> = first_pass.symbol_table.iter()
+		.map(|(name, value)| ExportedSymbol {
+			name: name.clone(),
+			value: *value,
+			kind: first_pass.symbol_defs[name].kind,
+			bank: first_pass.symbol_defs[name].bank
+		})
+		.filter(|v| v.kind != SymbolKind::Variable && v.kind != SymbolKind::Import)
+		.collect();
+	symbols.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+	symbols
+}
+
+// Gets the name of the kind of a symbol
+fn kind_name(kind: SymbolKind) -> &'static str {
+	match kind {
+		SymbolKind::Label => "label",
+		SymbolKind::Constant => "define",
+		SymbolKind::Variable => "variable",
+		SymbolKind::Import => "import"
+	}
+}
+
+// Gets the PRG bank and PRG ROM offset of a symbol, if it is in PRG ROM
+// Symbols from $8000 up outside of a .bank are taken to be in bank 0, which may be mapped at $8000 or $C000
+fn prg_location(symbol: &ExportedSymbol) -> Option<(usize, u32)> {
+	let bank = match (symbol.bank, symbol.value) {
+		(Bank::Prg(n), _) => n as usize,
+		(Bank::None, 0x8000..=0xFFFF) => 0,
+		_ => return None
+	};
+	Some((bank, (bank * PRG_BANK_SIZE) as u32 + (symbol.value as u32 & (PRG_BANK_SIZE as u32 - 1))))
+}
+
+// Gets the Mesen memory type and offset of a symbol in the NES memory map
+fn mesen_location(symbol: &ExportedSymbol) -> (char, u32) {
+	if let Some((_, offset)) = prg_location(symbol) {
+		return ('P', offset);
+	}
+	match symbol.value {
+		addr @ 0x0000..=0x1FFF => ('R', addr as u32 & 0x07FF),
+		addr @ 0x6000..=0x7FFF => ('S', addr as u32 - 0x6000),
+		addr => ('G', addr as u32)
+	}
+}
+
+// Exports symbols in the given format; returns a list of (path suffix, contents) since some formats are split over several files
+pub fn export_symbols(symbols: &[ExportedSymbol], format: SymbolFormat) -> Vec<(String, String)> {
+	let mut out = String::new();
+
+	match format {
+		SymbolFormat::Vice => {
+			for symbol in symbols {
+				let _ = writeln!(out, "al C:{:04X} .{}", symbol.value, symbol.name);
+			}
+		}
+
+		SymbolFormat::Mesen => {
+			// CHR ROM isn't in the CPU's address space
+			for symbol in symbols.iter().filter(|v| !matches!(v.bank, Bank::Chr(_))) {
+				let (kind, offset) = mesen_location(symbol);
+				let _ = writeln!(out, "{}:{:04X}:{}", kind, offset, symbol.name);
+			}
+		}
+
+		SymbolFormat::Fceux => {
+			// RAM goes in one file, and every 16 KiB PRG bank gets its own file
+			let mut files: Vec<(String, String)> = Vec::new();
+			for symbol in symbols.iter().filter(|v| !matches!(v.bank, Bank::Chr(_))) {
+				let suffix = match prg_location(symbol) {
+					Some((bank, _)) => format!(".{:X}.nl", bank),
+					None => String::from(".ram.nl")
+				};
+
+				let line = format!("${:04X}#{}#{}\n", symbol.value, symbol.name, kind_name(symbol.kind));
+				match files.iter_mut().find(|v| v.0 == suffix) {
+					Some(file) => file.1.push_str(&line),
+					None => files.push((suffix, line))
+				}
+			}
+			return files;
+		}
+
+		SymbolFormat::Plain => {
+			for symbol in symbols {
+				let _ = writeln!(out, "{} = ${:04X} ; {}", symbol.name, symbol.value, kind_name(symbol.kind));
+			}
+		}
+
+		SymbolFormat::Json => {
+			let json = JsonValue::Array(symbols.iter().map(|v| JsonValue::object(vec![
+				("name", JsonValue::string(&v.name)),
+				("value", JsonValue::Number(v.value as i64)),
+				("kind", JsonValue::string(kind_name(v.kind))),
+			])).collect());
+			let _ = writeln!(out, "{}", json);
+		}
+	}
+
+	vec![(String::new(), out)]
+}
+
+// Reads a plain (name = $addr) or VICE (al C:addr .name) symbol file
+pub fn read_symbols(filename: &str, src: &str) -> Result<Vec<(String, u16)>, String> {
+	let mut symbols = Vec::new();
+	for (i, line) in src.lines().enumerate() {
+		let line = line.split(';').next().unwrap_or("").trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		let (name, value) = if let Some(rest) = line.strip_prefix("al ") {
+			let mut parts = rest.split_whitespace();
+			let value = parts.next().map(|v| v.trim_start_matches("C:"));
+			let name = parts.next().map(|v| v.trim_start_matches('.'));
+			(name, value)
+		} else {
+			let mut parts = line.splitn(2, '=');
+			let name = parts.next().map(|v| v.trim());
+			let value = parts.next().map(|v| v.trim().trim_start_matches('$'));
+			(name, value)
+		};
+
+		match (name, value.and_then(|v| u16::from_str_radix(v, 16).ok())) {
+			(Some(name), Some(value)) if !name.is_empty() => symbols.push((String::from(name), value)),
+			_ => return Err(format!("{}:{}: Invalid symbol", filename, i + 1))
+		}
+	}
+	Ok(symbols)
+}
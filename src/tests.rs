@@ -6,10 +6,16 @@
 // Created on October 18 2020.
 //
 
+#[allow(unused_imports)]
+use crate::disasm::*;
 #[allow(unused_imports)]
 use crate::lexer::*;
 #[allow(unused_imports)]
 use crate::parser::*;
+#[allow(unused_imports)]
+use crate::pass_1::*;
+#[allow(unused_imports)]
+use crate::pass_2::*;
 
 #[test]
 fn lexer_misc_chars() {
@@ -107,3 +113,1069 @@ fn parser_labels_opcodes() {
 		vec!["this_is_a_label", "", "", "label_without_an_opcode"]
 	);
 }
+
+#[test]
+fn pass_1_repeated_symbol() {
+	let string = String::from("label:\n\tnop\nlabel:\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let err = first_pass(&mut lexer).unwrap_err();
+	assert_eq!(err.lino, 3);
+	assert_eq!(err.message, "Symbol 'label' redefined (first defined at test.s:1)");
+}
+
+#[test]
+fn pass_1_variables() {
+	let string = String::from("
+		.set counter 1
+		lda #counter
+		counter = 2
+		lda #counter
+		.define constant 3
+		constant = 4
+	");
+	let mut lexer = Lexer::new("test.s", &string);
+	let err = first_pass(&mut lexer).unwrap_err();
+	assert_eq!(err.message, "Symbol 'constant' redefined (first defined at test.s:6)");
+
+	let string = String::from(".set counter 1\nlda #counter\ncounter = 2\nlda #counter\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let result = first_pass(&mut lexer).unwrap();
+	assert_eq!(result.symbol_table["counter"], 2);
+	assert!(matches!(result.lines[0].arg, InstructionArg::ByteArg(1)));
+	assert!(matches!(result.lines[1].arg, InstructionArg::ByteArg(2)));
+
+	let string = String::from(".segment \"CODE\"\nstart:\n.set ptr start\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let err = first_pass(&mut lexer).unwrap_err();
+	assert_eq!(err.message, "Setting variable ptr to label start in segment CODE");
+}
+
+#[test]
+fn listing_bytes_rows() {
+	let string = String::from(".origin $1000\nstart:\n\tlda #1\n\t.bytes 1, 2, 3, 4, 5, 6, 7, 8, 9\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let first_pass = first_pass(&mut lexer).unwrap();
+	let result = second_pass(&first_pass).unwrap();
+	let listing = crate::listing::generate_listing(&first_pass, &result);
+	let lines: Vec<&str> = listing.lines().collect();
+	assert_eq!(lines[4], "    3  1000  A9 01                     2      \tlda #1");
+	assert_eq!(lines[5], "    4  1002  01 02 03 04 05 06 07 08          \t.bytes 1, 2, 3, 4, 5, 6, 7, 8, 9");
+	assert_eq!(lines[6], "       100A  09");
+	assert!(listing.contains("start                     $1000  label     test.s:2"));
+
+	// brk takes up its signature byte too
+	let string = String::from(".origin $10FF\n\tbrk\n\tnop\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let first = crate::pass_1::first_pass(&mut lexer).unwrap();
+	let result = second_pass(&first).unwrap();
+	let listing = crate::listing::generate_listing(&first, &result);
+	assert_eq!(listing.lines().nth(3), Some("    2  10FF  00 00                     7      \tbrk"));
+	assert_eq!(result.slices(), vec![(0x10FF, &[0x00, 0x00, 0xEA][..])]);
+	assert_eq!(result.page_crossings.len(), 1);
+}
+
+#[test]
+fn pass_2_cycle_counts() {
+	let string = String::from(".origin $10FA\n.cycles_begin 20\nloop:\n\tlda $2000, x\n\tdex\n\tbne loop\n.cycles_end\n.cycles_begin 8\n\tsta ($00), y\n.cycles_end\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let result = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	let cycles: Vec<String> = result.cycles.iter().map(|v| v.cycles.to_string()).collect();
+	assert_eq!(cycles, vec!["4-5", "2", "2-4", "6"]);
+	assert_eq!((result.cycle_reports[0].min, result.cycle_reports[0].max), (8, 11));
+	assert_eq!(result.cycle_reports[1].to_string(), "test.s:8-10: 6 cycles (budget 8)");
+
+	let string = String::from(".cycles_begin 5\n\tlda $2000, x\n\tlda $2000, x\n.cycles_end\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let err = second_pass(&first_pass(&mut lexer).unwrap()).unwrap_err();
+	assert_eq!((err.lino, err.message.as_str()), (1, "Cycle budget of 5 exceeded (takes 8-10 cycles)"));
+
+	let string = String::from(".cycles_begin\n\tnop\n");
+	assert!(first_pass(&mut Lexer::new("test.s", &string)).is_err());
+
+	// Merged results keep the cycles of each file apart
+	let a = first_pass(&mut Lexer::new("a.s", ".origin $1000\n\tlda $2000, x\n")).unwrap();
+	let b = first_pass(&mut Lexer::new("b.s", ".origin $2000\n\tnop\n")).unwrap();
+	let mut result = second_pass(&b).unwrap();
+	result.merge(&second_pass(&a).unwrap()).unwrap();
+	let listing = crate::listing::generate_listing(&b, &result);
+	assert_eq!(listing.lines().nth(3), Some("    2  2000  EA                        2      \tnop"));
+}
+
+#[test]
+fn pass_2_page_crossings() {
+	use crate::pass_2::PageCrossingKind;
+
+	let string = String::from(".origin $10FB\nloop:\n\tdex\n\tbne loop\n\tlda $2000\n\tbne loop\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let result = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	let kinds: Vec<(u32, PageCrossingKind)> = result.page_crossings.iter().map(|v| (v.lino, v.kind.clone())).collect();
+	assert_eq!(kinds, vec![(5, PageCrossingKind::Instruction), (6, PageCrossingKind::Branch(0x10FB))]);
+
+	let string = String::from(".origin $10FA\n.page_align\nloop:\n\tdex\n\tbne loop\n.assert_same_page loop\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let result = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	assert_eq!(result.start(), Some(0x1100));
+	assert!(result.page_crossings.is_empty());
+
+	let string = String::from(".origin $10FD\nloop:\n\tdex\n\tbne loop\n.assert_same_page loop\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let err = second_pass(&first_pass(&mut lexer).unwrap()).unwrap_err();
+	assert_eq!((err.lino, err.message.as_str()), (5, "Label 'loop' ($10FD) is not on the same page as $1100"));
+}
+
+#[test]
+fn asserts_and_diagnostics() {
+	use crate::pass_1::DiagnosticKind;
+
+	let string = String::from(".origin $1000\n.assert end <= $1003, \"code too large\"\n.print \"building\"\n\tlda #1\nend:\n.warning \"done\"\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let first = first_pass(&mut lexer).unwrap();
+	let kinds: Vec<(u32, DiagnosticKind, &str)> = first.diagnostics.iter().map(|v| (v.lino, v.kind, v.message.as_str())).collect();
+	assert_eq!(kinds, vec![(3, DiagnosticKind::Print, "building"), (6, DiagnosticKind::Warning, "done")]);
+	assert!(second_pass(&first).is_ok());
+
+	let string = String::from(".origin $1000\n.assert end < $1002, \"code too large\"\n\tlda #1\nend:\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let err = second_pass(&first_pass(&mut lexer).unwrap()).unwrap_err();
+	assert_eq!((err.lino, err.message.as_str()), (2, "code too large ($1002 < $1002)"));
+
+	let string = String::from(".assert missing = 0\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	assert!(second_pass(&first_pass(&mut lexer).unwrap()).is_err());
+
+	let string = String::from(".origin $1000\nstart:\n.assert start != $1000\n");
+	let err = second_pass(&first_pass(&mut Lexer::new("test.s", &string)).unwrap()).unwrap_err();
+	assert_eq!((err.lino, err.message.as_str()), (3, "Assertion failed: start != $1000 ($1000 != $1000)"));
+	let string = String::from(".assert 1 ! 2\n");
+	assert!(first_pass(&mut Lexer::new("test.s", &string)).is_err());
+
+	let string = String::from("\tnop\n.error \"unsupported target\"\n");
+	let err = first_pass(&mut Lexer::new("test.s", &string)).unwrap_err();
+	assert_eq!((err.lino, err.message.as_str()), (2, "unsupported target"));
+}
+
+#[test]
+fn lsp_requests() {
+	use crate::json::{self, JsonValue};
+	use crate::lsp::Server;
+
+	assert_eq!(json::parse(r#"{"a": [1, -2.5, "\u00e9\n"], "b": null}"#).unwrap(), JsonValue::object(vec![
+		("a", JsonValue::Array(vec![JsonValue::Number(1), JsonValue::Number(-2), JsonValue::string("\u{e9}\n")])),
+		("b", JsonValue::Null)
+	]));
+	assert!(json::parse("[1,").is_err());
+
+	let mut server = Server::new();
+	let request = |id: i64, method: &str, line: i64, character: i64| json::parse(&format!(
+		r#"{{"jsonrpc": "2.0", "id": {}, "method": "{}", "params": {{"textDocument": {{"uri": "file:///test.s"}}, "position": {{"line": {}, "character": {}}}}}}}"#,
+		id, method, line, character
+	)).unwrap();
+	let open = json::parse(r#"{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {"textDocument": {"uri": "file:///test.s", "text": ".origin $1000\nstart:\n\tjmp start\n\tlda missing\n"}}}"#).unwrap();
+
+	let replies = server.handle(&open);
+	let diagnostics = replies[0].get("params").and_then(|v| v.get("diagnostics")).unwrap();
+	assert_eq!(diagnostics.to_string(), r#"[{"range":{"start":{"line":3,"character":0},"end":{"line":3,"character":12}},"severity":1,"source":"asm6502","message":"Undeclared label 'missing' used as value"}]"#);
+
+	let result = |replies: Vec<JsonValue>| replies[0].get("result").unwrap().to_string();
+	assert_eq!(result(server.handle(&request(1, "textDocument/definition", 2, 6))), r#"{"uri":"file:///test.s","range":{"start":{"line":1,"character":0},"end":{"line":1,"character":5}}}"#);
+	assert_eq!(result(server.handle(&request(2, "textDocument/references", 1, 2))).matches("\"uri\"").count(), 2);
+	assert!(result(server.handle(&request(3, "textDocument/hover", 2, 6))).contains("`start` = $1000"));
+	assert!(result(server.handle(&request(4, "textDocument/hover", 2, 2))).contains("| `jmp (abs)` | $6C | 3 | 5 |"));
+	assert!(result(server.handle(&request(5, "textDocument/completion", 3, 1))).contains(r#"{"label":"start","kind":6,"detail":"$1000"}"#));
+	assert!(server.handle(&request(6, "unknown", 0, 0))[0].get("error").is_some());
+
+	// Included files are read from open documents before the disk
+	let mut open_document = |uri: &str, text: &str| server.handle(&json::parse(&format!(
+		r#"{{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {{"textDocument": {{"uri": "{}", "text": {}}}}}}}"#,
+		uri, JsonValue::string(text)
+	)).unwrap());
+	open_document("file:///unsaved/inc.s", ".define VALUE 5\n");
+	let replies = open_document("file:///unsaved/main.s", ".include \"/unsaved/inc.s\"\nlda #VALUE\n");
+	assert_eq!(replies[0].get("params").and_then(|v| v.get("diagnostics")).unwrap().to_string(), "[]");
+
+	// Includes are also found next to the document
+	let replies = open_document("file:///unsaved/other.s", ".include \"inc.s\"\nlda #VALUE\n");
+	assert_eq!(replies[0].get("params").and_then(|v| v.get("diagnostics")).unwrap().to_string(), "[]");
+
+	// Positions are in UTF-16 code units
+	open_document("file:///utf16.s", "start:\n\tjmp start\n");
+	let replies = server.handle(&json::parse(&format!(
+		r#"{{"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {{"textDocument": {{"uri": "file:///utf16.s"}}, "contentChanges": [{{"text": {}}}]}}}}"#,
+		JsonValue::string("start:\n\tjmp start\n\t.bytes \"\u{1F600}\", start\n")
+	)).unwrap());
+	assert!(replies[0].to_string().contains(r#""end":{"line":2,"character":19}"#));
+	let references = server.handle(&json::parse(r#"{"jsonrpc": "2.0", "id": 8, "method": "textDocument/references", "params": {"textDocument": {"uri": "file:///utf16.s"}, "position": {"line": 2, "character": 15}}}"#).unwrap());
+	let references = result(references);
+	assert!(references.contains(r#"{"start":{"line":2,"character":14},"end":{"line":2,"character":19}}"#), "{}", references);
+}
+
+#[test]
+fn lsp_protocol() {
+	use crate::json::{self, JsonValue};
+	use crate::lsp::*;
+	use std::io::Cursor;
+
+	// URIs are percent encoded
+	assert_eq!(uri_to_path("file:///a%20b/%C3%A9.s"), "/a b/\u{e9}.s");
+	assert_eq!(path_to_uri("/a b/\u{e9}.s"), "file:///a%20b/%C3%A9.s");
+	assert_eq!(uri_to_path("file:///100%"), "/100%");
+
+	// Messages are framed by a Content-Length header
+	let message = JsonValue::object(vec![("id", JsonValue::Number(1)), ("text", JsonValue::string("\u{1F600}"))]);
+	let mut out = Vec::new();
+	write_message(&mut out, &message).unwrap();
+	assert!(out.starts_with(b"Content-Length: 22\r\n\r\n"));
+	let mut input = Cursor::new(out);
+	assert_eq!(read_message(&mut input).unwrap().unwrap(), message);
+	assert!(read_message(&mut input).is_none());
+	assert_eq!(read_message(&mut Cursor::new(b"X-Other: 1\r\n\r\n{}".to_vec())).unwrap().unwrap_err(), "Message has no Content-Length header");
+
+	let mut server = Server::new();
+	let reply = server.handle(&json::parse(r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#).unwrap());
+	assert!(reply[0].to_string().contains(r#""positionEncoding":"utf-16""#));
+
+	let mut notify = |method: &str, uri: &str, text: &str| server.handle(&json::parse(&format!(
+		r#"{{"jsonrpc": "2.0", "method": "{}", "params": {{"textDocument": {{"uri": "{}", "text": {}}}}}}}"#,
+		method, uri, JsonValue::string(text)
+	)).unwrap()).iter().map(|v| v.get("params").and_then(|v| v.get("diagnostics")).unwrap().to_string()).collect::<Vec<_>>();
+
+	// Warnings and printed messages have their own severities
+	assert_eq!(notify("textDocument/didOpen", "file:///w.s", ".warning \"slow\"\n.print \"hi\"\n"), vec![
+		r#"[{"range":{"start":{"line":0,"character":0},"end":{"line":0,"character":15}},"severity":2,"source":"asm6502","message":"slow"},"#.to_string()
+		+ r#"{"range":{"start":{"line":1,"character":0},"end":{"line":1,"character":11}},"severity":3,"source":"asm6502","message":"hi"}]"#
+	]);
+
+	// Errors in included files are shown at the top of the document
+	notify("textDocument/didOpen", "file:///bad.s", ".origin missing\n");
+	assert_eq!(notify("textDocument/didOpen", "file:///main.s", "nop\n.include \"/bad.s\"\n"), vec![
+		r#"[{"range":{"start":{"line":0,"character":0},"end":{"line":0,"character":0}},"severity":1,"source":"asm6502","message":"/bad.s:1: Setting origin to value of undefined label missing"}]"#
+	]);
+
+	// Files that import symbols can't be checked until they're linked
+	assert_eq!(notify("textDocument/didOpen", "file:///imp.s", ".import far\njmp far\n"), vec!["[]"]);
+
+	// Closing a document clears its diagnostics
+	assert_eq!(notify("textDocument/didClose", "file:///main.s", ""), vec!["[]"]);
+
+	// The exit code depends on whether shutdown was requested first
+	assert_eq!(server.exit_code(), 1);
+	server.handle(&json::parse(r#"{"jsonrpc": "2.0", "id": 2, "method": "shutdown"}"#).unwrap());
+	server.handle(&json::parse(r#"{"jsonrpc": "2.0", "method": "exit"}"#).unwrap());
+	assert!(server.exited());
+	assert_eq!(server.exit_code(), 0);
+}
+
+#[test]
+fn formatter_layout() {
+	use crate::formatter::*;
+
+	let string = String::from("; header\n  ; note\nstart:   LDA   ( ptr ) ,Y ;hi\n  .assert  end<=$C000 ,  \"too big\"\n.assert end!=0\nx   =   %0101\n\tSTA a: $2a , x\nend: .bytes \"a;b\" ,1\n");
+	let formatted = format_source("test.s", &string, &FormatOptions::default()).unwrap();
+	assert_eq!(formatted, "; header\n\t; note\nstart:\tlda (ptr), Y\t\t;hi\n\t.assert end <= $C000, \"too big\"\n\t.assert end != 0\nx = %0101\n\tsta a:$2a, x\nend:\t.bytes \"a;b\", 1\n");
+	assert_eq!(format_source("test.s", &formatted, &FormatOptions::default()).unwrap(), formatted);
+
+	let options = FormatOptions { case: MnemonicCase::Upper, operand_column: 16, tabs: false, ..FormatOptions::default() };
+	assert_eq!(format_source("test.s", "lda #1 ; one", &options).unwrap(), "        LDA     #1              ; one");
+	assert!(format_source("test.s", "lda (", &options).is_err());
+}
+
+#[test]
+fn cst_lossless() {
+	use crate::cst::{self, CstLineKind};
+
+	let sources = [
+		String::from("; héllo ünïcode\r\nstart:  lda #$2a\t; load\r\n  x   =   5\n\t.bytes \"a;b\" , 1\n\n"),
+		String::from("  ~ ! @ bad \"unterminated\n\tjmp ( ptr )"),
+		String::from("\t.bytes \"two\nlines\"\nend:\n"),
+		String::new(),
+	];
+	for src in sources.iter().chain(std::fs::read_dir("examples").unwrap().map(|v| std::fs::read_to_string(v.unwrap().path()).unwrap_or_default()).collect::<Vec<_>>().iter()) {
+		assert_eq!(cst::parse("test.s", src).to_string(), *src);
+	}
+
+	let mut tree = cst::parse("test.s", &sources[0]);
+	let kinds: Vec<CstLineKind> = tree.lines.iter().map(|v| v.kind).collect();
+	assert_eq!(kinds, vec![CstLineKind::Empty, CstLineKind::Label, CstLineKind::Assignment, CstLineKind::Pragma, CstLineKind::Empty]);
+	assert_eq!(tree.lines[1].comment().map(|v| v.text.as_str()), Some("; load\r"));
+	assert_eq!(tree.lines[1].statement().iter().map(|v| v.text.as_str()).collect::<Vec<_>>(), vec!["lda", "#", "$2a"]);
+	assert_eq!(tree.lines[2].lino, 3);
+
+	// Changing a token leaves everything around it alone
+	tree.lines[1].tokens[0].text = String::from("begin");
+	assert_eq!(tree.to_string(), sources[0].replacen("start", "begin", 1));
+}
+
+#[test]
+fn assembler_builder() {
+	use crate::{Assembler, AssemblerError, OutputFormat, Processor};
+	use std::error::Error;
+
+	let assembly = Assembler::new()
+		.source("main.s", ".origin $0200\nlda #COLOUR\nsta $D020\nrts\n")
+		.source("data.s", ".origin $0210\n.bytes 1, 2\n.warning \"unused\"\n")
+		.define("COLOUR", 2)
+		.processor(Processor::Nmos6502)
+		.format(OutputFormat::Bin)
+		.assemble()
+		.unwrap();
+	let mut expected = vec![0xA9, 0x02, 0x8D, 0x20, 0xD0, 0x60];
+	expected.resize(16, 0);
+	expected.extend([1, 2]);
+	assert_eq!(assembly.bytes, expected);
+	assert_eq!(assembly.segments.iter().map(|v| (v.addr, v.bytes.len())).collect::<Vec<_>>(), vec![(0x0200, 6), (0x0210, 2)]);
+	assert!(assembly.symbols.iter().any(|v| v.name == "COLOUR" && v.value == 2));
+	assert_eq!(assembly.diagnostics.len(), 1);
+	assert_eq!(assembly.diagnostics[0].to_string(), "Warning on data.s:3: unused");
+
+	// Errors can be inspected or reported
+	match Assembler::new().source("bad.s", "nop\nlda (").assemble() {
+		Err(AssemblerError::Parse(e)) => assert_eq!(e.lino, 2),
+		v => panic!("{:?}", v)
+	}
+	let err = Assembler::new().source_path("missing/file.s").assemble().unwrap_err();
+	assert!(err.to_string().starts_with("Could not read missing/file.s"));
+	assert!(err.source().is_some());
+}
+
+#[test]
+fn assembler_linking() {
+	use crate::files::MemoryFileSystem;
+	use crate::{Assembler, OutputFormat};
+
+	let string = String::from(".export add\n.segment \"CODE\"\nadd:\nrts\n");
+	let mut lexer = Lexer::new("add.s", &string);
+	let member = crate::archive::ArchiveMember {
+		name: String::from("add.o"),
+		bytes: crate::object::write_object(&first_pass(&mut lexer).unwrap())
+	};
+	let mut files = MemoryFileSystem::new();
+	files.insert("lib/math.a", crate::archive::write_archive(&[member]));
+
+	// Library members and the linker config are used as on the command line, including filled areas
+	let config = crate::linker::parse_config("link.cfg", "MEMORY { ROM: start = $8000, size = $10, fill = yes, fillval = $EA; }\nSEGMENTS { CODE: load = ROM; }\n").unwrap();
+	let assembly = Assembler::new()
+		.file_provider(files)
+		.source("main.s", ".import add\n.segment \"CODE\"\njsr add\n")
+		.library("lib/math.a")
+		.linker_config(config)
+		.format(OutputFormat::Bin)
+		.assemble()
+		.unwrap();
+	let mut expected = vec![0x20, 0x03, 0x80, 0x60];
+	expected.resize(16, 0xEA);
+	assert_eq!(assembly.bytes, expected);
+	assert!(assembly.symbols.iter().any(|v| v.name == "add" && v.value == 0x8003));
+	assert_eq!(assembly.usage.iter().map(|v| (v.name.as_str(), v.used)).collect::<Vec<_>>(), vec![("ROM", 4)]);
+}
+
+#[test]
+fn memory_file_system() {
+	use crate::files::{FileProvider, MemoryFileSystem};
+	use crate::{Assembler, OutputFormat};
+
+	let mut files = MemoryFileSystem::new();
+	files.insert("main.s", ".origin $0300\n.include \"consts.s\"\nlda #value\n.incbin \"gfx/tile.bin\"\n");
+	files.insert("lib/consts.s", ".define value $2A\n");
+	files.insert("gfx/tile.bin", vec![0xFF, 0x81]);
+	assert!(files.exists("gfx/tile.bin") && !files.exists("consts.s"));
+
+	// Includes are found through the include paths, and nothing is read from disk
+	let assembly = Assembler::new()
+		.file_provider(files.clone())
+		.source_path("main.s")
+		.include_path("lib")
+		.format(OutputFormat::Bin)
+		.assemble()
+		.unwrap();
+	assert_eq!(assembly.bytes, vec![0xA9, 0x2A, 0xFF, 0x81]);
+	assert!(format!("{:?}", Assembler::new().file_provider(files.clone()).source_path("main.s")).contains("Path(\"main.s\")"));
+
+	let mut lexer = Lexer::new("main.s", ".include \"consts.s\"\n");
+	let err = first_pass_with_options(&mut lexer, &FirstPassOptions::default(), &files).unwrap_err();
+	assert_eq!(err.message, "Could not read included file consts.s: no such file in memory");
+
+	// Predefined constants can be used in included files, and are only defined once
+	files.insert("inc.s", ".define LOCAL COLOUR\n");
+	files.insert("colour.s", ".origin $0400\n.include \"inc.s\"\nlda #LOCAL\nldx #COLOUR\n");
+	let assembly = Assembler::new()
+		.file_provider(files)
+		.source_path("colour.s")
+		.define("COLOUR", 2)
+		.format(OutputFormat::Bin)
+		.assemble()
+		.unwrap();
+	assert_eq!(assembly.bytes, vec![0xA9, 0x02, 0xA2, 0x02]);
+	assert_eq!(assembly.symbols.iter().filter(|v| v.name == "COLOUR").count(), 1);
+}
+
+#[test]
+fn symbols_export_formats() {
+	use crate::symbols::*;
+
+	let string = String::from(".define io $0300\n.origin $C000\nreset:\n\tnop\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let symbols = collect_symbols(&first_pass(&mut lexer).unwrap());
+	assert_eq!(export_symbols(&symbols, SymbolFormat::Vice)[0].1, "al C:0300 .io\nal C:C000 .reset\n");
+	assert_eq!(export_symbols(&symbols, SymbolFormat::Mesen)[0].1, "R:0300:io\nP:0000:reset\n");
+	assert_eq!(export_symbols(&symbols, SymbolFormat::Fceux), vec![
+		(String::from(".ram.nl"), String::from("$0300#io#define\n")),
+		(String::from(".0.nl"), String::from("$C000#reset#label\n")),
+	]);
+
+	// Banked labels are placed by their bank, wherever it is mapped
+	let string = String::from(".bank 0, $C000\nreset:\n\tnop\n.bank 2\nfar:\n\tnop\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let symbols = collect_symbols(&first_pass(&mut lexer).unwrap());
+	assert_eq!(export_symbols(&symbols, SymbolFormat::Mesen)[0].1, "P:8000:far\nP:0000:reset\n");
+	assert_eq!(export_symbols(&symbols, SymbolFormat::Fceux), vec![
+		(String::from(".2.nl"), String::from("$8000#far#label\n")),
+		(String::from(".0.nl"), String::from("$C000#reset#label\n")),
+	]);
+}
+
+#[test]
+fn pass_2_debug_ranges() {
+	let string = String::from(".origin $1000\nstart:\n  lda #1\n  .bytes 1, 2, 3\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let result = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	assert_eq!(result.debug.len(), 2);
+	let code = &result.debug[0];
+	assert_eq!((code.start, code.end, code.lino, code.col, code.kind), (0x1000, 0x1001, 3, 2, LineKind::Code));
+	assert_eq!(code.label.as_deref(), Some("start"));
+	let data = &result.debug[1];
+	assert_eq!((data.start, data.end, data.lino, data.kind), (0x1002, 0x1004, 4, LineKind::Data));
+}
+
+#[test]
+fn output_hex_formats() {
+	use crate::output::*;
+
+	let string = String::from(".origin $1000\nlda #1\n.origin $2000\n.bytes \"ab\"\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let result = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	let segments = result.slices();
+	assert_eq!(segments, vec![(0x1000, &[0xA9, 0x01][..]), (0x2000, &b"ab"[..])]);
+
+	assert_eq!(write_ihex(&segments, 16, Some(0x1000)), ":02100000A90144\n:0220000061621B\n:0400000300001000E9\n:00000001FF\n");
+	assert_eq!(write_srec(&segments, 1, Some(0x1000), ""), "S0030000FC\nS1041000A942\nS104100101E9\nS1042000617A\nS10420016278\nS5030004F8\nS9031000EC\n");
+	assert_eq!(clip_segments(segments, 0x1001, 0x2000), vec![(0x1001, &[0x01][..]), (0x2000, &b"a"[..])]);
+}
+
+#[test]
+fn ines_banks() {
+	use crate::ines::*;
+
+	let string = String::from(".ines mapper 2\n.ines prg 2\n.ines battery\n.bank 0\n\tnop\n.bank 1, $C000\n\trti\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let first_pass = first_pass(&mut lexer).unwrap();
+	let banks = second_pass_banked(&first_pass).unwrap();
+	assert_eq!(banks.iter().map(|v| v.0).collect::<Vec<Bank>>(), vec![Bank::Prg(0), Bank::Prg(1)]);
+
+	let rom = write_ines(first_pass.ines.as_ref().unwrap(), &banks).unwrap();
+	assert_eq!(rom.len(), 16 + 2 * PRG_BANK_SIZE);
+	assert_eq!(&rom[0..8], b"NES\x1A\x02\x00\x22\x00");
+	assert_eq!(rom[16], 0xEA);
+	assert_eq!(rom[16 + PRG_BANK_SIZE], 0x40);
+
+	// Data may end at $FFFF (such as the vectors) but not run past it
+	let result = second_pass(&crate::pass_1::first_pass(&mut Lexer::new("test.s", ".origin $FFFC\n.word $8000\n.word $8000\n")).unwrap()).unwrap();
+	assert_eq!(result.end(), Some(0xFFFF));
+	let err = crate::pass_1::first_pass(&mut Lexer::new("test.s", ".origin $FFFE\n.bytes 1, 2, 3\n")).unwrap_err();
+	assert_eq!(err.message, "Code or data runs past $FFFF");
+	assert!(crate::pass_1::first_pass(&mut Lexer::new("test.s", ".origin $FFFF\n.byte 1\n.byte 2\n")).is_err());
+
+	// Instructions too
+	assert!(crate::pass_1::first_pass(&mut Lexer::new("test.s", ".origin $FFFD\nnop\nrts\nnop\n")).is_ok());
+	let err = crate::pass_1::first_pass(&mut Lexer::new("test.s", ".origin $FFFE\njmp $1234\n")).unwrap_err();
+	assert_eq!(err.message, "Code or data runs past $FFFF");
+	assert!(crate::pass_1::first_pass(&mut Lexer::new("test.s", ".origin $FFFF\nrts\nnop\n")).is_err());
+}
+
+#[test]
+fn cbm_basic_stub() {
+	let string = String::from(".basic_stub\nstart:\n\trts\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let first_pass = first_pass(&mut lexer).unwrap();
+	let result = second_pass(&first_pass).unwrap();
+	assert_eq!(first_pass.symbol_table["start"], 0x080D);
+	assert_eq!(&result.image()[0x0801..0x080E], b"\x0B\x08\x0A\x00\x9E2061\x00\x00\x00\x60");
+
+	// VIC-20 with 8K expansion loads at $1201 (4609), so the target is 4621
+	assert_eq!(crate::cbm::basic_stub(0x1201, 2020), b"\x0B\x12\xE4\x07\x9E4621\x00\x00\x00");
+}
+
+#[test]
+fn cbm_d64_layout() {
+	let prg: Vec<u8> = (0..300).map(|v| v as u8).collect();
+	let disk = crate::cbm::write_d64("game", &prg).unwrap();
+	assert_eq!(disk.len(), 683 * 256);
+
+	// The file takes the first two sectors of track 1, linked together, with the last holding the index of its last byte
+	assert_eq!(&disk[0..4], &[1, 1, 0, 1]);
+	assert_eq!(&disk[256..260], &[0, 47, 254, 255]);
+	assert_eq!(disk[256 + 47], 43);
+
+	// BAM on track 18 sector 0, linking to the directory
+	let bam = 357 * 256;
+	assert_eq!(&disk[bam..bam + 4], b"\x12\x01A\x00");
+	assert_eq!(&disk[bam + 4..bam + 8], &[19, 0xFC, 0xFF, 0x1F]);
+	assert_eq!(&disk[bam + 8..bam + 12], &[21, 0xFF, 0xFF, 0x1F]);
+	assert_eq!(&disk[bam + 72..bam + 76], &[17, 0xFC, 0xFF, 0x07]);
+	assert_eq!(&disk[bam + 0x90..bam + 0x96], b"GAME\xA0\xA0");
+	assert_eq!(&disk[bam + 0xA2..bam + 0xA7], b"01\xA02A");
+
+	// A single closed PRG entry in track 18 sector 1 pointing at the file, with its size in sectors
+	let dir = bam + 256;
+	assert_eq!(&disk[dir..dir + 5], &[0, 0xFF, 0x82, 1, 0]);
+	assert_eq!(&disk[dir + 5..dir + 10], b"GAME\xA0");
+	assert_eq!(&disk[dir + 30..dir + 32], &[2, 0]);
+	assert_eq!(disk[dir + 32], 0);
+
+	// 664 free blocks is too few for a file over 664 * 254 bytes
+	assert!(crate::cbm::write_d64("big", &vec![0u8; 664 * 254 + 1]).is_err());
+}
+
+#[test]
+fn container_formats() {
+	let segments: Vec<(u16, &[u8])> = vec![(0x2000, &[0xA9, 0x01]), (0x2004, &[0x60])];
+	assert_eq!(crate::apple::write_dos33(&segments).unwrap(), vec![0x00, 0x20, 0x05, 0x00, 0xA9, 0x01, 0x00, 0x00, 0x60]);
+
+	// The length field can't hold all 64 KiB of memory
+	let memory = vec![0u8; 0x10000];
+	assert_eq!(crate::apple::write_dos33(&[(0x0000, &memory[..])]).unwrap_err(), "65536 bytes is too large for the length of a DOS 3.3 binary file");
+	assert_eq!(crate::apple::write_dos33(&[(0x0001, &memory[1..])]).unwrap().len(), 4 + 0xFFFF);
+	assert_eq!(crate::atari::write_xex(&segments, Some(0x2000), None), vec![
+		0xFF, 0xFF,
+		0x00, 0x20, 0x01, 0x20, 0xA9, 0x01,
+		0x04, 0x20, 0x04, 0x20, 0x60,
+		0xE0, 0x02, 0xE1, 0x02, 0x00, 0x20,
+	]);
+
+	let files = crate::acorn::segment_files("game", &segments, None);
+	assert_eq!(files.iter().map(|v| v.name.as_str()).collect::<Vec<&str>>(), vec!["GAME1", "GAME2"]);
+	let disc = crate::acorn::write_ssd("game", &files).unwrap();
+	assert_eq!(disc.len(), 4 * 256);
+	assert_eq!(&disc[8..24], b"GAME2  $GAME1  $");
+	assert_eq!(&disc[256 + 8..256 + 16], &[0x04, 0x20, 0x04, 0x20, 0x01, 0x00, 0xCC, 0x03]);
+}
+
+#[test]
+fn pass_2_segments_merge() {
+	let string = String::from(".origin $1000\n.byte 0\n.origin $1000\n.byte 1\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let err = second_pass(&first_pass(&mut lexer).unwrap()).unwrap_err();
+	assert_eq!((err.lino, err.message.as_str()), (4, "This line overrides previously written code"));
+
+	// The later line is blamed even when it sits below the code it overwrites
+	let string = String::from(".origin $1001\n.byte 0\n.origin $1000\nlda #1\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let err = second_pass(&first_pass(&mut lexer).unwrap()).unwrap_err();
+	assert_eq!((err.lino, err.message.as_str()), (4, "This line overrides previously written code"));
+
+	// Adjacent lines join into one segment regardless of source order
+	let string = String::from(".origin $1002\n.byte 3\n.origin $1000\n.byte 1\n.byte 2\n");
+	let mut lexer = Lexer::new("test.s", &string);
+	let result = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	assert_eq!(result.slices(), vec![(0x1000, &[1, 2, 3][..])]);
+
+	// Interleaved files merge as long as their written bytes don't overlap
+	let string = String::from(".origin $1000\n.byte 1\n.origin $1004\n.byte 2\n");
+	let mut lexer = Lexer::new("a.s", &string);
+	let a = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	let string = String::from(".origin $1002\n.byte 3\n.origin $1004\n.byte 4\n");
+	let mut lexer = Lexer::new("b.s", &string);
+	let b = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+
+	let mut total = AssemblerResult::new("total");
+	total.merge(&a).unwrap();
+	assert_eq!(total.merge(&b).unwrap_err(), "Could not merge a.s with b.s ($1004-$1004 overlaps $1004-$1004)");
+
+	let string = String::from(".origin $1002\n.byte 3\n");
+	let mut lexer = Lexer::new("b.s", &string);
+	let b = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	total.merge(&b).unwrap();
+	assert_eq!(total.slices(), vec![(0x1000, &[1][..]), (0x1002, &[3][..]), (0x1004, &[2][..])]);
+	assert_eq!((total.start(), total.end()), (Some(0x1000), Some(0x1004)));
+}
+
+#[test]
+fn linker_segments() {
+	let config = crate::linker::parse_config("link.cfg", "\
+# Memory map
+MEMORY {
+	ZP:  start = $0000, size = $0100;
+	ROM: start = $F000, size = $1000, fill = yes, fillval = $FF;
+}
+SEGMENTS {
+	ZEROPAGE: load = ZP;
+	CODE:     load = ROM;
+	RODATA:   load = ROM, align = $100;
+	VECTORS:  load = ROM, start = $FFFA;
+}
+").unwrap();
+
+	let string = String::from(".segment \"ZEROPAGE\"\nptr:\n.res 2\n.segment \"CODE\"\nstart:\nlda #<msg\n.segment \"RODATA\"\nmsg:\n.byte 1\n.segment \"CODE\"\njmp start\n.segment \"VECTORS\"\n.word ptr\n.word start\n");
+	let mut lexer = Lexer::new("a.s", &string);
+	let a = first_pass(&mut lexer).unwrap();
+	let string = String::from(".segment \"CODE\"\nloop:\njmp loop\n");
+	let mut lexer = Lexer::new("b.s", &string);
+	let b = first_pass(&mut lexer).unwrap();
+
+	// Segments are placed in order, with each file's part of a segment after the previous file's
+	let mut files = vec![a, b];
+	let usage = crate::linker::link(&config, &mut files).unwrap();
+	assert_eq!(usage.iter().map(|v| (v.name.as_str(), v.used)).collect::<Vec<_>>(), vec![("ZP", 2), ("ROM", 13)]);
+	assert_eq!(files[0].symbol_table["msg"], 0xF100);
+	assert_eq!(files[1].symbol_table["loop"], 0xF005);
+
+	let mut total = AssemblerResult::new("total");
+	for file in files.iter() {
+		total.merge(&second_pass(file).unwrap()).unwrap();
+	}
+	crate::linker::fill_areas(&config, &mut total);
+	let image = total.image();
+	assert_eq!(&image[0xF000..0xF009], &[0xA9, 0x00, 0x4C, 0x00, 0xF0, 0x4C, 0x05, 0xF0, 0xFF]);
+	assert_eq!(&image[0xFFFA..], &[0x00, 0x00, 0x00, 0xF0, 0xFF, 0xFF]);
+
+	// Segments that don't fit are an error
+	let string = String::from(".segment \"ZEROPAGE\"\n.res 257\n");
+	let mut lexer = Lexer::new("c.s", &string);
+	let mut files = vec![first_pass(&mut lexer).unwrap()];
+	assert_eq!(crate::linker::link(&config, &mut files).unwrap_err(), "Segment ZEROPAGE overflows memory area ZP by 1 bytes");
+
+	// Parts of a segment that use .page_align start on a page
+	let string = String::from(".segment \"CODE\"\nnop\n");
+	let c = first_pass(&mut Lexer::new("c.s", &string)).unwrap();
+	let string = String::from(".segment \"CODE\"\nnop\n.page_align\ntable:\n.byte 1\n");
+	let d = first_pass(&mut Lexer::new("d.s", &string)).unwrap();
+	let mut files = vec![c, d];
+	crate::linker::link(&config, &mut files).unwrap();
+	assert_eq!(files[1].symbol_table["table"], 0xF200);
+	assert!(crate::o65::write_o65(&mut files).unwrap_err().contains("uses .page_align"));
+}
+
+#[test]
+fn object_link_imports() {
+	let config = crate::linker::parse_config("link.cfg", "MEMORY { ROM: start = $8000, size = $100; }\nSEGMENTS { CODE: load = ROM; }\n").unwrap();
+
+	// Object files round trip through the first pass result
+	let string = String::from(".import print\n.export main\n.segment \"CODE\"\nmain:\njsr print\nbne main\n.word print\n");
+	let mut lexer = Lexer::new("a.s", &string);
+	let bytes = crate::object::write_object(&first_pass(&mut lexer).unwrap());
+	let a = crate::object::read_object("a.o", &bytes).unwrap();
+	assert_eq!(crate::object::write_object(&a), bytes);
+	assert!(a.source.is_empty());
+
+	let string = String::from(".global print, main\n.segment \"CODE\"\nprint:\njmp main\n");
+	let mut lexer = Lexer::new("b.s", &string);
+	let b = first_pass(&mut lexer).unwrap();
+
+	let mut files = vec![a, b];
+	crate::linker::link(&config, &mut files).unwrap();
+	let a = second_pass(&files[0]).unwrap();
+	let b = second_pass(&files[1]).unwrap();
+	assert_eq!(a.slices(), vec![(0x8000, &[0x20, 0x07, 0x80, 0xD0, 0xFB, 0x07, 0x80][..])]);
+	assert_eq!(b.slices(), vec![(0x8007, &[0x4C, 0x00, 0x80][..])]);
+
+	// Undefined and duplicate symbols
+	let string = String::from(".import missing\n");
+	let mut lexer = Lexer::new("c.s", &string);
+	let mut files = vec![first_pass(&mut lexer).unwrap()];
+	assert_eq!(crate::linker::link(&config, &mut files).unwrap_err(), "Undefined symbol 'missing' imported at c.s:1");
+
+	let string = String::from(".export x\nx:\n");
+	let mut lexer = Lexer::new("d.s", &string);
+	let d = first_pass(&mut lexer).unwrap();
+	let mut lexer = Lexer::new("e.s", &string);
+	let e = first_pass(&mut lexer).unwrap();
+	assert_eq!(crate::linker::link(&config, &mut [d, e]).unwrap_err(), "Symbol 'x' is exported by both d.s and e.s");
+
+	// Variables can't be exported, but symbols exported by included files are
+	let string = String::from(".set x 1
+.export x
+");
+	let err = first_pass(&mut Lexer::new("f.s", &string)).unwrap_err();
+	assert_eq!(err.message, "Exported symbol 'x' is a variable");
+
+	let mut files = crate::files::MemoryFileSystem::new();
+	files.insert("lib.s", ".export helper\n.segment \"CODE\"\nhelper:\n");
+	let string = String::from(".include \"lib.s\"\n");
+	let result = first_pass_with_options(&mut Lexer::new("g.s", &string), &FirstPassOptions::default(), &files).unwrap();
+	assert_eq!(result.exports, vec![String::from("helper")]);
+}
+
+#[test]
+fn archive_lazy_members() {
+	// Library members are only added when something imports their symbols, including other members
+	let sources = [
+		("mul.s", ".import add\n.export mul\nmul:\njsr add\n"),
+		("add.s", ".export add\nadd:\nrts\n"),
+		("div.s", ".export div\ndiv:\nrts\n"),
+	];
+	let members: Vec<crate::archive::ArchiveMember> = sources.iter().map(|(name, source)| {
+		let string = String::from(*source);
+		let mut lexer = Lexer::new(name, &string);
+		crate::archive::ArchiveMember {
+			name: name.replace(".s", ".o"),
+			bytes: crate::object::write_object(&first_pass(&mut lexer).unwrap())
+		}
+	}).collect();
+
+	let bytes = crate::archive::write_archive(&members);
+	assert_eq!(crate::archive::read_archive("math.a", &bytes).unwrap(), members);
+	let library = crate::archive::read_objects("math.a", &bytes).unwrap();
+
+	let string = String::from(".import mul\njsr mul\n");
+	let mut lexer = Lexer::new("main.s", &string);
+	let mut files = vec![first_pass(&mut lexer).unwrap()];
+	crate::linker::add_library_members(&mut files, vec![library]);
+	assert_eq!(files.iter().map(|v| v.filename.as_str()).collect::<Vec<&str>>(), vec!["main.s", "mul.s", "add.s"]);
+}
+
+#[test]
+fn o65_round_trip() {
+	let string = String::from(".import putc\n.export main\n.segment \"ZEROPAGE\"\nptr:\n.res 2\n.segment \"CODE\"\nmain:\nlda #<msg\nldx #>msg\nsta ptr\njsr putc\nrts\n.segment \"DATA\"\nmsg:\n.word main\n");
+	let mut lexer = Lexer::new("r.s", &string);
+	let mut files = vec![first_pass(&mut lexer).unwrap()];
+	let bytes = crate::o65::write_o65(&mut files).unwrap();
+
+	// Header, text, and data
+	assert_eq!(&bytes[0..26], &[
+		0x01, 0x00, b'o', b'6', b'5', 0x00, 0x00, 0x10,
+		0x00, 0x10, 0x0B, 0x00, 0x0B, 0x10, 0x02, 0x00, 0x0D, 0x10, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+	]);
+	assert_eq!(&bytes[33..46], &[0xA9, 0x0B, 0xA2, 0x10, 0x8D, 0x00, 0x00, 0x20, 0x00, 0x00, 0x60, 0x00, 0x10]);
+
+	// Undefined references, relocations, and exports
+	assert_eq!(&bytes[46..], &[
+		0x01, 0x00, b'p', b'u', b't', b'c', 0x00,
+		0x02, 0x23, 0x02, 0x43, 0x0B, 0x02, 0x85, 0x03, 0x80, 0x00, 0x00, 0x00,
+		0x01, 0x82, 0x00,
+		0x01, 0x00, b'm', b'a', b'i', b'n', 0x00, 0x02, 0x00, 0x10,
+	]);
+
+	// Reading it back and linking it elsewhere relocates every reference
+	let read = crate::o65::read_o65("r.o65", &bytes).unwrap();
+	let string = String::from(".export putc\n.segment \"CODE\"\nputc:\nrts\n");
+	let mut lexer = Lexer::new("p.s", &string);
+	let mut files = vec![read, first_pass(&mut lexer).unwrap()];
+	let config = crate::linker::parse_config("link.cfg", "MEMORY { ZP: start = $80, size = $80; ROM: start = $C000, size = $100; }\nSEGMENTS { ZEROPAGE: load = ZP; CODE: load = ROM; DATA: load = ROM; }\n").unwrap();
+	crate::linker::link(&config, &mut files).unwrap();
+	assert_eq!(second_pass(&files[0]).unwrap().slices(), vec![
+		(0xC000, &[0xA9, 0x0C, 0xA2, 0xC0, 0x8D, 0x80, 0x00, 0x20, 0x0B, 0xC0, 0x60][..]),
+		(0xC00C, &[0x00, 0xC0][..]),
+	]);
+
+	// Names and relocation tables that run off the end of the file
+	for len in [48, 60] {
+		assert_eq!(crate::o65::read_o65("r.o65", &bytes[..len]).unwrap_err(), "r.o65 is truncated");
+	}
+}
+
+#[test]
+fn o65_errors() {
+	let write = |source: &str| {
+		let string = String::from(source);
+		let mut lexer = Lexer::new("o.s", &string);
+		crate::o65::write_o65(&mut [first_pass(&mut lexer).unwrap()])
+	};
+
+	// Everything has to be relocatable and fit in its segment
+	assert_eq!(write(".origin $1000\nnop\n").unwrap_err(), "o.s:2: o65 output requires all code to be in a named segment");
+	assert_eq!(write(".segment \"DATA\"\nfar:\n.segment \"CODE\"\nbeq far\n").unwrap_err(), "o.s:4: Branch to far cannot be relocated");
+	assert_eq!(write(".segment \"CODE\"\nptr:\n.segment \"BSS\"\n.word ptr\n").unwrap_err(), "o.s:4: The BSS segment cannot contain code or data");
+	assert_eq!(write(".segment \"ZEROPAGE\"\n.res $101\n").unwrap_err(), "zp segment is 257 bytes, which does not fit in zero page");
+
+	// Only 6502 files can be read
+	let mut bytes = write(".segment \"CODE\"\nrts\n").unwrap();
+	assert!(crate::o65::read_o65("r.o65", &bytes).is_ok());
+	assert_eq!(crate::o65::read_o65("r.txt", b"hello").unwrap_err(), "r.txt is not an o65 file");
+	bytes[7] |= 0x80;
+	assert_eq!(crate::o65::read_o65("r.o65", &bytes).unwrap_err(), "r.o65 uses 65816 or 32 bit mode, which is not supported");
+
+	// Relocations have to be inside their segment
+	let bytes = write(".segment \"CODE\"\nmain:\n.word main\n").unwrap();
+	let table = bytes.len() - bytes.windows(3).rev().position(|v| v == [0x01, 0x82, 0x00]).unwrap() - 3;
+	for (diff, addr) in [(0x02, 0x1001), (0x03, 0x1002)] {
+		let mut bytes = bytes.clone();
+		bytes[table] = diff;
+		assert_eq!(crate::o65::read_o65("r.o65", &bytes).unwrap_err(), format!("r.o65 is corrupt (relocation at ${:04X} is outside its segment)", addr));
+	}
+}
+
+#[test]
+fn disasm_round_trip() {
+	// Every opcode the first pass emits decodes back to its mnemonic
+	let table = opcode_table();
+	assert_eq!(table.iter().filter(|v| v.is_some()).count(), 151);
+	assert_eq!(table[0xB6].unwrap().kind, OperandKind::ZeroPageY);
+	assert_eq!(table[0xB9].unwrap().kind, OperandKind::AbsoluteY);
+
+	let bytes = [0xA2, 0x00, 0xBD, 0x10, 0xC0, 0xF0, 0x06, 0x20, 0xD2, 0xFF, 0xE8, 0xD0, 0xF5, 0x60, 0xAD, 0x10, 0x00, 0xFF];
+	let source = disassemble(&bytes, 0xC000, &[(String::from("chrout"), 0xFFD2)], &[]).unwrap();
+	assert_eq!(source, String::from(".define chrout $FFD2\n.origin $C000\n\tldx #$00\t; $C000\nLC002:\n")
+		+ "\tlda $C010, x\t; $C002\n\tbeq LC00D\t; $C005\n\tjsr chrout\t; $C007\n\tinx\t; $C00A\n\tbne LC002\t; $C00B\nLC00D:\n\trts\t; $C00D\n"
+		+ "\tlda a:$10\t; $C00E\n\t.bytes $FF\t; $C011\n");
+
+	// Assembling the source gives back the same bytes
+	let mut lexer = Lexer::new("d.s", &source);
+	let result = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	assert_eq!(result.slices(), vec![(0xC000, &bytes[..])]);
+}
+
+// Flattens the output of the assembler into the bytes from the first to the last written address, with gaps set to zero
+#[cfg(test)]
+fn flatten(result: &AssemblerResult) -> (u16, Vec<u8>) {
+	let slices = result.slices();
+	let start = slices.iter().map(|v| v.0 as usize).min().unwrap_or(0);
+	let end = slices.iter().map(|v| v.0 as usize + v.1.len()).max().unwrap_or(0);
+	let mut bytes = vec![0u8; end - start];
+	for (addr, slice) in slices {
+		bytes[addr as usize - start..][..slice.len()].copy_from_slice(slice);
+	}
+	(start as u16, bytes)
+}
+
+// Disassembles bytes, assembles the source again, and checks that the same bytes come back
+#[cfg(test)]
+fn assert_round_trip(bytes: &[u8], load: u16, entries: &[u16]) {
+	let source = disassemble(bytes, load, &[], entries).unwrap();
+	let mut lexer = Lexer::new("d.s", &source);
+	let result = first_pass(&mut lexer).and_then(|v| second_pass(&v)).unwrap_or_else(|e| panic!("{:?} in\n{}", e, source));
+	assert_eq!(flatten(&result), (load, bytes.to_vec()), "in\n{}", source);
+}
+
+#[test]
+fn disasm_round_trip_examples() {
+	for entry in std::fs::read_dir("examples").unwrap() {
+		let path = entry.unwrap().path();
+		if path.extension().map(|v| v != "s").unwrap_or(true) {
+			continue;
+		}
+
+		// Some examples are meant to fail
+		let source = std::fs::read_to_string(&path).unwrap();
+		let mut lexer = Lexer::new(&path.to_string_lossy(), &source);
+		let result = match first_pass(&mut lexer).and_then(|v| second_pass(&v)) {
+			Ok(result) => result,
+			Err(_) => continue
+		};
+
+		for (addr, bytes) in result.slices() {
+			assert_round_trip(bytes, addr, &[]);
+			assert_round_trip(bytes, addr, &[addr]);
+		}
+	}
+}
+
+#[test]
+fn disasm_round_trip_random() {
+	// xorshift, so every run generates the same programs
+	let mut state = 0x2545F491u32;
+	let mut rand = move || {
+		state ^= state << 13;
+		state ^= state >> 17;
+		state ^= state << 5;
+		state
+	};
+
+	let table = opcode_table();
+	let opcodes: Vec<u8> = (0..=255).filter(|v| table[*v as usize].is_some()).collect();
+	for _ in 0..50 {
+		// Generate a program with a label before every instruction, so branches and jumps have somewhere to go
+		let origin = 0x0200 + (rand() % 0x8000) as u16;
+		let count = 200;
+		let mut source = format!(".origin ${:04X}\n", origin);
+		for i in 0..count {
+			let opcode = opcodes[rand() as usize % opcodes.len()];
+			let info = table[opcode as usize].unwrap();
+			let operand = match (info.kind, rand() % 4) {
+				(OperandKind::ZeroPage, _)
+				| (OperandKind::ZeroPageX, _)
+				| (OperandKind::ZeroPageY, _)
+				| (OperandKind::IndirectX, _)
+				| (OperandKind::IndirectY, _)
+				| (OperandKind::Immediate, _)
+				| (_, 0) => rand() as u16 & 0xFF,
+				_ => rand() as u16
+			};
+
+			// Branches go to a nearby label, and jumps sometimes go to any label
+			let label = match (info.kind, info.mnemonic) {
+				(OperandKind::Relative, _) => Some(format!("l{}", (i + rand() as usize % 20).saturating_sub(10).min(count - 1))),
+				(OperandKind::Absolute, "jmp") | (OperandKind::Absolute, "jsr") if rand() % 2 == 0 => Some(format!("l{}", rand() as usize % count)),
+				_ => None
+			};
+
+			let d = Decoded { addr: 0, opcode, info, operand };
+			source += &format!("l{}:\n\t{}\n", i, d.to_instruction(|_| label.clone()));
+		}
+
+		let mut lexer = Lexer::new("r.s", &source);
+		let result = first_pass(&mut lexer).and_then(|v| second_pass(&v)).unwrap_or_else(|e| panic!("{:?} in\n{}", e, source));
+		let (load, bytes) = flatten(&result);
+		assert_eq!(load, origin);
+		assert_round_trip(&bytes, load, &[]);
+		assert_round_trip(&bytes, load, &[load]);
+	}
+
+	// Binaries loaded above the vectors don't have any
+	assert_round_trip(&[0x02, 0x02], 0xFFFE, &[0xFFFE]);
+	assert_round_trip(&[0x00, 0x80, 0x00, 0x80], 0xFFFC, &[0xFFFC]);
+	assert_eq!(disassemble(&[0xEA, 0xEA], 0xFFFF, &[], &[]).unwrap_err(), "2 bytes loaded at $FFFF run past $FFFF");
+}
+
+#[test]
+fn sim_flags_and_cycles() {
+	let string = String::from(".origin $0200\nsed\nsec\nlda #$58\nadc #$46\nsta $10\nsec\nlda #$46\nsbc #$12\nsta $11\ncld\nclc\nlda #$50\nadc #$50\nsta $12\nphp\npla\nsta $13\nldx #1\nlda $10FF, x\nbrk\n");
+	let mut lexer = Lexer::new("s.s", &string);
+	let result = second_pass(&first_pass(&mut lexer).unwrap()).unwrap();
+	let mut cpu = crate::sim::Cpu::new(result.image());
+	cpu.pc = 0x0200;
+
+	assert_eq!(cpu.run(1000), crate::sim::StopReason::Brk);
+	assert_eq!(cpu.pc, 0x0220);
+
+	// Decimal addition and subtraction, then binary overflow
+	assert_eq!(&cpu.memory[0x10..0x14], &[0x05, 0x34, 0xA0, 0xF4]);
+
+	// Reading across a page takes an extra cycle
+	assert_eq!(cpu.cycles, 48);
+
+	// The cycle limit is checked before every instruction
+	assert_eq!(cpu.run(40), crate::sim::StopReason::CycleLimit);
+}
+
+// Assembles code and runs it from the start label until it reaches brk
+#[cfg(test)]
+fn sim_run(source: &str) -> crate::sim::Cpu {
+	let string = String::from(source);
+	let mut lexer = Lexer::new("s.s", &string);
+	let first = first_pass(&mut lexer).unwrap();
+	let mut cpu = crate::sim::Cpu::new(second_pass(&first).unwrap().image());
+	cpu.pc = first.symbol_table["start"];
+	assert_eq!(cpu.run(1000), crate::sim::StopReason::Brk, "{}", source);
+	cpu
+}
+
+#[test]
+fn sim_opcodes() {
+	// The registers, flags and cycles after each snippet, starting with I set
+	let cases = [
+		// Decimal mode sets N and V before the high digit is adjusted, and Z from the binary sum
+		("sed\nclc\nlda #$99\nadc #$01\n", "A=$00 X=$00 Y=$00 SP=$FD P=N.-.DI.C cycles=8"),
+		("sed\nsec\nlda #$79\nadc #$00\n", "A=$80 X=$00 Y=$00 SP=$FD P=NV-.DI.. cycles=8"),
+		("sed\nclc\nlda #$50\nadc #$50\n", "A=$00 X=$00 Y=$00 SP=$FD P=NV-.DI.C cycles=8"),
+		("sed\nsec\nlda #$00\nsbc #$01\n", "A=$99 X=$00 Y=$00 SP=$FD P=N.-.DI.. cycles=8"),
+		("sed\nsec\nlda #$20\nsbc #$20\n", "A=$00 X=$00 Y=$00 SP=$FD P=..-.DIZC cycles=8"),
+		("sed\nclc\nlda #$10\nsbc #$05\n", "A=$04 X=$00 Y=$00 SP=$FD P=..-.DI.C cycles=8"),
+
+		// Binary overflow and borrow
+		("clc\nlda #$7F\nadc #$01\n", "A=$80 X=$00 Y=$00 SP=$FD P=NV-..I.. cycles=6"),
+		("sec\nlda #$80\nsbc #$01\n", "A=$7F X=$00 Y=$00 SP=$FD P=.V-..I.C cycles=6"),
+
+		// Comparisons, bit tests, shifts and rotates
+		("ldx #$10\ncpx #$20\n", "A=$00 X=$10 Y=$00 SP=$FD P=N.-..I.. cycles=4"),
+		("lda #$40\ncmp #$40\n", "A=$40 X=$00 Y=$00 SP=$FD P=..-..IZC cycles=4"),
+		("lda #$C0\nsta $10\nlda #$01\nbit $10\n", "A=$01 X=$00 Y=$00 SP=$FD P=NV-..IZ. cycles=10"),
+		("lda #$81\nasl\n", "A=$02 X=$00 Y=$00 SP=$FD P=..-..I.C cycles=4"),
+		("sec\nlda #$01\nror\n", "A=$80 X=$00 Y=$00 SP=$FD P=N.-..I.C cycles=6"),
+		("lda #$80\nsta $10\nclc\nrol $10\nlda $10\n", "A=$00 X=$00 Y=$00 SP=$FD P=..-..IZC cycles=15"),
+		("ldy #$00\ndey\n", "A=$00 X=$00 Y=$FF SP=$FD P=N.-..I.. cycles=4"),
+
+		// plp ignores the break flag, which php always pushes set
+		("php\npla\nsta $10\nlda #$FF\npha\nplp\nldx $10\n", "A=$FF X=$34 Y=$00 SP=$FD P=.V-.DI.C cycles=22"),
+
+		// Indexed reads take an extra cycle when crossing a page, but stores always take the longer time
+		("ldx #$01\nlda $02FF, x\n", "A=$00 X=$01 Y=$00 SP=$FD P=..-..IZ. cycles=7"),
+		("ldx #$01\nlda $0200, x\n", "A=$01 X=$01 Y=$00 SP=$FD P=..-..I.. cycles=6"),
+		("ldx #$01\nsta $0200, x\n", "A=$00 X=$01 Y=$00 SP=$FD P=..-..I.. cycles=7"),
+	];
+	for (source, expected) in cases.iter() {
+		let cpu = sim_run(&format!(".origin $0200\nstart:\n{}", source));
+		assert_eq!(&cpu.format_registers()[9..], *expected, "{}", source);
+	}
+}
+
+#[test]
+fn sim_jumps_and_branches() {
+	// jmp ($xxFF) reads the high byte from the start of the same page
+	let cpu = sim_run(".origin $0400\n.byte $06\n.origin $04FF\n.byte $20\n.origin $0500\n.byte $FF\n.origin $0300\nstart:\njmp ($04FF)\n");
+	assert_eq!((cpu.pc, cpu.cycles), (0x0620, 5));
+
+	// Branches take 2 cycles when not taken, 3 when taken and 4 when taken to another page
+	let cpu = sim_run(".origin $02F0\nstart:\nclc\nbcc far\n.origin $0310\nfar:\nsec\nbcc far\nbcs near\nnop\nnear:\nlda #1\n");
+	assert_eq!((cpu.a, cpu.cycles), (1, 2 + 4 + 2 + 2 + 3 + 2));
+
+	// Pages are compared from the instruction after the branch, including going backwards
+	let cpu = sim_run(".origin $0400\nback:\ninx\nbrk\nstart:\nlda #0\nbeq back\n");
+	assert_eq!((cpu.pc, cpu.x, cpu.cycles), (0x0401, 1, 2 + 3 + 2));
+	let cpu = sim_run(".origin $03FD\nback:\ninx\nbrk\n.origin $0410\nstart:\nlda #0\nbeq back\n");
+	assert_eq!((cpu.pc, cpu.x, cpu.cycles), (0x03FE, 1, 2 + 4 + 2));
+	let cpu = sim_run(".origin $04FC\nstart:\nlda #0\nbeq next\nnext:\nbrk\n");
+	assert_eq!((cpu.pc, cpu.cycles), (0x0500, 2 + 3));
+
+	// jsr and rts take 6 cycles each and leave the stack where it was
+	let cpu = sim_run(".origin $0200\nstart:\njsr sub\nldy #3\nbrk\nsub:\nldx #2\nrts\n");
+	assert_eq!((cpu.x, cpu.y, cpu.sp, cpu.cycles), (2, 3, 0xFD, 6 + 2 + 6 + 2));
+}
+
+#[test]
+fn spec_runs_tests() {
+	let string = String::from(".origin $0600\nadd_three:\nclc\nadc #3\nrts\ncopy:\nldx #2\nloop:\nlda src, x\nsta buffer, x\ndex\nbpl loop\nrts\nsrc:\n.bytes 1, 2, 3\nbuffer:\n.bytes 0, 0, 0\n");
+	let mut lexer = Lexer::new("lib.s", &string);
+	let first = first_pass(&mut lexer).unwrap();
+	let image = second_pass(&first).unwrap().image();
+
+	let tests = crate::spec::parse_spec("lib.t", "test add\n\ta = $05\n\tjsr add_three\n\texpect a = 8\n\texpect cycles <= 10\nend\n\ntest copy ; copies src\n\tjsr copy\n\texpect mem buffer = 1, 2, 4\n\texpect x = $FF\n\texpect cycles < 20\nend\n").unwrap();
+	assert_eq!(tests.len(), 2);
+	assert!(crate::spec::run_test(&tests[0], &image, &first.symbol_table, 1000).is_empty());
+	assert_eq!(crate::spec::run_test(&tests[1], &image, &first.symbol_table, 1000), vec![
+		crate::spec::TestFailure { lino: 10, message: String::from("Expected mem $0613 = $01, $02, $04, found $01, $02, $03") },
+		crate::spec::TestFailure { lino: 12, message: String::from("Expected cycles < 20, found 49") },
+	]);
+
+	// Statements outside tests are errors
+	assert!(crate::spec::parse_spec("bad.t", "a = 1\n").is_err());
+}
+
+#[test]
+fn spec_failures() {
+	let string = String::from(".origin $0600\nset_carry:\nsec\nrts\nhalt:\nbrk\nspin:\njmp spin\n");
+	let mut lexer = Lexer::new("lib.s", &string);
+	let first = first_pass(&mut lexer).unwrap();
+	let image = second_pass(&first).unwrap().image();
+	let run = |spec: &str| {
+		let tests = crate::spec::parse_spec("lib.t", spec).unwrap();
+		crate::spec::run_test(&tests[0], &image, &first.symbol_table, 100).into_iter().map(|v| (v.lino, v.message)).collect::<Vec<_>>()
+	};
+
+	// Flags and != comparisons
+	assert_eq!(run("test flags\n\tc = 0\n\tjsr set_carry\n\texpect c = 1\n\texpect c != 1\n\texpect a != 0\n\texpect sp = $FD\nend\n"), vec![
+		(5, String::from("Expected c != 1, found 1")),
+		(6, String::from("Expected a != $00, found $00")),
+	]);
+
+	// Calls that don't return stop the test
+	assert_eq!(run("test halt\n\tjsr halt\n\texpect a = 1\nend\n"), vec![(2, String::from("Reached brk at $0602"))]);
+	assert_eq!(run("test spin\n\tjsr spin\nend\n"), vec![(2, String::from("Did not return within 100 cycles"))]);
+
+	// Values have to fit and labels have to exist
+	assert_eq!(run("test values\n\ta = $100\nend\n"), vec![(2, String::from("Value $0100 doesn't fit in a byte"))]);
+	assert_eq!(run("test labels\n\tjsr missing\nend\n"), vec![(2, String::from("Undefined label 'missing'"))]);
+
+	// Errors in the spec itself
+	let errors = [
+		("test t\n\texpect mem $10 < 1\nend\n", 2, "Memory can only be compared with '='"),
+		("test t\n\tcycles = 1\nend\n", 2, "Cycles cannot be set"),
+		("test t\n\ta = 1, 2\nend\n", 2, "Only memory can have several values"),
+		("test t\n\tq = 1\nend\n", 2, "Unknown register or flag 'q'"),
+		("test a\ntest b\n", 2, "Tests cannot be nested (missing 'end')"),
+		("end\n", 1, "'end' without 'test'"),
+		("test t\n\tjsr set_carry\n", 3, "Missing 'end'"),
+	];
+	for (spec, lino, message) in errors.iter() {
+		let err = crate::spec::parse_spec("bad.t", spec).unwrap_err();
+		assert_eq!((err.lino, err.message.as_str()), (*lino, *message), "{}", spec);
+	}
+}